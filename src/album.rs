@@ -1,59 +1,281 @@
-use reqwest::Client;
-
 use crate::api;
+use crate::api::Client;
+use crate::artist;
+use crate::cache;
 use crate::config;
 
+// The most albums the Subsonic API will return in one getAlbumList call.
+pub const PAGE_SIZE: u16 = 500;
+
+// The most albums to ask search3 for.
+const SEARCH_ALBUMS: u16 = 500;
+
+/// The different orderings and filters getAlbumList supports, or at least the ones we use.
+pub enum ListType<'a> {
+    AlphabeticalByName,
+    ByGenre(&'a str),
+    Highest,
+    Newest,
+    Random,
+    Starred,
+}
+
+impl ListType<'_> {
+    /// The type parameter and any others that it needs.
+    fn query(&self) -> String {
+        match self {
+            ListType::AlphabeticalByName => "type=alphabeticalByName".to_string(),
+            ListType::ByGenre(genre) => format!("type=byGenre&genre={}", api::encode(genre)),
+            ListType::Highest => "type=highest".to_string(),
+            ListType::Newest => "type=newest".to_string(),
+            ListType::Random => "type=random".to_string(),
+            ListType::Starred => "type=starred".to_string(),
+        }
+    }
+
+    /// What to cache pages of this list under, if they're worth caching at all. Lists that are
+    /// meant to be different every time, or that are all about what's just happened, aren't.
+    fn cache_key(&self) -> Option<String> {
+        match self {
+            ListType::AlphabeticalByName => Some("alphabetical".to_string()),
+            ListType::ByGenre(genre) => Some(format!("genre {}", genre)),
+            ListType::Highest | ListType::Newest | ListType::Random | ListType::Starred => None,
+        }
+    }
+}
+
+/// What an album is, whatever ID a server gives it: its MusicBrainz ID if the server says, and
+/// otherwise its artist and name. This stays the same when a rescan changes the server's IDs.
+pub fn identity(album: &api::Album) -> String {
+    match &album.music_brainz_id {
+        Some(mbid) if !mbid.is_empty() => mbid.clone(),
+        _ => format!(
+            "{}\n{}",
+            album.artist.as_deref().unwrap_or_default().to_lowercase(),
+            album.name.as_deref().unwrap_or(&album.id).to_lowercase()
+        ),
+    }
+}
+
+/// Albums whose name or artist matches `query`, as the server sees it.
+pub async fn search(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    query: &str,
+) -> Result<Vec<api::Album>, api::Error> {
+    let url = format!(
+        "{}/rest/search3?u={}&f=json&v={}&c={}&query={}&artistCount=0&albumCount={}&songCount=0",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        api::encode(query),
+        SEARCH_ALBUMS
+    );
+    let context = |e: api::Error| e.context(format!("searching for \"{}\"", query));
+    let subsonic_response = api::get(client, &url).await.map_err(context)?;
+
+    artist::check_search_response(&subsonic_response)
+        .map_err(api::Error::from)
+        .map_err(context)?;
+
+    // Safe to unwrap() because we already checked it was Some().
+    Ok(subsonic_response
+        .subsonic_response
+        .search_result
+        .unwrap()
+        .album
+        .unwrap_or_default())
+}
+
 pub async fn get(
     client: &Client,
     conf: &config::Config,
     api_ver: &str,
     id: &str,
-) -> Result<(api::TopLevel, String), api::Error> {
+) -> Result<api::TopLevel, api::Error> {
     let url = format!(
-        "{}/rest/getAlbum?u={}&t={}&s={}&f=json&v={}&c=graplsub&id={}",
-        conf.base_url, conf.user, conf.md5_pass_salt, conf.salt, api_ver, id
+        "{}/rest/getAlbum?u={}&f=json&v={}&c={}&id={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        api::encode(id)
     );
 
-    api::get(client, &url).await
+    api::get_cached(client, conf, &url, "album", id).await
 }
 
-pub fn check_get_response(resp: &api::TopLevel, json: &str) -> Result<(), api::RespParseError> {
-    api::check_generic_response(resp, json)?;
+pub fn check_get_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
+    api::check_generic_response(resp)?;
 
     // I think we only need to check that resp.subsonic_response.album is not None as
     // everything else is enforced by the JSON structure.
     if resp.subsonic_response.album.is_none() {
         return Err(api::RespParseError::MissingAlbum {
-            response: json.to_string(),
+            excerpt: resp.excerpt.clone(),
         });
     }
 
     Ok(())
 }
 
-pub async fn random_list(
+/// The full details of an album, including its songs. These come from the cache if they can.
+pub async fn fetch(
     client: &Client,
     conf: &config::Config,
     api_ver: &str,
-) -> Result<(api::TopLevel, String), api::Error> {
-    let url = format!(
-        "{}/rest/getAlbumList?u={}&t={}&s={}&f=json&v={}&c=graplsub&type=random&size={}",
-        conf.base_url, conf.user, conf.md5_pass_salt, conf.salt, api_ver, conf.num_albums
+    id: &str,
+) -> Result<api::Album, api::Error> {
+    let context = |e: api::Error| e.context(format!("album {}", id));
+    let subsonic_response = get(client, conf, api_ver, id).await.map_err(context)?;
+
+    check_get_response(&subsonic_response)
+        .map_err(api::Error::from)
+        .map_err(context)?;
+
+    // Safe to unwrap() because we already checked it was Some().
+    Ok(subsonic_response.subsonic_response.album.unwrap())
+}
+
+/// One page of getAlbumList of the given type (random, newest, etc.), handed to `f` before its
+/// albums are made into Albums, so that those that aren't wanted never are. The Subsonic API won't
+/// return more than 500 at once.
+pub async fn scan_list<T>(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    list_type: &ListType<'_>,
+    size: u16,
+    offset: usize,
+    f: impl FnOnce(Vec<api::ListedAlbum<'_>>) -> T,
+) -> Result<T, api::Error> {
+    let mut url = format!(
+        "{}/rest/getAlbumList?u={}&f=json&v={}&c={}&{}&size={}&offset={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        list_type.query(),
+        size,
+        offset
     );
 
-    api::get(client, &url).await
+    if let Some(id) = &conf.music_folder_id {
+        url.push_str(&format!("&musicFolderId={}", id));
+    }
+
+    api::get_album_list(client, &url, f).await.map_err(|e| {
+        e.context(format!(
+            "albums {} to {}",
+            offset + 1,
+            offset + usize::from(size)
+        ))
+    })
 }
 
-pub fn check_list_response(resp: &api::TopLevel, json: &str) -> Result<(), api::RespParseError> {
-    api::check_generic_response(resp, json)?;
+/// One page of getAlbumList of the given type, every album of it.
+pub async fn fetch_list(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    list_type: &ListType<'_>,
+    size: u16,
+    offset: usize,
+) -> Result<Vec<api::Album>, api::Error> {
+    scan_list(client, conf, api_ver, list_type, size, offset, |page| {
+        page.into_iter().map(api::Album::from).collect()
+    })
+    .await
+}
 
-    // I think we only need to check that resp.subsonic_response.album_list is not None as
-    // everything else is enforced by the JSON structure.
-    if resp.subsonic_response.album_list.is_none() {
-        return Err(api::RespParseError::MissingAlbumList {
-            response: json.to_string(),
-        });
+/// Every album of the given list type, a page at a time.
+pub async fn fetch_all_of(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    list_type: &ListType<'_>,
+) -> Result<Vec<api::Album>, api::Error> {
+    let mut albums = Vec::new();
+    each_of(client, conf, api_ver, list_type, |a| albums.push(a)).await?;
+    Ok(albums)
+}
+
+/// Hand every album in the library (or in the selected music folder) to `f`, a page at a time.
+/// This can be a lot of requests for a big library so is only for modes that really need to see
+/// everything. Only one page is held at once, so albums that aren't wanted needn't be kept at all.
+pub async fn each(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    f: impl FnMut(api::Album),
+) -> Result<(), api::Error> {
+    each_of(client, conf, api_ver, &ListType::AlphabeticalByName, f).await
+}
+
+/// Every album in the library (or the selected music folder) as each() last cached it, however long
+/// ago, without asking the server. Nothing if any of it isn't cached.
+pub fn cached(conf: &config::Config) -> Option<Vec<api::Album>> {
+    let key = format!(
+        "{} {}",
+        ListType::AlphabeticalByName.cache_key()?,
+        conf.music_folder_id.as_deref().unwrap_or_default()
+    );
+    let mut albums: Vec<api::Album> = Vec::new();
+
+    loop {
+        let (page, _): (Vec<api::Album>, bool) =
+            cache::load_any(conf, "albums", &format!("{} {}", key, albums.len()))?;
+        let fetched = page.len();
+        albums.extend(page);
+
+        if fetched < usize::from(PAGE_SIZE) {
+            return Some(albums);
+        }
     }
+}
 
-    Ok(())
+/// Hand every album of the given list type to `f`, a page at a time.
+pub async fn each_of(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    list_type: &ListType<'_>,
+    mut f: impl FnMut(api::Album),
+) -> Result<(), api::Error> {
+    let mut offset = 0;
+
+    // Pages are cached separately for each music folder, as they'll be different.
+    let cache_key = list_type.cache_key().map(|k| {
+        format!(
+            "{} {}",
+            k,
+            conf.music_folder_id.as_deref().unwrap_or_default()
+        )
+    });
+
+    loop {
+        let cached = cache_key
+            .as_ref()
+            .and_then(|k| cache::load(conf, "albums", &format!("{} {}", k, offset)));
+        let page: Vec<api::Album> = match cached {
+            Some(p) => p,
+            None => {
+                let page = fetch_list(client, conf, api_ver, list_type, PAGE_SIZE, offset).await?;
+                if let Some(k) = &cache_key {
+                    cache::store(conf, "albums", &format!("{} {}", k, offset), &page);
+                }
+                page
+            }
+        };
+        let fetched = page.len();
+        offset += fetched;
+
+        page.into_iter().for_each(&mut f);
+
+        if fetched < usize::from(PAGE_SIZE) {
+            return Ok(());
+        }
+    }
 }
@@ -1,48 +1,357 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use format_serde_error::SerdeError;
-use reqwest::Client;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::body::Bytes;
+use hyper_util::rt::TokioIo;
 use reqwest::StatusCode;
-use serde::Deserialize;
-use std::time::Duration;
+use reqwest::header::{
+    AUTHORIZATION, CONTENT_TYPE, ETAG, HOST, HeaderMap, HeaderName, HeaderValue, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, LAST_MODIFIED, USER_AGENT,
+};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tokio::net::UnixStream;
+
+use crate::bugreport;
+use crate::cache;
+use crate::config;
+use crate::config::AuthMode;
+use crate::playlist;
+use crate::quirks::Quirks;
 
 // Infrastructure needed to be a Subsonic API client.
 
+/// The version of the API this is written for. It's what's sent as `v=`, unless the server is
+/// older.
+pub const API_VERSION: &str = "1.14.0";
+
+// What a request sent as a form is.
+const FORM: &str = "application/x-www-form-urlencoded";
+
+/// Whether API version `a` is older than `b`, like "1.13.0" and "1.14.0".
+pub fn older(a: &str, b: &str) -> bool {
+    let parse = |v: &str| {
+        let mut parts: Vec<u32> = v.split('.').map(|n| n.parse().unwrap_or(0)).collect();
+        parts.resize(3, 0);
+        parts
+    };
+
+    parse(a) < parse(b)
+}
+
 // Structures that will be deserialsied from JSON.
 
-/// A playlist. We only need its name and ID as we never check what's actually in it.
-#[derive(Debug, Deserialize)]
+/// A playlist. Its entries are only present when the individual playlist is requested.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Playlist {
     pub id: String,
     pub name: String,
+    pub owner: Option<String>,
+    pub song_count: Option<u32>,
+    // Total length in seconds.
+    pub duration: Option<u32>,
+    pub comment: Option<String>,
+    // Whether other users can see it.
+    pub public: Option<bool>,
+    // When it was made, as an ISO 8601 timestamp.
+    pub created: Option<String>,
+    // When it was last changed, as an ISO 8601 timestamp. Not worth exporting.
+    #[serde(skip_serializing)]
+    pub changed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub entry: Option<Vec<Song>>,
 }
 
 /// For calls that return a list of playlists.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct Playlists {
     // There'll be an empty "playlists {}" block if there's no playlists.
+    #[serde(default, deserialize_with = "one_or_many")]
     pub playlist: Option<Vec<Playlist>>,
 }
 
-/// A song. Right now we only keep track of song IDs.
-#[derive(Debug, Deserialize)]
+/// A song. Only the ID is needed to put it in a playlist; the rest is for reporting on it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Song {
     pub id: String,
+    pub album: Option<String>,
+    #[serde(rename = "albumId")]
+    pub album_id: Option<String>,
+    pub artist: Option<String>,
+    // In kbps. For VBR files it's the average.
+    #[serde(rename = "bitRate")]
+    pub bit_rate: Option<u32>,
+    // Length in seconds.
+    pub duration: Option<u32>,
+    pub genre: Option<String>,
+    // The MusicBrainz recording ID, from OpenSubsonic servers.
+    #[serde(rename = "musicBrainzId")]
+    pub music_brainz_id: Option<String>,
+    // Where the file is, relative to the music folder. Servers can choose not to say.
+    pub path: Option<String>,
+    // The file's extension, like "flac" or "mp3".
+    pub suffix: Option<String>,
+    pub title: Option<String>,
+    // What it is: "music", "podcast", "audiobook" or "video". Servers don't have to say.
+    #[serde(rename = "type")]
+    pub media_type: Option<String>,
+    #[serde(rename = "isPodcast")]
+    pub is_podcast: Option<bool>,
+    // How many times it's been played. Changes too often to be worth exporting.
+    #[serde(rename = "playCount", skip_serializing)]
+    pub play_count: Option<u64>,
+    pub year: Option<u32>,
 }
 
-/// An individual album's details as returned by getAlbum. We only care about the album ID and the
-/// list of songs on it.
-#[derive(Debug, Deserialize)]
+/// An album, as returned by getAlbum or in a list of albums. Field names are the same as the API's
+/// so that it serialises back to the same thing for album hooks.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Album {
     pub id: String,
+    // The MusicBrainz release ID, from OpenSubsonic servers.
+    pub music_brainz_id: Option<String>,
+    pub artist: Option<String>,
+    pub artist_id: Option<String>,
+    // What to ask getCoverArt for, which is often but not always the album's ID.
+    pub cover_art: Option<String>,
+    // When it was added to the library, as an ISO 8601 timestamp.
+    pub created: Option<String>,
+    // Total length in seconds.
+    pub duration: Option<u32>,
+    pub genre: Option<String>,
+    // OpenSubsonic servers can list more than one genre.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub genres: Option<Vec<ItemGenre>>,
+    pub name: Option<String>,
+    // OpenSubsonic servers can say outright if it's a compilation.
+    pub is_compilation: Option<bool>,
+    pub play_count: Option<u64>,
+    // When it was last played, as an ISO 8601 timestamp. Only OpenSubsonic servers say.
+    pub played: Option<String>,
+    // When it was starred, if it has been.
+    pub starred: Option<String>,
+    // OpenSubsonic again, e.g. ["Album", "Compilation"].
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub release_types: Option<Vec<String>>,
+    pub song_count: Option<u32>,
+    // The user's own rating, 1 to 5.
+    pub user_rating: Option<u8>,
+    // Release year.
+    pub year: Option<u32>,
     // This one will only be present when the individual album is requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, deserialize_with = "one_or_many")]
     pub song: Option<Vec<Song>>,
 }
 
-/// This is a list of albums as returned by albumList.
+/// That there was a list of albums, as returned by getAlbumList. What's in it is read by
+/// get_album_list(), as ListedAlbums.
+#[derive(Debug, Default, Deserialize)]
+pub struct AlbumList {}
+
+/// An album in a page of getAlbumList, with its strings borrowed from the response rather than
+/// copied out of it. A page can be thousands of albums of which only a few are wanted, like those
+/// added since a cutoff or not already drawn, so the rest needn't be made into Albums at all. It
+/// has the same fields as Album, bar the songs, which lists never have.
 #[derive(Debug, Deserialize)]
-pub struct AlbumList {
+#[serde(rename_all = "camelCase")]
+pub struct ListedAlbum<'a> {
+    #[serde(borrow)]
+    pub id: Cow<'a, str>,
+    #[serde(borrow, default, deserialize_with = "borrowed")]
+    pub music_brainz_id: Option<Cow<'a, str>>,
+    #[serde(borrow, default, deserialize_with = "borrowed")]
+    pub artist: Option<Cow<'a, str>>,
+    #[serde(borrow, default, deserialize_with = "borrowed")]
+    pub artist_id: Option<Cow<'a, str>>,
+    #[serde(borrow, default, deserialize_with = "borrowed")]
+    pub cover_art: Option<Cow<'a, str>>,
+    #[serde(borrow, default, deserialize_with = "borrowed")]
+    pub created: Option<Cow<'a, str>>,
+    pub duration: Option<u32>,
+    #[serde(borrow, default, deserialize_with = "borrowed")]
+    pub genre: Option<Cow<'a, str>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub genres: Option<Vec<ItemGenre>>,
+    #[serde(borrow, default, deserialize_with = "borrowed")]
+    pub name: Option<Cow<'a, str>>,
+    pub is_compilation: Option<bool>,
+    pub play_count: Option<u64>,
+    #[serde(borrow, default, deserialize_with = "borrowed")]
+    pub played: Option<Cow<'a, str>>,
+    #[serde(borrow, default, deserialize_with = "borrowed")]
+    pub starred: Option<Cow<'a, str>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub release_types: Option<Vec<String>>,
+    pub song_count: Option<u32>,
+    pub user_rating: Option<u8>,
+    pub year: Option<u32>,
+}
+
+impl From<ListedAlbum<'_>> for Album {
+    fn from(a: ListedAlbum<'_>) -> Album {
+        let owned = |s: Option<Cow<str>>| s.map(Cow::into_owned);
+
+        Album {
+            id: a.id.into_owned(),
+            music_brainz_id: owned(a.music_brainz_id),
+            artist: owned(a.artist),
+            artist_id: owned(a.artist_id),
+            cover_art: owned(a.cover_art),
+            created: owned(a.created),
+            duration: a.duration,
+            genre: owned(a.genre),
+            genres: a.genres,
+            name: owned(a.name),
+            is_compilation: a.is_compilation,
+            play_count: a.play_count,
+            played: owned(a.played),
+            starred: owned(a.starred),
+            release_types: a.release_types,
+            song_count: a.song_count,
+            user_rating: a.user_rating,
+            year: a.year,
+            song: None,
+        }
+    }
+}
+
+/// A getAlbumList response, as far as it has to be understood to get at the albums.
+#[derive(Deserialize)]
+struct ListResponse<'a> {
+    #[serde(rename = "subsonic-response", borrow)]
+    subsonic_response: ListBody<'a>,
+}
+
+#[derive(Deserialize)]
+struct ListBody<'a> {
+    #[serde(borrow)]
+    status: Cow<'a, str>,
+    #[serde(rename = "albumList", borrow)]
+    album_list: Option<ListedAlbums<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListedAlbums<'a> {
     // There'll be an empty "album {}" block if there's no albums.
+    #[serde(borrow, default, deserialize_with = "one_or_many")]
+    album: Option<Vec<ListedAlbum<'a>>>,
+}
+
+/// One of the OpenSubsonic list of genres on an album.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ItemGenre {
+    pub name: String,
+}
+
+/// A podcast channel. We only want its title.
+#[derive(Debug, Deserialize)]
+pub struct PodcastChannel {
+    pub title: Option<String>,
+}
+
+/// As returned by getPodcasts.
+#[derive(Debug, Default, Deserialize)]
+pub struct Podcasts {
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub channel: Option<Vec<PodcastChannel>>,
+}
+
+/// An artist, as found by search3 or getArtist. Only getArtist includes their albums.
+#[derive(Debug, Deserialize)]
+pub struct Artist {
+    pub id: String,
+    pub name: String,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub album: Option<Vec<Album>>,
+}
+
+/// As returned by getArtistInfo2. We only care about the similar artists, and of those only the
+/// ones that are actually in the library will have IDs.
+#[derive(Debug, Default, Deserialize)]
+pub struct ArtistInfo {
+    #[serde(rename(deserialize = "similarArtist"))]
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub similar_artist: Option<Vec<Artist>>,
+}
+
+/// As returned by search3.
+#[derive(Debug, Default, Deserialize)]
+pub struct SearchResult {
+    #[serde(default, deserialize_with = "one_or_many")]
     pub album: Option<Vec<Album>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub artist: Option<Vec<Artist>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub song: Option<Vec<Song>>,
+}
+
+/// As returned by getSimilarSongs2.
+#[derive(Debug, Default, Deserialize)]
+pub struct SimilarSongs {
+    // Empty if the server doesn't know of any.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub song: Option<Vec<Song>>,
+}
+
+/// As returned by getStarred2. Starred albums come with it too, but only the artists and songs are
+/// wanted.
+#[derive(Debug, Default, Deserialize)]
+pub struct Starred {
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub artist: Option<Vec<Artist>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub song: Option<Vec<Song>>,
+}
+
+/// As returned by getTopSongs.
+#[derive(Debug, Default, Deserialize)]
+pub struct TopSongs {
+    // Empty if the server doesn't know what an artist's top songs are.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub song: Option<Vec<Song>>,
+}
+
+/// An OpenSubsonic extension the server supports, like "formPost".
+#[derive(Debug, Deserialize)]
+pub struct Extension {
+    pub name: String,
+}
+
+/// A music folder, i.e. a library. Servers disagree about whether the ID is a number or a string
+/// so it's always turned into a string.
+#[derive(Debug, Deserialize)]
+pub struct MusicFolder {
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    pub name: Option<String>,
+}
+
+/// As returned by getMusicFolders.
+#[derive(Debug, Default, Deserialize)]
+pub struct MusicFolders {
+    #[serde(rename(deserialize = "musicFolder"))]
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub music_folder: Option<Vec<MusicFolder>>,
+}
+
+/// As returned by getScanStatus and startScan.
+#[derive(Debug, Deserialize)]
+pub struct ScanStatus {
+    pub scanning: bool,
+    // How many files have been scanned so far.
+    pub count: Option<u64>,
 }
 
 /// The main response structure. Usually there'll only be one of these members present, depending
@@ -51,14 +360,134 @@ pub struct AlbumList {
 pub struct SubsonicResponse {
     // This one can only come back after requesting an album.
     pub album: Option<Album>,
+    // From getArtist.
+    pub artist: Option<Artist>,
+    // From getArtistInfo2.
+    #[serde(rename(deserialize = "artistInfo2"))]
+    pub artist_info: Option<ArtistInfo>,
     // This won't be here if it wasn't a getAlbumList query.
     #[serde(rename(deserialize = "albumList"))]
     pub album_list: Option<AlbumList>,
-    // Again, this one can only come back after creating a playlist.
+    // Again, this one can only come back after creating a playlist or from getPlaylist.
     pub playlist: Option<Playlist>,
+    // Only from getPodcasts.
+    pub podcasts: Option<Podcasts>,
     // This won't be here if it wasn't a getPlaylists query.
     pub playlists: Option<Playlists>,
+    // Only from getMusicFolders.
+    #[serde(rename(deserialize = "musicFolders"))]
+    pub music_folders: Option<MusicFolders>,
+    // Only from search3.
+    #[serde(rename(deserialize = "searchResult3"))]
+    pub search_result: Option<SearchResult>,
+    // Only from getSimilarSongs2.
+    #[serde(rename(deserialize = "similarSongs2"))]
+    pub similar_songs: Option<SimilarSongs>,
+    // From getScanStatus or startScan.
+    #[serde(rename(deserialize = "scanStatus"))]
+    pub scan_status: Option<ScanStatus>,
+    // Only from getStarred2.
+    #[serde(rename(deserialize = "starred2"))]
+    pub starred: Option<Starred>,
+    // Only from getTopSongs.
+    #[serde(rename(deserialize = "topSongs"))]
+    pub top_songs: Option<TopSongs>,
     status: String,
+    // Only when the status isn't "ok".
+    pub error: Option<ApiError>,
+    // The API version the server speaks, which comes with every response.
+    pub version: Option<String>,
+    // Only from getOpenSubsonicExtensions.
+    #[serde(default, rename(deserialize = "openSubsonicExtensions"))]
+    pub extensions: Option<Vec<Extension>>,
+    // Whether the server does OpenSubsonic, which comes with every response from one.
+    #[serde(rename(deserialize = "openSubsonic"))]
+    pub open_subsonic: Option<bool>,
+    // OpenSubsonic servers also say what they are, like "navidrome" and "0.53.3 (13af8ed4)".
+    #[serde(rename(deserialize = "type"))]
+    pub server_type: Option<String>,
+    #[serde(rename(deserialize = "serverVersion"))]
+    pub server_version: Option<String>,
+}
+
+/// Accept either a JSON string or a JSON number, for IDs.
+fn string_or_number<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Number(serde_json::Number),
+    }
+
+    Ok(match StringOrNumber::deserialize(deserializer)? {
+        StringOrNumber::String(s) => s,
+        StringOrNumber::Number(n) => n.to_string(),
+    })
+}
+
+/// Accept a single item where there should be a list of them. Servers whose JSON is made from XML
+/// can't tell a list of one from one on its own, so send just the one. This can't hurt with any
+/// other server so isn't left to the quirks.
+///
+/// It's done with a visitor rather than an untagged enum so that a big list isn't buffered while
+/// trying one thing and then the other, and so that an error in one item says what was wrong with
+/// it rather than that nothing matched.
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    struct OneOrMany<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> Visitor<'de> for OneOrMany<T> {
+        type Value = Option<Vec<T>>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a list, or a single item")
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_some<D2: Deserializer<'de>>(self, d: D2) -> Result<Self::Value, D2::Error> {
+            d.deserialize_any(self)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+            Vec::deserialize(de::value::SeqAccessDeserializer::new(seq)).map(Some)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+            T::deserialize(de::value::MapAccessDeserializer::new(map)).map(|t| Some(vec![t]))
+        }
+
+        // Lists of strings, like releaseTypes, can be one string on its own too.
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            T::deserialize(de::value::StrDeserializer::new(v)).map(|t| Some(vec![t]))
+        }
+    }
+
+    deserializer.deserialize_any(OneOrMany(PhantomData))
+}
+
+/// An optional string, borrowed from the response if it can be. Without this, serde only borrows a
+/// Cow that isn't in an Option.
+fn borrowed<'de: 'a, 'a, D>(deserializer: D) -> Result<Option<Cow<'a, str>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Borrowed<'a>(#[serde(borrow)] Cow<'a, str>);
+
+    Ok(Option::<Borrowed>::deserialize(deserializer)?.map(|b| b.0))
 }
 
 /// Outer wrapper returned in every API response.
@@ -66,6 +495,16 @@ pub struct SubsonicResponse {
 pub struct TopLevel {
     #[serde(rename(deserialize = "subsonic-response"))]
     pub subsonic_response: SubsonicResponse,
+    // The start of the response as it came, for errors.
+    #[serde(skip)]
+    pub excerpt: String,
+}
+
+/// What a server says went wrong when the status isn't "ok".
+#[derive(Debug, Deserialize)]
+pub struct ApiError {
+    pub code: u32,
+    pub message: Option<String>,
 }
 
 // Error handling.
@@ -76,6 +515,12 @@ pub enum Error {
     #[error("Network error: {0}")]
     Network(#[from] reqwest::Error),
 
+    #[error("Invalid extra header: {header}")]
+    InvalidHeader { header: String },
+
+    #[error("Response was bigger than the limit of {limit} bytes")]
+    ResponseTooLarge { limit: usize },
+
     #[error("Resource not found: {resource}")]
     NotFound { resource: String },
 
@@ -84,30 +529,551 @@ pub enum Error {
 
     #[error(transparent)]
     SerdeError(#[from] format_serde_error::SerdeError),
+
+    #[error("Unix socket error for {socket}: {reason}")]
+    UnixSocket { socket: String, reason: String },
+
+    #[error("Unix socket error for {socket}: HTTP status {status}")]
+    UnixSocketStatus { socket: String, status: u16 },
+
+    #[error("Server said \"not modified\" to a request that wasn't conditional")]
+    UnexpectedNotModified,
+
+    // Any of the above, saying what we were doing at the time.
+    #[error("{context}: {source}")]
+    Context { context: String, source: Box<Error> },
 }
 
-/// Errors related to parsing API responses. <ost of these never get triggered because the response
-/// won't deserialise if it's incorrect.
+impl Error {
+    /// Say what was going on when this happened, like which endpoint or album it was. Context
+    /// reads outermost first, so "album 73 of 100: getAlbum: Network error: …".
+    pub fn context(self, context: impl Into<String>) -> Error {
+        Error::Context {
+            context: context.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /// The error itself, without any context around it.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.root(),
+            e => e,
+        }
+    }
+
+    /// The HTTP status the server answered with, if that's what went wrong.
+    pub fn status(&self) -> Option<u16> {
+        match self.root() {
+            Error::Network(e) => e.status().map(|s| s.as_u16()),
+            Error::UnixSocketStatus { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+/// Errors related to parsing API responses. Most of these never get triggered because the response
+/// won't deserialise if it's incorrect. They carry only the start of the response, so that a big
+/// response isn't kept around just in case.
 #[derive(Debug, Error)]
 pub enum RespParseError {
-    #[error("Subsonic response was missing an album: {response}")]
-    MissingAlbum { response: String },
+    #[error("Subsonic response was missing an album: {excerpt}")]
+    MissingAlbum { excerpt: String },
+
+    #[error("Subsonic response was missing an albumList: {excerpt}")]
+    MissingAlbumList { excerpt: String },
+
+    #[error("Subsonic response was missing an artist: {excerpt}")]
+    MissingArtist { excerpt: String },
+
+    #[error("Subsonic response was missing an artistInfo2: {excerpt}")]
+    MissingArtistInfo { excerpt: String },
+
+    #[error("Subsonic response was missing a musicFolders: {excerpt}")]
+    MissingMusicFolders { excerpt: String },
+
+    #[error("Subsonic response was missing a playlist: {excerpt}")]
+    MissingPlaylist { excerpt: String },
+
+    #[error("Subsonic response was missing a playlists: {excerpt}")]
+    MissingPlaylists { excerpt: String },
+
+    #[error("Subsonic response was missing a scanStatus: {excerpt}")]
+    MissingScanStatus { excerpt: String },
+
+    #[error("Subsonic response was missing a podcasts: {excerpt}")]
+    MissingPodcasts { excerpt: String },
+
+    #[error("Subsonic response was missing a searchResult3: {excerpt}")]
+    MissingSearchResult { excerpt: String },
+
+    #[error("Subsonic response was missing a similarSongs2: {excerpt}")]
+    MissingSimilarSongs { excerpt: String },
+
+    #[error("Subsonic response was missing a starred2: {excerpt}")]
+    MissingStarred { excerpt: String },
+
+    #[error("Subsonic response was missing a topSongs: {excerpt}")]
+    MissingTopSongs { excerpt: String },
+
+    #[error("Subsonic server said no: {message} (error {code})")]
+    ResponseNotOk { code: u32, message: String },
+}
+
+/// Parse the user's extra headers, one "Name: value" pair per line. Values are marked sensitive as
+/// these are usually there to carry reverse proxy credentials.
+fn extra_headers(headers: &str) -> Result<HeaderMap, Error> {
+    let mut map = HeaderMap::new();
+
+    for line in headers.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        // Only ever report the name, as the value is likely a secret.
+        let (name, value) = line.split_once(':').ok_or_else(|| Error::InvalidHeader {
+            header: line
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        })?;
+        let name = name.trim();
+        let invalid = || Error::InvalidHeader {
+            header: name.to_string(),
+        };
+
+        let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?;
+        let mut header_value = HeaderValue::from_str(value.trim()).map_err(|_| invalid())?;
+        header_value.set_sensitive(true);
+
+        map.append(header_name, header_value);
+    }
+
+    Ok(map)
+}
+
+/// An HTTP Basic Authorization header for servers behind a basic-auth protected proxy. This is sent
+/// as well as the usual Subsonic token auth, not instead of it.
+fn basic_auth_header(user: &str, pass: &str) -> Result<HeaderValue, Error> {
+    let encoded = BASE64.encode(format!("{}:{}", user, pass));
+    let mut value =
+        HeaderValue::from_str(&format!("Basic {}", encoded)).map_err(|_| Error::InvalidHeader {
+            header: AUTHORIZATION.to_string(),
+        })?;
+    value.set_sensitive(true);
+
+    Ok(value)
+}
+
+/// A client for the Subsonic API.
+pub struct Client {
+    transport: Transport,
+    // Responses bigger than this are abandoned rather than read into memory.
+    max_response_bytes: usize,
+    throttle: Throttle,
+    auth: AuthStrategy,
+    quirks: Quirks,
+    // What the server said it was, like "navidrome 0.53.3", once it's been asked.
+    server: Option<String>,
+    // How many songs to add to a playlist in one request. Cut down if the server turns out not to
+    // take URLs that long.
+    batch_size: AtomicUsize,
+    // Whether the server takes requests as POSTed forms, and how long a URL can get before one is
+    // sent that way instead.
+    form_post: bool,
+    max_url_length: usize,
+}
+
+/// How each request proves who it's from. The URLs are built without any credentials and these
+/// are added as each request is sent, so a new one only needs handling here.
+pub enum AuthStrategy {
+    // A token made from the password and a salt, both new for every request so that no two
+    // requests look the same.
+    TokenMd5 { pass: String, salt_bytes: usize },
+    // For servers too old for token authentication: "enc:" and the password in hex.
+    PlainPassword(String),
+    // An OpenSubsonic API key, which stands in for the user name as well as the password.
+    ApiKey(String),
+    // Nothing in the URL at all. A reverse proxy in front of the server has checked who we are
+    // and says so in a header, which create_client() adds to every request.
+    ReverseProxyHeader,
+}
+
+impl AuthStrategy {
+    /// The strategy GRAPLSUB_AUTH_MODE asks for. Auto starts off with a token, or the API key if
+    /// there is one; find_server() might change its mind once it knows how old the server is.
+    pub fn new(conf: &config::Config) -> AuthStrategy {
+        let token = || AuthStrategy::TokenMd5 {
+            pass: conf.pass.clone(),
+            salt_bytes: conf.salt_bytes,
+        };
+        let api_key = || AuthStrategy::ApiKey(conf.api_key.clone().unwrap_or_default());
+
+        match conf.auth_mode {
+            AuthMode::Auto if conf.api_key.is_some() => api_key(),
+            AuthMode::Auto | AuthMode::Token => token(),
+            AuthMode::Password => AuthStrategy::password(&conf.pass),
+            AuthMode::ApiKey => api_key(),
+            AuthMode::Header => AuthStrategy::ReverseProxyHeader,
+        }
+    }
+
+    fn password(pass: &str) -> AuthStrategy {
+        AuthStrategy::PlainPassword(format!("enc:{}", hex::encode(pass)))
+    }
+
+    /// The URL with credentials added. Made afresh for each request, including each retry, rather
+    /// than once for the whole run.
+    fn apply(&self, url: &str) -> String {
+        match self {
+            AuthStrategy::TokenMd5 { pass, salt_bytes } => {
+                let (salt, token) = config::salted_token(pass, *salt_bytes);
+                format!("{}&t={}&s={}", url, token, salt)
+            }
+            AuthStrategy::PlainPassword(p) => format!("{}&p={}", url, p),
+            AuthStrategy::ApiKey(key) => {
+                // The server has to refuse an API key that comes with a user name.
+                let Some((path, query)) = url.split_once('?') else {
+                    return url.to_string();
+                };
+                let params: Vec<&str> = query.split('&').filter(|p| !p.starts_with("u=")).collect();
+
+                format!("{}?{}&apiKey={}", path, params.join("&"), encode(key))
+            }
+            AuthStrategy::ReverseProxyHeader => url.to_string(),
+        }
+    }
+}
+
+impl Client {
+    /// Work around the known quirks of the server, once we know what it is.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Remember what the server said it was, for bug reports.
+    pub fn set_server(&mut self, server: String) {
+        self.server = Some(server);
+    }
+
+    /// What the server said it was, if it's been asked.
+    pub fn server(&self) -> Option<&str> {
+        self.server.as_deref()
+    }
+
+    /// Send requests with long URLs as POSTed forms, for servers with OpenSubsonic's formPost
+    /// extension.
+    pub fn set_form_post(&mut self, form_post: bool) {
+        self.form_post = form_post;
+    }
+
+    /// How many songs to add to a playlist in one request.
+    pub fn batch_size(&self) -> usize {
+        self.batch_size.load(Ordering::Relaxed)
+    }
+
+    /// Add fewer songs in each request from now on.
+    pub fn set_batch_size(&self, size: usize) {
+        self.batch_size.store(size.max(1), Ordering::Relaxed);
+    }
+
+    /// Send the password with every request instead of a token, for servers older than API 1.13.0.
+    pub fn use_password(&mut self, pass: &str) {
+        self.auth = AuthStrategy::password(pass);
+    }
+}
+
+/// Limits how fast requests are made so that a run of hundreds of them doesn't swamp a small server.
+/// It's a token bucket: a request takes a token, tokens come back at a steady rate, and up to
+/// `burst` can be saved up to go at once. Each request reserves its token before waiting for it,
+/// so requests made at the same time queue up fairly.
+struct Throttle {
+    // Tokens per second. None for no limit.
+    rate: Option<f64>,
+    burst: f64,
+    // Up to this much more is added to each wait at random.
+    jitter: Duration,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    // Negative when requests are queued waiting for tokens.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Throttle {
+    fn new(conf: &config::Config) -> Throttle {
+        // A plain delay between requests is the same as a bucket that holds one token.
+        let (rate, burst) = match conf.requests_per_second {
+            Some(r) if r > 0.0 => (Some(r), f64::from(conf.request_burst.max(1))),
+            _ if conf.request_delay_ms > 0 => (Some(1000.0 / conf.request_delay_ms as f64), 1.0),
+            _ => (None, 1.0),
+        };
+
+        Throttle {
+            rate,
+            burst,
+            jitter: Duration::from_millis(conf.request_jitter_ms),
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Wait for our turn to make a request.
+    async fn wait(&self) {
+        let mut wait = Duration::ZERO;
+
+        if let Some(rate) = self.rate {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.updated).as_secs_f64() * rate;
+            bucket.tokens = (bucket.tokens + refill).min(self.burst) - 1.0;
+            bucket.updated = now;
+
+            if bucket.tokens < 0.0 {
+                wait = Duration::from_secs_f64(-bucket.tokens / rate);
+            }
+        }
+
+        if !self.jitter.is_zero() {
+            wait += Duration::from_millis(rand::random_range(0..=self.jitter.as_millis() as u64));
+        }
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// What a server gives to tell later whether a response has changed. Either can be missing, and
+/// plenty of servers send neither.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(headers: &HeaderMap) -> Validators {
+        let get = |name: HeaderName| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+
+        Validators {
+            etag: get(ETAG),
+            last_modified: get(LAST_MODIFIED),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// The headers to make a request conditional on these.
+    fn headers(&self) -> Vec<(HeaderName, &str)> {
+        let mut headers = Vec::new();
+        if let Some(e) = &self.etag {
+            headers.push((IF_NONE_MATCH, e.as_str()));
+        }
+        if let Some(l) = &self.last_modified {
+            headers.push((IF_MODIFIED_SINCE, l.as_str()));
+        }
+        headers
+    }
+}
 
-    #[error("Subsonic response was missing an albumList: {response}")]
-    MissingAlbumList { response: String },
+/// What a GET came back with.
+enum Fetched {
+    Body {
+        body: Vec<u8>,
+        validators: Validators,
+    },
+    // The answer to a conditional request when what we have is still current.
+    NotModified,
+}
+
+/// A response kept in the cache, as it came, so that it can be used again if the server says it
+/// hasn't changed.
+#[derive(Deserialize, Serialize)]
+struct CachedResponse {
+    validators: Validators,
+    body: String,
+}
+
+/// How requests get to the Subsonic server.
+enum Transport {
+    /// Plain old HTTP(S), with reqwest doing all the work.
+    Http(reqwest::Client),
+    /// HTTP/1.1 over a Unix domain socket, for a base URL like
+    /// `unix:///run/navidrome/navidrome.sock`.
+    Unix(UnixClient),
+}
+
+/// reqwest can't talk over Unix domain sockets so this does a minimal HTTP/1.1 exchange with hyper
+/// instead. There's no connection reuse, but it's a local socket so that hardly matters.
+struct UnixClient {
+    headers: HeaderMap,
+    user_agent: HeaderValue,
+}
+
+impl UnixClient {
+    async fn get(
+        &self,
+        url: &str,
+        post: bool,
+        max_bytes: usize,
+        validators: &Validators,
+    ) -> Result<Fetched, Error> {
+        // Everything between the scheme and "/rest/" is the path to the socket; the rest is the
+        // request path and query string.
+        let (socket, path) = url
+            .strip_prefix("unix://")
+            .and_then(|u| u.find("/rest/").map(|i| u.split_at(i)))
+            .ok_or_else(|| Error::UnixSocket {
+                socket: url.split('?').next().unwrap_or_default().to_string(),
+                reason: "can't find the socket path in the URL".to_string(),
+            })?;
+        let fail = |reason: String| Error::UnixSocket {
+            socket: socket.to_string(),
+            reason,
+        };
+        let (path, form) = match path.split_once('?').filter(|_| post) {
+            Some((path, form)) => (path, Some(form)),
+            None => (path, None),
+        };
+
+        let request = async {
+            let stream = UnixStream::connect(socket)
+                .await
+                .map_err(|e| fail(e.to_string()))?;
+            let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+                .await
+                .map_err(|e| fail(e.to_string()))?;
+            tokio::spawn(conn);
+
+            let mut builder = match form {
+                Some(_) => hyper::Request::post(path).header(CONTENT_TYPE, FORM),
+                None => hyper::Request::get(path),
+            }
+            .header(HOST, "localhost")
+            .header(USER_AGENT, self.user_agent.clone());
+            for (name, value) in &self.headers {
+                builder = builder.header(name, value);
+            }
+            for (name, value) in validators.headers() {
+                builder = builder.header(name, value);
+            }
+            let req = builder
+                .body(Full::new(Bytes::from(form.unwrap_or_default().to_string())))
+                .map_err(|e| fail(e.to_string()))?;
 
-    #[error("Subsonic response was missing a playlist: {response}")]
-    MissingPlaylist { response: String },
+            let response = sender
+                .send_request(req)
+                .await
+                .map_err(|e| fail(e.to_string()))?;
+            let status = response.status();
+            let validators = Validators::from_headers(response.headers());
+            let body = Limited::new(response.into_body(), max_bytes)
+                .collect()
+                .await
+                .map_err(|e| {
+                    if e.is::<LengthLimitError>() {
+                        Error::ResponseTooLarge { limit: max_bytes }
+                    } else {
+                        fail(e.to_string())
+                    }
+                })?
+                .to_bytes();
 
-    #[error("Subsonic response was missing a playlists: {response}")]
-    MissingPlaylists { response: String },
+            Ok::<_, Error>((status, validators, body))
+        };
 
-    #[error("Subsonic response did not have 'ok' status: {response}")]
-    ResponseNotOk { response: String },
+        let (status, validators, body) = tokio::time::timeout(Duration::from_secs(5), request)
+            .await
+            .map_err(|_| fail("request timed out".to_string()))??;
+
+        match status {
+            StatusCode::OK => Ok(Fetched::Body {
+                body: body.into(),
+                validators,
+            }),
+            StatusCode::NOT_MODIFIED => Ok(Fetched::NotModified),
+            StatusCode::NOT_FOUND => {
+                // As with HTTP, leave off the query string because it has the auth info in it.
+                Err(Error::NotFound {
+                    resource: format!(
+                        "unix://{}{}",
+                        socket,
+                        path.split('?').next().unwrap_or_default()
+                    ),
+                })
+            }
+            _ => Err(Error::UnixSocketStatus {
+                socket: socket.to_string(),
+                status: status.as_u16(),
+            }),
+        }
+    }
 }
 
-pub fn create_client() -> Result<Client, reqwest::Error> {
-    Client::builder()
+pub fn create_client(conf: &config::Config) -> Result<Client, Error> {
+    let mut headers = match &conf.headers {
+        Some(h) => extra_headers(h)?,
+        None => HeaderMap::new(),
+    };
+
+    if let Some(user) = &conf.proxy_user {
+        let pass = conf.proxy_pass.as_deref().unwrap_or_default();
+        headers.insert(AUTHORIZATION, basic_auth_header(user, pass)?);
+    }
+
+    if conf.auth_mode == AuthMode::Header {
+        let invalid = || Error::InvalidHeader {
+            header: conf.auth_header.clone(),
+        };
+        headers.insert(
+            HeaderName::from_bytes(conf.auth_header.as_bytes()).map_err(|_| invalid())?,
+            HeaderValue::from_str(&conf.user).map_err(|_| invalid())?,
+        );
+    }
+
+    if conf.base_url.starts_with("unix://") {
+        return Ok(Client {
+            transport: Transport::Unix(UnixClient {
+                headers,
+                user_agent: HeaderValue::from_str(&conf.user_agent).map_err(|_| {
+                    Error::InvalidHeader {
+                        header: USER_AGENT.to_string(),
+                    }
+                })?,
+            }),
+            max_response_bytes: conf.max_response_bytes,
+            throttle: Throttle::new(conf),
+            auth: AuthStrategy::new(conf),
+            quirks: Quirks::default(),
+            server: None,
+            batch_size: AtomicUsize::new(playlist::BATCH_SIZE),
+            form_post: false,
+            max_url_length: conf.max_url_length,
+        });
+    }
+
+    let mut builder = reqwest::Client::builder();
+
+    if !headers.is_empty() {
+        builder = builder.default_headers(headers);
+    }
+
+    // reqwest already honours HTTP_PROXY, HTTPS_PROXY, ALL_PROXY and NO_PROXY from the
+    // environment. An explicit GRAPLSUB_PROXY overrides those for every request.
+    if let Some(proxy) = &conf.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+
+    let http = builder
         // Total request timeout
         .timeout(Duration::from_secs(30))
         // Connection timeout
@@ -116,28 +1082,261 @@ pub fn create_client() -> Result<Client, reqwest::Error> {
         .pool_idle_timeout(Duration::from_secs(90))
         // Max idle connections
         .pool_max_idle_per_host(10)
-        .user_agent("graplsub/0.1.0")
-        .build()
+        // Ask for compressed responses. Album lists are mostly repetitive JSON so shrink a lot,
+        // which matters over a slow link. The size limit applies to what they decompress to.
+        .gzip(true)
+        .brotli(true)
+        .deflate(true)
+        .user_agent(&conf.user_agent)
+        .build()?;
+
+    Ok(Client {
+        transport: Transport::Http(http),
+        max_response_bytes: conf.max_response_bytes,
+        throttle: Throttle::new(conf),
+        auth: AuthStrategy::new(conf),
+        quirks: Quirks::default(),
+        server: None,
+        batch_size: AtomicUsize::new(playlist::BATCH_SIZE),
+        form_post: false,
+        max_url_length: conf.max_url_length,
+    })
 }
 
 /// An HTTP GET request to the API.
-pub async fn get(client: &Client, url: &str) -> Result<(TopLevel, String), Error> {
-    let response = client
-        .get(url)
-        .timeout(Duration::from_secs(5))
-        .send()
-        .await?;
+pub async fn get(client: &Client, url: &str) -> Result<TopLevel, Error> {
+    match fetch(client, url, &Validators::default()).await? {
+        Fetched::Body { body, .. } => parse(client, url, &body),
+        Fetched::NotModified => Err(Error::UnexpectedNotModified.context(endpoint(url))),
+    }
+}
+
+/// An HTTP GET request to getAlbumList, with `f` handed the albums in the page while they're still
+/// borrowed from the response. Whatever `f` makes of them is what comes back.
+pub async fn get_album_list<T>(
+    client: &Client,
+    url: &str,
+    f: impl FnOnce(Vec<ListedAlbum<'_>>) -> T,
+) -> Result<T, Error> {
+    let body = match fetch(client, url, &Validators::default()).await? {
+        Fetched::Body { body, .. } => body,
+        Fetched::NotModified => return Err(Error::UnexpectedNotModified.context(endpoint(url))),
+    };
+
+    let page: ListResponse =
+        serde_json::from_slice(&body).map_err(|err| unparsed(url, &body, err))?;
+    if let ListBody {
+        status,
+        album_list: Some(list),
+    } = page.subsonic_response
+        && status == "ok"
+    {
+        return Ok(f(list.album.unwrap_or_default()));
+    }
+
+    // Anything else, like an error, is made sense of as any other response would be.
+    let obj = parse(client, url, &body)?;
+    check_generic_response(&obj)?;
+    match obj.subsonic_response.album_list {
+        // Only an empty list, left out by the server and put back for its quirks, gets this far,
+        // as one with albums in would have been read above.
+        Some(_) => Ok(f(Vec::new())),
+        None => Err(RespParseError::MissingAlbumList {
+            excerpt: obj.excerpt,
+        }
+        .into()),
+    }
+}
+
+/// An HTTP GET request to the API whose response is cached as `kind` and `key`. A fresh cached
+/// response is used without asking the server at all. A stale one is asked about conditionally if
+/// the server gave an ETag or Last-Modified with it, so that if it hasn't changed it costs a 304
+/// instead of the whole thing again.
+pub async fn get_cached(
+    client: &Client,
+    conf: &config::Config,
+    url: &str,
+    kind: &str,
+    key: &str,
+) -> Result<TopLevel, Error> {
+    let cached: Option<(CachedResponse, bool)> = cache::load_any(conf, kind, key);
+
+    let cached = match cached {
+        Some((c, true)) => return parse(client, url, c.body.as_bytes()),
+        Some((c, false)) if !c.validators.is_empty() => Some(c),
+        _ => None,
+    };
+    let none = Validators::default();
+    let validators = cached.as_ref().map_or(&none, |c| &c.validators);
+
+    let fetched = fetch(client, url, validators).await?;
+
+    let response = match fetched {
+        Fetched::Body { body, validators } => CachedResponse {
+            validators,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        },
+        Fetched::NotModified => {
+            cached.ok_or_else(|| Error::UnexpectedNotModified.context(endpoint(url)))?
+        }
+    };
+
+    let obj = parse(client, url, response.body.as_bytes())?;
+    // Errors aren't worth keeping; they might be gone next time.
+    if obj.subsonic_response.status == "ok" {
+        cache::store(conf, kind, key, &response);
+    }
+    Ok(obj)
+}
+
+/// An HTTP GET request for something that isn't JSON, like cover art. If anything's wrong then the
+/// API still answers in JSON, which is turned into an error.
+pub async fn get_binary(client: &Client, url: &str) -> Result<Vec<u8>, Error> {
+    match fetch(client, url, &Validators::default()).await? {
+        Fetched::Body { body, .. } => {
+            if body.first() == Some(&b'{') {
+                let obj = parse(client, url, &body)?;
+                check_generic_response(&obj).map_err(|e| Error::from(e).context(endpoint(url)))?;
+            }
+            Ok(body)
+        }
+        Fetched::NotModified => Err(Error::UnexpectedNotModified.context(endpoint(url))),
+    }
+}
+
+/// Wait for our turn, then make the request.
+async fn fetch(client: &Client, url: &str, validators: &Validators) -> Result<Fetched, Error> {
+    client.throttle.wait().await;
+    bugreport::request(url);
+
+    let url = client.auth.apply(url);
+    let url = url.as_str();
+
+    // Too long a URL can be turned down by the server, or a proxy in front of it, so if it'll take
+    // the same thing as a form then send it that way.
+    let post = client.form_post && url.len() > client.max_url_length;
+
+    let fetched = match &client.transport {
+        Transport::Http(c) => http_get(c, url, post, client.max_response_bytes, validators).await,
+        Transport::Unix(c) => {
+            c.get(url, post, client.max_response_bytes, validators)
+                .await
+        }
+    }
+    .map_err(|e| e.context(endpoint(url)))?;
+
+    if let Fetched::Body { body, .. } = &fetched {
+        bugreport::response(body);
+    }
+    Ok(fetched)
+}
+
+/// `value` made safe to go in a URL's query, such as the user name or GRAPLSUB_CLIENT_NAME.
+pub fn encode(value: &str) -> String {
+    form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+/// The name of the API call a URL is for, like "getAlbum", to say which one failed.
+fn endpoint(url: &str) -> &str {
+    url.split('?')
+        .next()
+        .and_then(|path| path.rsplit('/').next())
+        .unwrap_or(url)
+}
+
+fn parse(client: &Client, url: &str, body: &[u8]) -> Result<TopLevel, Error> {
+    // Parsed straight from the bytes, rather than first making a String of what could be several
+    // megabytes of album list. The text is only needed if it doesn't parse.
+    let mut obj: TopLevel = serde_json::from_slice(body).map_err(|err| unparsed(url, body, err))?;
+    obj.excerpt = excerpt(&String::from_utf8_lossy(
+        &body[..body.len().min(EXCERPT_BYTES)],
+    ));
+    if obj.subsonic_response.status == "ok" {
+        client
+            .quirks
+            .fill_in(endpoint(url), &mut obj.subsonic_response);
+    }
+    Ok(obj)
+}
+
+/// What went wrong parsing a response, showing where in it.
+fn unparsed(url: &str, body: &[u8], err: serde_json::Error) -> Error {
+    Error::from(SerdeError::new(
+        String::from_utf8_lossy(body).into_owned(),
+        err,
+    ))
+    .context(endpoint(url))
+}
+
+// How much of a response to keep for error messages.
+const EXCERPT_CHARS: usize = 200;
+
+// Enough of the response to make an excerpt from, however much whitespace it starts with.
+const EXCERPT_BYTES: usize = 4096;
+
+/// The start of a response, on one line, without any secrets it might have echoed back.
+fn excerpt(text: &str) -> String {
+    let mut excerpt: String = bugreport::redact_text(text)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(EXCERPT_CHARS + 1)
+        .collect();
+
+    if excerpt.chars().count() > EXCERPT_CHARS {
+        excerpt = excerpt.chars().take(EXCERPT_CHARS).collect();
+        excerpt.push('…');
+    }
+    excerpt
+}
+
+/// GET `url`, or with `post` send its query string as a form to the rest of it.
+async fn http_get(
+    client: &reqwest::Client,
+    url: &str,
+    post: bool,
+    max_bytes: usize,
+    validators: &Validators,
+) -> Result<Fetched, Error> {
+    let request = match url.split_once('?').filter(|_| post) {
+        Some((path, form)) => client
+            .post(path)
+            .header(CONTENT_TYPE, FORM)
+            .body(form.to_string()),
+        None => client.get(url),
+    };
+    let mut request = request.timeout(Duration::from_secs(5));
+    for (name, value) in validators.headers() {
+        request = request.header(name, value);
+    }
+    let mut response = request.send().await?;
 
     match response.status() {
         StatusCode::OK => {
-            let text = response.text().await?;
-            let obj: TopLevel = serde_json::from_str(&text)
-                .map_err(|err| SerdeError::new(text.to_string(), err))?;
-            Ok((obj, text))
+            let validators = Validators::from_headers(response.headers());
+
+            // Don't even start if the server admits up front that it's too big. (For a compressed
+            // response reqwest doesn't know the length, so this only catches uncompressed ones.)
+            if response.content_length().unwrap_or(0) > max_bytes as u64 {
+                return Err(Error::ResponseTooLarge { limit: max_bytes });
+            }
+
+            // Otherwise read it a chunk at a time so we can bail out as soon as it goes over.
+            let mut body = Vec::new();
+            while let Some(chunk) = response.chunk().await? {
+                if body.len() + chunk.len() > max_bytes {
+                    return Err(Error::ResponseTooLarge { limit: max_bytes });
+                }
+                body.extend_from_slice(&chunk);
+            }
+
+            Ok(Fetched::Body { body, validators })
         }
+        StatusCode::NOT_MODIFIED => Ok(Fetched::NotModified),
         StatusCode::NOT_FOUND => {
             // Take a copy of the URL and remove the query string as that contains auth info (user,
-            // md5_pass_salt and salt) and isn't the problem here anyway.
+            // token and salt) and isn't the problem here anyway.
             let mut report_url = response.url().clone();
             report_url.set_query(None);
             Err(Error::NotFound {
@@ -149,12 +1348,247 @@ pub async fn get(client: &Client, url: &str) -> Result<(TopLevel, String), Error
 }
 
 /// Basic checks that are common to every API response.
-pub fn check_generic_response(resp: &TopLevel, json: &str) -> Result<(), RespParseError> {
+pub fn check_generic_response(resp: &TopLevel) -> Result<(), RespParseError> {
     if resp.subsonic_response.status != "ok" {
+        let error = resp.subsonic_response.error.as_ref();
         return Err(RespParseError::ResponseNotOk {
-            response: json.to_string(),
+            code: error.map_or(0, |e| e.code),
+            message: error
+                .and_then(|e| e.message.clone())
+                .unwrap_or_else(|| format!("status \"{}\"", resp.subsonic_response.status)),
         });
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::album::PAGE_SIZE;
+    use crate::testing;
+
+    fn playlist(json: &str) -> Playlist {
+        let top: TopLevel = serde_json::from_str(json).unwrap();
+        top.subsonic_response.playlist.unwrap()
+    }
+
+    #[test]
+    fn get_playlist_with_entries() {
+        let playlist = playlist(
+            r#"{"subsonic-response": {"status": "ok", "version": "1.16.1", "playlist": {
+                "id": "800", "name": "graplsub", "owner": "andy", "public": false,
+                "songCount": 2, "duration": 500, "created": "2025-01-02T03:04:05Z",
+                "entry": [
+                    {"id": "1", "title": "One", "albumId": "10", "playCount": 3},
+                    {"id": "2", "title": "Two", "albumId": "10", "duration": 200}
+                ]
+            }}}"#,
+        );
+
+        assert_eq!(playlist.public, Some(false));
+        assert_eq!(playlist.created.as_deref(), Some("2025-01-02T03:04:05Z"));
+        let entries = playlist.entry.unwrap();
+        let ids: Vec<&str> = entries.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2"]);
+        assert_eq!(entries[0].album_id.as_deref(), Some("10"));
+        assert_eq!(entries[0].play_count, Some(3));
+        assert_eq!(entries[1].duration, Some(200));
+    }
+
+    #[test]
+    fn get_playlist_with_one_entry_or_none() {
+        let one = playlist(
+            r#"{"subsonic-response": {"status": "ok", "playlist": {
+                "id": "800", "name": "graplsub", "entry": {"id": "1"}
+            }}}"#,
+        );
+        let empty = playlist(
+            r#"{"subsonic-response": {"status": "ok", "playlist": {
+                "id": "800", "name": "graplsub"
+            }}}"#,
+        );
+
+        assert_eq!(one.entry.unwrap().len(), 1);
+        assert!(empty.entry.is_none());
+    }
+
+    #[test]
+    fn one_or_many_of_anything() {
+        let list = |json| serde_json::from_str::<ListedAlbums>(json).map(|l| l.album);
+        let ids = |albums: Option<Vec<ListedAlbum>>| -> Vec<String> {
+            albums
+                .unwrap()
+                .into_iter()
+                .map(|a| a.id.into_owned())
+                .collect()
+        };
+
+        assert_eq!(ids(list(r#"{"album": {"id": "1"}}"#).unwrap()), vec!["1"]);
+        assert_eq!(
+            ids(list(r#"{"album": [{"id": "1"}, {"id": "2"}]}"#).unwrap()),
+            vec!["1", "2"]
+        );
+        assert_eq!(ids(list(r#"{"album": []}"#).unwrap()), Vec::<String>::new());
+        assert!(list(r#"{"album": null}"#).unwrap().is_none());
+        assert!(list("{}").unwrap().is_none());
+    }
+
+    #[test]
+    fn one_or_many_strings() {
+        let types = |json: &str| {
+            serde_json::from_str::<Album>(json)
+                .unwrap()
+                .release_types
+                .unwrap()
+        };
+
+        assert_eq!(
+            types(r#"{"id": "1", "releaseTypes": "Album"}"#),
+            vec!["Album"]
+        );
+        assert_eq!(
+            types(r#"{"id": "1", "releaseTypes": ["Album", "Compilation"]}"#),
+            vec!["Album", "Compilation"]
+        );
+    }
+
+    #[test]
+    fn one_or_many_says_what_was_wrong() {
+        let e = serde_json::from_str::<ListedAlbums>(r#"{"album": [{"id": "1"}, {"name": "x"}]}"#)
+            .unwrap_err();
+        assert!(e.to_string().contains("missing field `id`"), "{}", e);
+
+        assert!(serde_json::from_str::<ListedAlbums>(r#"{"album": 3}"#).is_err());
+    }
+
+    #[test]
+    fn listed_albums_borrow_what_they_can() {
+        let json = r#"{"id": "1", "name": "Hounds of Love", "artist": "Kate \"K\" Bush",
+            "created": "2025-01-02T03:04:05Z", "year": 1985, "userRating": 5,
+            "genres": [{"name": "Art Pop"}], "releaseTypes": "Album"}"#;
+        let listed: ListedAlbum = serde_json::from_str(json).unwrap();
+
+        assert!(matches!(listed.name, Some(Cow::Borrowed("Hounds of Love"))));
+        // Unless there's an escape in it.
+        assert!(matches!(listed.artist, Some(Cow::Owned(_))));
+
+        let owned: Album = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            serde_json::to_value(Album::from(listed)).unwrap(),
+            serde_json::to_value(owned).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn album_lists() {
+        let server = testing::Server::start(|call| {
+            let body = match call.get("type") {
+                Some("newest") => {
+                    json!({ "albumList": { "album": [{ "id": "1" }, { "id": "2" }] } })
+                }
+                Some("random") => json!({ "albumList": {} }),
+                Some("highest") => json!({}),
+                _ => json!({ "status": "failed", "error": { "code": 50, "message": "no" } }),
+            };
+            (Duration::ZERO, body)
+        })
+        .await;
+        let conf = testing::conf(&[("GRAPLSUB_BASE_URL", &server.base_url)]);
+        let client = create_client(&conf).unwrap();
+        let list = |t: &'static str| {
+            let url = format!("{}/rest/getAlbumList?type={}", server.base_url, t);
+            let client = &client;
+            async move {
+                get_album_list(client, &url, |page| {
+                    page.into_iter()
+                        .map(|a| a.id.into_owned())
+                        .collect::<Vec<_>>()
+                })
+                .await
+            }
+        };
+
+        assert_eq!(list("newest").await.unwrap(), vec!["1", "2"]);
+        assert!(list("random").await.unwrap().is_empty());
+        assert!(
+            list("highest")
+                .await
+                .unwrap_err()
+                .to_string()
+                .contains("missing an albumList")
+        );
+        assert!(
+            list("starred")
+                .await
+                .unwrap_err()
+                .to_string()
+                .contains("no")
+        );
+    }
+
+    // A benchmark rather than a test, of reading a page of albums owned and borrowed:
+    // cargo test --release listed_albums_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn listed_albums_benchmark() {
+        const ROUNDS: u32 = 1000;
+
+        let albums: Vec<Value> = (0..PAGE_SIZE)
+            .map(|i| {
+                json!({
+                    "id": format!("al-{:08x}", i), "name": format!("Album {}", i),
+                    "artist": format!("Artist {}", i), "artistId": format!("ar-{:08x}", i),
+                    "coverArt": format!("al-{:08x}_0", i), "created": "2025-01-02T03:04:05.000Z",
+                    "duration": 2400, "genre": "Rock", "playCount": 3, "songCount": 11,
+                    "year": 1979,
+                })
+            })
+            .collect();
+        let page = serde_json::to_vec(&json!({ "album": albums })).unwrap();
+
+        let time = |what: &str, read: &dyn Fn() -> usize| {
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                std::hint::black_box(read());
+            }
+            println!("{}: {:?} a page", what, start.elapsed() / ROUNDS);
+        };
+        let listed = || {
+            serde_json::from_slice::<ListedAlbums>(&page)
+                .unwrap()
+                .album
+                .unwrap()
+        };
+
+        time("owned", &|| {
+            #[derive(Deserialize)]
+            struct Owned {
+                album: Vec<Album>,
+            }
+            serde_json::from_slice::<Owned>(&page).unwrap().album.len()
+        });
+        time("borrowed, every album kept", &|| {
+            listed().into_iter().map(Album::from).count()
+        });
+        time("borrowed, none kept", &|| listed().len());
+    }
+
+    #[test]
+    fn encodes_query_values() {
+        assert_eq!(encode("graplsub"), "graplsub");
+        assert_eq!(encode("a b&c=d?é"), "a+b%26c%3Dd%3F%C3%A9");
+    }
+
+    #[test]
+    fn excerpts_leave_out_secrets() {
+        let body = "{ \"error\": \"Bad request:\n  /rest/ping?u=andy&t=abc&s=def\" }";
+        assert_eq!(
+            excerpt(body),
+            "{ \"error\": \"Bad request: /rest/ping?u=REDACTED&t=REDACTED&s=REDACTED\" }"
+        );
+        assert_eq!(excerpt(&format!("t={}", "x".repeat(300))), "t=REDACTED");
+    }
+}
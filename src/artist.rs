@@ -0,0 +1,167 @@
+use crate::api;
+use crate::api::Client;
+use crate::config;
+
+// How many similar artists to ask getArtistInfo2 for.
+const SIMILAR_ARTISTS: u16 = 10;
+
+async fn search(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    query: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/search3?u={}&f=json&v={}&c={}&query={}&artistCount=10&albumCount=0&songCount=0",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        api::encode(query)
+    );
+
+    api::get(client, &url).await
+}
+
+pub fn check_search_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
+    api::check_generic_response(resp)?;
+
+    if resp.subsonic_response.search_result.is_none() {
+        return Err(api::RespParseError::MissingSearchResult {
+            excerpt: resp.excerpt.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+async fn get(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/getArtist?u={}&f=json&v={}&c={}&id={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        id
+    );
+
+    api::get(client, &url).await
+}
+
+fn check_get_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
+    api::check_generic_response(resp)?;
+
+    if resp.subsonic_response.artist.is_none() {
+        return Err(api::RespParseError::MissingArtist {
+            excerpt: resp.excerpt.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+async fn info(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/getArtistInfo2?u={}&f=json&v={}&c={}&id={}&count={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        id,
+        SIMILAR_ARTISTS
+    );
+
+    api::get(client, &url).await
+}
+
+fn check_info_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
+    api::check_generic_response(resp)?;
+
+    if resp.subsonic_response.artist_info.is_none() {
+        return Err(api::RespParseError::MissingArtistInfo {
+            excerpt: resp.excerpt.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Find an artist in the library by name. An exact (case-insensitive) match is preferred, otherwise
+/// whatever the server thinks is the best match.
+pub async fn find(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    name: &str,
+) -> Result<Option<api::Artist>, api::Error> {
+    let subsonic_response = search(client, conf, api_ver, name).await?;
+
+    check_search_response(&subsonic_response)?;
+
+    // Safe to unwrap() as we already checked it was Some().
+    let mut artists = subsonic_response
+        .subsonic_response
+        .search_result
+        .unwrap()
+        .artist
+        .unwrap_or_default();
+
+    let exact = artists
+        .iter()
+        .position(|a| a.name.to_lowercase() == name.to_lowercase());
+
+    Ok(match exact {
+        Some(i) => Some(artists.swap_remove(i)),
+        None => artists.into_iter().next(),
+    })
+}
+
+/// All of an artist's albums.
+pub async fn albums(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id: &str,
+) -> Result<Vec<api::Album>, api::Error> {
+    let subsonic_response = get(client, conf, api_ver, id).await?;
+
+    check_get_response(&subsonic_response)?;
+
+    // Safe to unwrap() as we already checked it was Some().
+    Ok(subsonic_response
+        .subsonic_response
+        .artist
+        .unwrap()
+        .album
+        .unwrap_or_default())
+}
+
+/// Artists the server thinks are similar to this one, limited to those that are in the library.
+pub async fn similar(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id: &str,
+) -> Result<Vec<api::Artist>, api::Error> {
+    let subsonic_response = info(client, conf, api_ver, id).await?;
+
+    check_info_response(&subsonic_response)?;
+
+    // Safe to unwrap() as we already checked it was Some().
+    Ok(subsonic_response
+        .subsonic_response
+        .artist_info
+        .unwrap()
+        .similar_artist
+        .unwrap_or_default())
+}
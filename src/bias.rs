@@ -0,0 +1,162 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::album;
+use crate::api;
+use crate::api::Client;
+use crate::config;
+use crate::feedback;
+use crate::filter::Filter;
+use crate::hook;
+use crate::lastfm;
+use crate::listenbrainz;
+use crate::output;
+use crate::script::Script;
+
+// How much more or less likely an album is to be picked when it matches one of these.
+const RECENT_ARTIST_WEIGHT: f64 = 0.2;
+const RECOMMENDED_ARTIST_WEIGHT: f64 = 4.0;
+// Scaled by how similar the artist is to a favourite, so a favourite itself gets the whole boost.
+const SIMILAR_ARTIST_BOOST: f64 = 4.0;
+
+/// Things that make some albums more or less likely to be picked than others, or not picked at all.
+/// This is gathered up once before selection starts and then consulted for every candidate album.
+#[derive(Debug, Default)]
+pub struct Bias {
+    filter: Filter,
+    hook: Option<String>,
+    script: Option<Script>,
+    // Lowercase artist name to similarity, from 0 to 1.
+    lastfm: Option<HashMap<String, f64>>,
+    listenbrainz: Option<listenbrainz::Artists>,
+    // Albums found by GRAPLSUB_INCLUDE_QUERY, and their IDs to look them up by.
+    included: Vec<api::Album>,
+    included_ids: HashSet<String>,
+    include_weight: f64,
+    // How many playlists in a row albums have gone unplayed in, by album::identity().
+    skips: HashMap<String, u32>,
+}
+
+impl Bias {
+    pub async fn gather(client: &Client, conf: &config::Config, api_ver: &str) -> Bias {
+        let mut bias = Bias {
+            filter: Filter::gather(client, conf, api_ver).await,
+            hook: conf.album_hook.clone(),
+            script: conf.script.clone(),
+            ..Default::default()
+        };
+
+        if conf.learn_from_plays {
+            bias.skips = feedback::skips(conf);
+        }
+
+        if let Some(user) = &conf.listenbrainz_user {
+            bias.listenbrainz = Some(listenbrainz::artists(conf, user).await);
+        }
+
+        if let (Some(api_key), Some(favourites)) = (&conf.lastfm_api_key, &conf.lastfm_favourites) {
+            bias.lastfm = Some(lastfm::similar_to_favourites(conf, api_key, favourites).await);
+        }
+
+        // Like the other outside help, a search that fails only makes for an ordinary selection.
+        if let Some(query) = &conf.include_query {
+            match album::search(client, conf, api_ver, query).await {
+                Ok(albums) => {
+                    if albums.is_empty() {
+                        output::warn(format!(
+                            "No albums match GRAPLSUB_INCLUDE_QUERY \"{}\".",
+                            query
+                        ));
+                    }
+                    bias.included_ids = albums.iter().map(|a| a.id.clone()).collect();
+                    bias.included = albums;
+                    bias.include_weight = conf.include_weight;
+                }
+                Err(e) => output::warn(format!("Couldn't use GRAPLSUB_INCLUDE_QUERY: {}", e)),
+            }
+        }
+
+        bias
+    }
+
+    /// What can be had without the server or anywhere else, for simulating: Filter::offline(),
+    /// GRAPLSUB_SCRIPT, and what's been learnt from plays.
+    pub fn offline(conf: &config::Config) -> Bias {
+        let mut bias = Bias {
+            filter: Filter::offline(conf),
+            script: conf.script.clone(),
+            ..Default::default()
+        };
+
+        if conf.learn_from_plays {
+            bias.skips = feedback::skips(conf);
+        }
+
+        bias
+    }
+
+    /// Whether this makes any difference at all, so that modes can skip the extra work.
+    pub fn is_neutral(&self) -> bool {
+        !self.filter.is_active()
+            && self.hook.is_none()
+            && self.script.is_none()
+            && self.lastfm.is_none()
+            && self.listenbrainz.is_none()
+            && self.included.is_empty()
+            && self.skips.is_empty()
+    }
+
+    /// Albums from GRAPLSUB_INCLUDE_QUERY, for modes that draw from only part of the library to
+    /// add to what they draw.
+    pub fn included(&self) -> &[api::Album] {
+        &self.included
+    }
+
+    /// The final say on an album that is about to be picked. This is separate from weight() as it
+    /// can be expensive, so is only asked about albums that would otherwise make it in.
+    pub async fn accepts(&self, album: &api::Album) -> bool {
+        match &self.hook {
+            Some(command) => hook::accepts(command, album).await,
+            None => true,
+        }
+    }
+
+    /// The relative likelihood of an album being picked, where 1.0 is normal and 0.0 means it must
+    /// not be picked at all.
+    pub fn weight(&self, album: &api::Album) -> f64 {
+        if !self.filter.allows(album) {
+            return 0.0;
+        }
+
+        let mut weight = 1.0;
+        let artist = album.artist.as_deref().unwrap_or_default().to_lowercase();
+
+        if let Some(lb) = &self.listenbrainz {
+            if lb.recent.contains(&artist) {
+                weight *= RECENT_ARTIST_WEIGHT;
+            }
+            if lb.recommended.contains(&artist) {
+                weight *= RECOMMENDED_ARTIST_WEIGHT;
+            }
+        }
+
+        if let Some(similarity) = self.lastfm.as_ref().and_then(|l| l.get(&artist)) {
+            weight *= 1.0 + SIMILAR_ARTIST_BOOST * similarity;
+        }
+
+        if self.included_ids.contains(&album.id) {
+            weight *= self.include_weight;
+        }
+
+        if !self.skips.is_empty()
+            && let Some(&times) = self.skips.get(&album::identity(album))
+        {
+            weight *= feedback::weight(times);
+        }
+
+        if let Some(script) = &self.script {
+            weight *= script.weight(album);
+        }
+
+        weight
+    }
+}
@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::output;
+use crate::state;
+
+// A cache of what the server told us, kept as JSON files under GRAPLSUB_CACHE_DIR so that the next
+// run (or a re-run after a failure) needn't ask again. Each server and user gets a directory of
+// its own, as IDs only mean anything to the server that gave them out. It's only ever a
+// shortcut: anything missing, stale or unreadable is fetched again as if it weren't there.
+
+/// What's in each file.
+#[derive(Deserialize, Serialize)]
+struct Entry<T> {
+    // Seconds since the Unix epoch.
+    fetched: u64,
+    value: T,
+}
+
+/// Where the cached thing of this kind and key lives, if there's a cache at all.
+fn path(conf: &config::Config, kind: &str, key: &str) -> Option<PathBuf> {
+    let dir = conf.cache_dir.as_ref()?;
+    let server = format!(
+        "{:x}",
+        md5::compute(format!("{}\n{}", conf.base_url, conf.user))
+    );
+
+    // Keys are IDs and such from the server, which could have anything in them, so encode them
+    // to be safe as file names.
+    Some(
+        dir.join(server)
+            .join(kind)
+            .join(format!("{}.json", hex::encode(key))),
+    )
+}
+
+/// The cached thing of this kind and key, unless it's not there, too old, or being refreshed.
+pub fn load<T: DeserializeOwned>(conf: &config::Config, kind: &str, key: &str) -> Option<T> {
+    load_any(conf, kind, key).and_then(|(value, fresh)| fresh.then_some(value))
+}
+
+/// The cached thing of this kind and key however old it is, and whether it's still fresh. Nothing
+/// when refreshing.
+pub fn load_any<T: DeserializeOwned>(
+    conf: &config::Config,
+    kind: &str,
+    key: &str,
+) -> Option<(T, bool)> {
+    if conf.refresh {
+        return None;
+    }
+
+    let text = fs::read(path(conf, kind, key)?).ok()?;
+    let entry: Entry<T> = serde_json::from_slice(&text).ok()?;
+    let fresh = state::now().saturating_sub(entry.fetched) < conf.cache_ttl_secs;
+
+    Some((entry.value, fresh))
+}
+
+/// Remember a thing of this kind under the key. Failing to is only worth a warning.
+pub fn store<T: Serialize>(conf: &config::Config, kind: &str, key: &str, value: &T) {
+    let Some(path) = path(conf, kind, key) else {
+        return;
+    };
+
+    let entry = Entry {
+        fetched: state::now(),
+        value,
+    };
+    // Written to the side and moved into place so that a reader never sees half a file.
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&tmp, serde_json::to_vec(&entry).unwrap_or_default()))
+        .and_then(|_| fs::rename(&tmp, &path));
+
+    if let Err(e) = result {
+        output::warn(format!("Couldn't cache to {}: {}", path.display(), e));
+    }
+}
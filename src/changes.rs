@@ -0,0 +1,78 @@
+use std::collections::HashSet;
+
+use crate::album;
+use crate::api;
+use crate::output::{self, Stream, Style};
+use crate::state;
+
+/// How this run's albums differ from the previous run's.
+pub struct Changes<'a> {
+    // Albums that weren't in the previous run, in playlist order.
+    pub added: Vec<&'a api::Album>,
+    // How many of the previous run's albums aren't in this one.
+    pub dropped: usize,
+    // How much of this run was also in the previous one.
+    pub overlap_percent: f64,
+}
+
+/// Albums are compared by what they are, so that a rescan that gives them new IDs doesn't make them
+/// all look new. Runs from before that was remembered only have IDs to go on.
+pub fn between<'a>(previous: &state::Run, current: &'a [api::Album]) -> Changes<'a> {
+    let by_id = previous.identities.is_empty();
+    let key = |a: &api::Album| {
+        if by_id {
+            a.id.clone()
+        } else {
+            album::identity(a)
+        }
+    };
+
+    let before: HashSet<String> = if by_id {
+        previous.albums.iter().cloned().collect()
+    } else {
+        previous.identities.iter().cloned().collect()
+    };
+    let now: HashSet<String> = current.iter().map(key).collect();
+
+    let added: Vec<&api::Album> = current
+        .iter()
+        .filter(|a| !before.contains(&key(a)))
+        .collect();
+    let kept = now.intersection(&before).count();
+
+    Changes {
+        added,
+        dropped: before.len() - kept,
+        overlap_percent: if now.is_empty() {
+            0.0
+        } else {
+            100.0 * kept as f64 / now.len() as f64
+        },
+    }
+}
+
+/// Print the changes for a human to read.
+pub fn print(changes: &Changes) {
+    println!(
+        "{}",
+        output::paint(
+            Stream::Stdout,
+            Style::Bold,
+            format!(
+                "Since the last run: {} albums new, {} dropped, {:.0}% the same.",
+                changes.added.len(),
+                changes.dropped,
+                changes.overlap_percent
+            )
+        )
+    );
+
+    for album in &changes.added {
+        println!(
+            "  {} {} – {}",
+            output::paint(Stream::Stdout, Style::Green, "+"),
+            album.artist.as_deref().unwrap_or("Unknown artist"),
+            album.name.as_deref().unwrap_or("Unknown album")
+        );
+    }
+}
@@ -0,0 +1,416 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::builder::ArgPredicate;
+use clap::{Args, Parser, Subcommand, ValueHint};
+use clap_complete::ArgValueCandidates;
+
+use crate::completions;
+use crate::config;
+use crate::schedule::Days;
+
+// Command line handling. Most configuration is in environment variables, so this is mainly about
+// choosing what to do, plus a few global options to override the environment for a one-off.
+
+/// Build a playlist of random albums on a Subsonic server.
+#[derive(Debug, Parser)]
+#[command(
+    name = "graplsub",
+    version,
+    after_help = "Other configuration is by GRAPLSUB_* environment variables; see the README."
+)]
+pub struct Cli {
+    #[command(flatten)]
+    pub globals: Globals,
+    /// What to do, which is to build the playlist if it isn't given.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Options that apply to every command, overriding the environment. They can go before or after
+/// the command.
+#[derive(Args, Debug, Default)]
+pub struct Globals {
+    /// GRAPLSUB_BASE_URL
+    #[arg(long, global = true, value_name = "URL", value_hint = ValueHint::Url)]
+    base_url: Option<String>,
+    /// GRAPLSUB_USER
+    #[arg(long, global = true)]
+    user: Option<String>,
+    /// GRAPLSUB_PLAYLIST_NAME
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        add = ArgValueCandidates::new(completions::playlists)
+    )]
+    playlist_name: Option<String>,
+    /// GRAPLSUB_STATE_FILE
+    #[arg(long, global = true, value_name = "PATH", value_hint = ValueHint::FilePath)]
+    state_file: Option<PathBuf>,
+    /// GRAPLSUB_CONFIG_FILE
+    #[arg(long, global = true, value_name = "PATH", value_hint = ValueHint::FilePath)]
+    config_file: Option<PathBuf>,
+    /// Only use this [section] of the config file
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+    /// GRAPLSUB_VERBOSE
+    #[arg(long, global = true)]
+    verbose: bool,
+    /// Don't use anything in GRAPLSUB_CACHE_DIR this time
+    #[arg(long, global = true)]
+    refresh: bool,
+}
+
+impl Globals {
+    /// The file of GRAPLSUB_* settings to read on top of the environment, if any.
+    pub fn config_file(&self) -> Option<PathBuf> {
+        self.config_file
+            .clone()
+            .or_else(|| std::env::var_os("GRAPLSUB_CONFIG_FILE").map(PathBuf::from))
+    }
+
+    /// The one profile to use, if only one is wanted.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    pub fn apply(&self, conf: &mut config::Config) {
+        if let Some(b) = &self.base_url {
+            conf.base_url = b.clone();
+        }
+        if let Some(u) = &self.user {
+            conf.user = u.clone();
+        }
+        if let Some(p) = &self.playlist_name {
+            conf.playlist_name = p.clone();
+        }
+        if let Some(s) = &self.state_file {
+            conf.state_file = Some(s.clone());
+        }
+        if self.verbose {
+            conf.verbose = true;
+        }
+        conf.refresh = self.refresh;
+    }
+}
+
+/// What to do.
+#[derive(Clone, Debug, PartialEq, Subcommand)]
+pub enum Command {
+    /// Build the playlist (the default)
+    Run {
+        /// Review the albums before they go in
+        #[arg(long)]
+        interactive: bool,
+        /// Only for the profiles whose GRAPLSUB_DAYS include these days (default today)
+        #[arg(long, value_name = "DAYS", value_parser = Days::parse)]
+        when: Option<Days>,
+        /// Write a report of the run to PATH
+        #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath)]
+        report_html: Option<PathBuf>,
+        /// Use exactly the albums listed in FILE (- for stdin), by ID or as "Artist - Album"
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        albums_from: Option<String>,
+    },
+    /// List your playlists
+    List {
+        /// Print them as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the songs in a playlist, given its ID or name
+    Show {
+        #[arg(add = ArgValueCandidates::new(completions::playlists))]
+        playlist: String,
+        #[command(flatten)]
+        format: Formats,
+    },
+    /// Delete playlists whose names match a pattern like "graplsub_*"
+    Delete {
+        #[arg(add = ArgValueCandidates::new(completions::playlists))]
+        pattern: String,
+        /// Only say what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+        /// Don't ask first
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Rename a playlist, given its ID or name
+    Rename {
+        #[arg(add = ArgValueCandidates::new(completions::playlists))]
+        playlist: String,
+        #[arg(value_name = "NEW_NAME")]
+        name: String,
+    },
+    /// Delete playlists graplsub made that are out of date or no longer any profile's playlist
+    Gc {
+        /// Only say what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check that each profile's playlist still has what the last run put in it and that the
+    /// server still has its songs
+    Check {
+        /// Put the playlist right
+        #[arg(long, default_value_if("replace", ArgPredicate::IsPresent, "true"))]
+        repair: bool,
+        /// Put it right, adding random albums for songs that are gone (implies --repair)
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Check that the server can be reached and the settings make sense
+    Doctor,
+    /// Check or describe the settings
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Try out the selection against the library as it was last cached, without the server, and
+    /// say how it went
+    Simulate {
+        /// How many times to try it
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 30,
+            value_parser = clap::value_parser!(u32).range(1..)
+        )]
+        runs: u32,
+    },
+    /// Never pick an album again, given its ID, as "Artist - Album" or as something to search
+    /// for. Needs GRAPLSUB_STATE_FILE.
+    Ban { album: String },
+    /// Let a banned album be picked again
+    Unban { album: String },
+    /// Print a playlist as JSON, for import
+    Export {
+        #[arg(add = ArgValueCandidates::new(completions::playlists))]
+        playlist: String,
+    },
+    /// Make a new playlist from an export
+    Import {
+        /// The export, or - for stdin
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: String,
+        /// What to call the playlist, rather than what it was called
+        #[arg(long)]
+        name: Option<String>,
+        /// Find the songs by what they are, for an export from another server
+        #[arg(long = "match")]
+        match_songs: bool,
+    },
+    /// Build the playlist, then again every so often or on GRAPLSUB_SCHEDULE
+    Daemon {
+        /// How long to wait between playlists
+        #[arg(
+            long,
+            value_name = "DURATION",
+            default_value = "24h",
+            value_parser = config::parse_duration
+        )]
+        every: Duration,
+    },
+    /// Print tab completion for a shell
+    Completions { shell: completions::Shell },
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command::Run {
+            interactive: false,
+            when: None,
+            report_html: None,
+            albums_from: None,
+        }
+    }
+}
+
+/// What to do with the settings.
+#[derive(Clone, Debug, PartialEq, Subcommand)]
+pub enum ConfigCommand {
+    /// Check the settings over without doing anything with them, including for variables that
+    /// aren't settings at all
+    Check,
+    /// Print a JSON Schema of the settings
+    Schema,
+}
+
+/// How `show` is to print the songs, of which only one can be asked for.
+#[derive(Args, Clone, Debug, PartialEq)]
+#[group(multiple = false)]
+pub struct Formats {
+    /// Print them as JSON
+    #[arg(long)]
+    json: bool,
+    /// Print them as an M3U playlist
+    #[arg(long)]
+    m3u: bool,
+}
+
+impl Formats {
+    pub fn format(&self) -> Format {
+        if self.json {
+            Format::Json
+        } else if self.m3u {
+            Format::M3u
+        } else {
+            Format::Table
+        }
+    }
+}
+
+/// How to print a list of songs.
+#[derive(Debug, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+    M3u,
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(["graplsub"].iter().chain(args))
+    }
+
+    #[test]
+    fn definition_is_sound() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn no_command_is_run() {
+        let cli = parse(&[]).unwrap();
+        assert_eq!(cli.command, None);
+        assert_eq!(cli.command.unwrap_or_default(), Command::default());
+    }
+
+    #[test]
+    fn globals_go_before_or_after_the_command() {
+        for args in [
+            &["--user", "u", "--refresh", "list"][..],
+            &["list", "--user", "u", "--refresh"],
+        ] {
+            let cli = parse(args).unwrap();
+            assert_eq!(cli.globals.user.as_deref(), Some("u"));
+            assert!(cli.globals.refresh);
+            assert_eq!(cli.command, Some(Command::List { json: false }));
+        }
+    }
+
+    #[test]
+    fn globals_override_the_configuration() {
+        let cli = parse(&[
+            "--playlist-name",
+            "p",
+            "--state-file",
+            "s.json",
+            "--verbose",
+        ])
+        .unwrap();
+        let mut conf = crate::testing::conf(&[]);
+        cli.globals.apply(&mut conf);
+
+        assert_eq!(conf.playlist_name, "p");
+        assert_eq!(conf.state_file, Some(PathBuf::from("s.json")));
+        assert!(conf.verbose);
+    }
+
+    #[test]
+    fn show_takes_one_format() {
+        let format = |args: &[&str]| match parse(args).unwrap().command {
+            Some(Command::Show { format, .. }) => format.format(),
+            other => panic!("not show: {:?}", other),
+        };
+
+        assert_eq!(format(&["show", "p"]), Format::Table);
+        assert_eq!(format(&["show", "p", "--json"]), Format::Json);
+        assert_eq!(format(&["show", "--m3u", "p"]), Format::M3u);
+        assert!(parse(&["show", "p", "--json", "--m3u"]).is_err());
+        assert!(parse(&["show"]).is_err());
+    }
+
+    #[test]
+    fn replacing_is_repairing() {
+        let check = |args: &[&str]| parse(args).unwrap().command.unwrap();
+
+        assert_eq!(
+            check(&["check"]),
+            Command::Check {
+                repair: false,
+                replace: false
+            }
+        );
+        assert_eq!(
+            check(&["check", "--replace"]),
+            Command::Check {
+                repair: true,
+                replace: true
+            }
+        );
+    }
+
+    #[test]
+    fn values_are_checked() {
+        assert_eq!(
+            parse(&["simulate"]).unwrap().command,
+            Some(Command::Simulate { runs: 30 })
+        );
+        assert!(parse(&["simulate", "--runs", "0"]).is_err());
+        assert!(parse(&["simulate", "--runs", "x"]).is_err());
+
+        assert_eq!(
+            parse(&["daemon"]).unwrap().command,
+            Some(Command::Daemon {
+                every: Duration::from_secs(24 * 60 * 60)
+            })
+        );
+        assert_eq!(
+            parse(&["daemon", "--every", "10m"]).unwrap().command,
+            Some(Command::Daemon {
+                every: Duration::from_secs(600)
+            })
+        );
+        assert!(parse(&["daemon", "--every", "soon"]).is_err());
+        assert!(parse(&["run", "--when", "someday"]).is_err());
+    }
+
+    #[test]
+    fn import_options() {
+        assert_eq!(
+            parse(&["import", "-", "--match", "--name", "n"])
+                .unwrap()
+                .command,
+            Some(Command::Import {
+                file: "-".to_string(),
+                name: Some("n".to_string()),
+                match_songs: true
+            })
+        );
+    }
+
+    #[test]
+    fn config_has_its_own_commands() {
+        assert_eq!(
+            parse(&["config", "schema"]).unwrap().command,
+            Some(Command::Config {
+                command: ConfigCommand::Schema
+            })
+        );
+        assert!(parse(&["config"]).is_err());
+        assert!(parse(&["config", "fix"]).is_err());
+    }
+
+    #[test]
+    fn unknown_commands_and_options_are_refused() {
+        assert!(parse(&["frobnicate"]).is_err());
+        assert!(parse(&["list", "--yes"]).is_err());
+        assert!(parse(&["ban"]).is_err());
+    }
+}
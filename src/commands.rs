@@ -0,0 +1,894 @@
+// The commands other than building a playlist, which is main::run().
+
+use std::collections::HashSet;
+use std::io::{BufRead, IsTerminal, Write};
+
+use futures_util::{StreamExt, stream};
+use thiserror::Error;
+
+use crate::album;
+use crate::api;
+use crate::api::Client;
+use crate::cli;
+use crate::cli::Format;
+use crate::config;
+use crate::music_folder;
+use crate::output;
+use crate::playlist;
+use crate::scan;
+use crate::select;
+use crate::song;
+use crate::state;
+use crate::stats;
+use crate::system;
+
+// How many songs to ask the server about at once.
+const IN_FLIGHT: usize = 4;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Api(#[from] api::Error),
+
+    #[error("Couldn't read {file}: {source}")]
+    Read {
+        file: String,
+        source: std::io::Error,
+    },
+
+    #[error(transparent)]
+    Parse(#[from] format_serde_error::SerdeError),
+
+    #[error("There's already a playlist called \"{name}\". Use --name to import it as another.")]
+    Exists { name: String },
+
+    #[error("Checking playlists needs GRAPLSUB_STATE_FILE, to know what should be in them")]
+    NoStateFile,
+
+    #[error("Banning albums needs GRAPLSUB_STATE_FILE, to remember them in")]
+    NoBanList,
+
+    #[error("Couldn't find an album \"{0}\"")]
+    NoAlbum(String),
+
+    #[error("\"{query}\" could be any of {count} albums. Try \"Artist - Album\", or its ID.")]
+    Ambiguous { query: String, count: usize },
+
+    #[error("\"{0}\" isn't banned")]
+    NotBanned(String),
+
+    #[error(transparent)]
+    State(#[from] state::Error),
+}
+
+/// Print the user's playlists, as a table or as JSON.
+pub async fn list(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    json: bool,
+) -> Result<(), Error> {
+    let playlists = playlist::all(client, conf, api_ver).await?;
+
+    if json {
+        // Can't fail: everything in a Playlist serialises.
+        println!("{}", serde_json::to_string_pretty(&playlists).unwrap());
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = playlists
+        .iter()
+        .map(|p| {
+            vec![
+                p.id.clone(),
+                p.name.clone(),
+                p.owner.clone().unwrap_or_default(),
+                p.song_count.map(|n| n.to_string()).unwrap_or_default(),
+                p.duration
+                    .map(|d| stats::duration(u64::from(d)))
+                    .unwrap_or_default(),
+                p.comment.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    print_table(
+        &["ID", "Name", "Owner", "Songs", "Duration", "Comment"],
+        &rows,
+    );
+
+    Ok(())
+}
+
+/// Print the songs in a playlist, found by ID or name, as a table, JSON or an M3U playlist.
+pub async fn show(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id_or_name: &str,
+    format: &Format,
+) -> Result<(), Error> {
+    let found = playlist::find(client, conf, api_ver, id_or_name).await?;
+    let songs = playlist::fetch(client, conf, api_ver, &found.id)
+        .await?
+        .entry
+        .unwrap_or_default();
+
+    match format {
+        Format::Json => {
+            // Can't fail: everything in a Song serialises.
+            println!("{}", serde_json::to_string_pretty(&songs).unwrap());
+        }
+        // Extended M3U, with file paths as the server gives them. They're relative to the music
+        // folder, so the playlist needs to be put there to be any use to a local player.
+        Format::M3u => {
+            println!("#EXTM3U");
+            println!("#PLAYLIST:{}", found.name);
+            for song in &songs {
+                println!(
+                    "#EXTINF:{},{} - {}",
+                    song.duration.map(i64::from).unwrap_or(-1),
+                    song.artist.as_deref().unwrap_or_default(),
+                    song.title.as_deref().unwrap_or_default()
+                );
+                println!("{}", song.path.as_deref().unwrap_or(&song.id));
+            }
+        }
+        Format::Table => {
+            let rows: Vec<Vec<String>> = songs
+                .iter()
+                .enumerate()
+                .map(|(i, s)| {
+                    vec![
+                        (i + 1).to_string(),
+                        s.artist.clone().unwrap_or_default(),
+                        s.title.clone().unwrap_or_default(),
+                        s.album.clone().unwrap_or_default(),
+                        s.duration.map(clock).unwrap_or_default(),
+                    ]
+                })
+                .collect();
+
+            print_table(&["#", "Artist", "Title", "Album", "Length"], &rows);
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete every playlist whose name matches a pattern, after asking first unless `yes` is set.
+pub async fn delete(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    pattern: &str,
+    dry_run: bool,
+    yes: bool,
+) -> Result<(), Error> {
+    let doomed: Vec<api::Playlist> = playlist::all(client, conf, api_ver)
+        .await?
+        .into_iter()
+        .filter(|p| glob_match(pattern, &p.name))
+        .collect();
+
+    if doomed.is_empty() {
+        output::warn(format!("No playlists match \"{}\".", pattern));
+        return Ok(());
+    }
+
+    for p in &doomed {
+        println!("{}  {}", p.id, p.name);
+    }
+
+    if dry_run {
+        output::note(format!("Would delete {} playlists.", doomed.len()));
+        return Ok(());
+    }
+
+    if !yes && !confirm(&format!("Delete these {} playlists?", doomed.len())) {
+        output::note("Not deleting anything.");
+        return Ok(());
+    }
+
+    for p in &doomed {
+        playlist::remove(client, conf, api_ver, &p.id).await?;
+    }
+
+    output::note(format!("Deleted {} playlists.", doomed.len()));
+
+    Ok(())
+}
+
+/// Delete graplsub's own playlists that aren't wanted any more, or with `dry_run` just say which
+/// they are. Nothing else is touched, so there's no need to ask first.
+pub async fn gc(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let stale = playlist::stale(client, conf, api_ver).await?;
+
+    if stale.is_empty() {
+        output::note("No stale playlists.");
+        return Ok(());
+    }
+
+    for (p, why) in &stale {
+        println!("{}  {}  ({})", p.id, p.name, why);
+    }
+
+    if dry_run {
+        output::note(format!("Would delete {} playlists.", stale.len()));
+        return Ok(());
+    }
+
+    for (p, _) in &stale {
+        playlist::remove(client, conf, api_ver, &p.id).await?;
+    }
+
+    output::note(format!("Deleted {} playlists.", stale.len()));
+
+    Ok(())
+}
+
+/// Check that the playlist is still what the last run made it: that it's still there, has the same
+/// songs, as a library rescan can leave a playlist with some or all of them gone, and that the
+/// server still has every song in it, as files get deleted and moved. With `repair`, songs that
+/// have gone from the playlist are put back, on the end, a playlist that's gone is made again, and
+/// songs that the server no longer has are taken out, and with `replace` made up for with random
+/// albums. Returns whether the playlist is as it should be, after any repairs.
+pub async fn check(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    repair: bool,
+    replace: bool,
+) -> Result<bool, Error> {
+    let path = conf.state_file.as_ref().ok_or(Error::NoStateFile)?;
+    let mut state = state::State::load(path)?;
+
+    let Some(run) = state.last_finished(conf) else {
+        output::note(format!(
+            "No run has made \"{}\" yet, so there's nothing to check.",
+            conf.playlist_name
+        ));
+        return Ok(true);
+    };
+    let Some(mut id) = run.playlist_id.clone() else {
+        return Ok(true);
+    };
+    let started = run.started;
+    let recorded = run.songs.clone();
+    let chosen = run.albums.clone();
+    if recorded.is_empty() {
+        output::note(
+            "The last run was by a version that didn't record its songs, so only checking the \
+             songs that are in the playlist now.",
+        );
+    }
+
+    let exists = playlist::all(client, conf, api_ver)
+        .await?
+        .iter()
+        .any(|p| p.id == id);
+    let entries = if exists {
+        playlist::song_ids(client, conf, api_ver, &id).await?
+    } else {
+        Vec::new()
+    };
+
+    let present: HashSet<&String> = entries.iter().collect();
+    let wanted: HashSet<&String> = recorded.iter().collect();
+    let missing: Vec<&String> = recorded.iter().filter(|s| !present.contains(s)).collect();
+    let extra = entries.iter().filter(|s| !wanted.contains(s)).count();
+
+    // Songs the server no longer has: entries in the playlist, and missing ones, which can't be put
+    // back.
+    let orphans: HashSet<String> = entries
+        .iter()
+        .zip(on_server(client, conf, api_ver, &entries).await?)
+        .filter(|(_, there)| !*there)
+        .map(|(song, _)| song.clone())
+        .collect();
+    let mut restorable: Vec<&String> = Vec::new();
+    let mut lost: Vec<&String> = Vec::new();
+    for (song, there) in missing
+        .iter()
+        .zip(on_server(client, conf, api_ver, &missing).await?)
+    {
+        if there {
+            restorable.push(*song);
+        } else {
+            lost.push(*song);
+        }
+    }
+
+    let name = &conf.playlist_name;
+    if !exists {
+        println!("\"{}\" ({}) has gone.", name, id);
+    } else if entries.is_empty() && !recorded.is_empty() {
+        println!(
+            "\"{}\" has been emptied, probably by a library rescan. It should have {} songs.",
+            name,
+            recorded.len()
+        );
+    } else if !missing.is_empty() {
+        println!(
+            "\"{}\" is missing {} of its {} songs.",
+            name,
+            missing.len(),
+            recorded.len()
+        );
+    }
+    if !lost.is_empty() {
+        println!(
+            "{} of the songs that should be in \"{}\" are no longer on the server.",
+            lost.len(),
+            name
+        );
+    }
+    if !orphans.is_empty() {
+        println!(
+            "{} of the songs in \"{}\" are no longer on the server.",
+            orphans.len(),
+            name
+        );
+    }
+    if extra > 0 && !recorded.is_empty() {
+        // Someone else's business, so they're left alone.
+        println!(
+            "\"{}\" has {} songs that the last run didn't put in it.",
+            name, extra
+        );
+    }
+
+    if exists && missing.is_empty() && orphans.is_empty() {
+        output::note(format!("\"{}\" is as the last run left it.", name));
+        return Ok(true);
+    }
+    if !repair || (!exists && recorded.is_empty()) {
+        return Ok(false);
+    }
+
+    let removed = if exists {
+        playlist::remove_ids(client, conf, api_ver, &id, &orphans).await?
+    } else {
+        id = playlist::create_managed(client, conf, api_ver, name).await?;
+        println!("Made it again as {}.", id);
+        0
+    };
+
+    let restorable: Vec<String> = restorable.into_iter().cloned().collect();
+    playlist::add_songs(client, conf, api_ver, &id, &restorable).await?;
+
+    // Whatever's gone from the server is gone from the record too, so it isn't reported again.
+    let gone: HashSet<&String> = orphans.iter().chain(lost).collect();
+    let mut songs: Vec<String> = recorded
+        .iter()
+        .filter(|s| !gone.contains(s))
+        .cloned()
+        .collect();
+    let mut albums = chosen.clone();
+
+    println!(
+        "Put back {} songs and took out {}.",
+        restorable.len(),
+        removed
+    );
+
+    if replace && !gone.is_empty() {
+        // As many albums as it usually takes to make up that many songs.
+        let per_album = (recorded.len() / chosen.len().max(1)).max(1);
+        let count = gone.len().div_ceil(per_album);
+        let (ids, added) = replacements(client, conf, api_ver, &id, count, &chosen).await?;
+        println!(
+            "Added {} songs from {} random albums to make up for those.",
+            added.len(),
+            ids.len()
+        );
+        albums.extend(ids);
+        songs.extend(added);
+    }
+
+    if let Some(run) = state.runs.iter_mut().find(|r| r.started == started) {
+        run.playlist_id = Some(id);
+        run.songs = songs;
+        run.albums = albums;
+    }
+    state.save(path)?;
+
+    Ok(true)
+}
+
+/// Whether the server still has each of the songs, a few at a time.
+async fn on_server(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    ids: &[impl AsRef<str>],
+) -> Result<Vec<bool>, api::Error> {
+    stream::iter(ids)
+        .map(|id| song::exists(client, conf, api_ver, id.as_ref()))
+        .buffered(IN_FLIGHT)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect()
+}
+
+/// Add the songs of `count` random albums, other than those `chosen` already, to the end of the
+/// playlist. Returns the IDs of the albums and of the songs.
+async fn replacements(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    playlist_id: &str,
+    count: usize,
+    chosen: &[String],
+) -> Result<(Vec<String>, Vec<String>), api::Error> {
+    let mut albums = Vec::new();
+    let mut songs = Vec::new();
+
+    for picked in select::replacements(client, conf, api_ver, count, chosen).await? {
+        let album = album::fetch(client, conf, api_ver, &picked.id).await?;
+        songs.extend(album.song.into_iter().flatten().map(|s| s.id));
+        albums.push(album.id);
+    }
+    playlist::add_songs(client, conf, api_ver, playlist_id, &songs).await?;
+
+    Ok((albums, songs))
+}
+
+/// Rename a playlist, found by ID or name.
+pub async fn rename(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id_or_name: &str,
+    new_name: &str,
+) -> Result<(), Error> {
+    let found = playlist::find(client, conf, api_ver, id_or_name).await?;
+
+    playlist::rename(client, conf, api_ver, &found.id, new_name).await?;
+
+    output::note(format!("Renamed \"{}\" to \"{}\".", found.name, new_name));
+
+    Ok(())
+}
+
+/// Check that the server can be reached and that the settings make sense to it, printing how each
+/// check went. Returns whether they all passed.
+pub async fn doctor(conf: &mut config::Config, api_ver: &str) -> bool {
+    let mut healthy = true;
+    let mut report = |ok: bool, what: String| {
+        println!("{}  {}", if ok { "ok  " } else { "FAIL" }, what);
+        healthy &= ok;
+    };
+
+    if let Some(path) = &conf.state_file {
+        match state::State::load(path) {
+            Ok(s) => report(
+                true,
+                format!("State file {} ({} runs)", path.display(), s.runs.len()),
+            ),
+            Err(e) => report(false, e.to_string()),
+        }
+    }
+
+    let (client, negotiated) = match system::find_server(conf, api_ver).await {
+        Ok(c) => c,
+        Err(e) => {
+            report(false, format!("Couldn't set up a client: {}", e));
+            return false;
+        }
+    };
+    let api_ver = negotiated.as_str();
+
+    match system::info(&client, conf, api_ver).await {
+        Ok(info) => report(
+            true,
+            format!(
+                "Server at {} is {} {}, speaking API version {}",
+                conf.base_url,
+                info.server_type.as_deref().unwrap_or("a Subsonic server"),
+                info.server_version.as_deref().unwrap_or_default(),
+                info.api_version.as_deref().unwrap_or("unknown")
+            ),
+        ),
+        Err(e) => {
+            // Nothing else is going to work either.
+            report(
+                false,
+                format!("Server at {} didn't answer properly: {}", conf.base_url, e),
+            );
+            return false;
+        }
+    }
+
+    match music_folder::resolve(&client, conf, api_ver).await {
+        Ok(Some(id)) => {
+            report(true, format!("Music folder has ID {}", id));
+            conf.music_folder_id = Some(id);
+        }
+        Ok(None) => report(true, "Using all music folders".to_string()),
+        Err(e) => report(false, e.to_string()),
+    }
+
+    match scan::current(&client, conf, api_ver).await {
+        Ok(s) if s.scanning => report(true, "Library scan in progress".to_string()),
+        Ok(_) => report(true, "Library isn't being scanned".to_string()),
+        Err(e) => report(false, format!("Couldn't get scan status: {}", e)),
+    }
+
+    match album::fetch_list(&client, conf, api_ver, &album::ListType::Random, 1, 0).await {
+        Ok(albums) if albums.is_empty() => report(false, "No albums found".to_string()),
+        Ok(_) => report(true, "Albums found".to_string()),
+        Err(e) => report(false, format!("Couldn't list albums: {}", e)),
+    }
+
+    match playlist::all(&client, conf, api_ver).await {
+        Ok(lists) => {
+            let exists = lists.iter().any(|p| p.name == conf.playlist_name);
+            report(
+                true,
+                format!(
+                    "{} playlists, {} called \"{}\"",
+                    lists.len(),
+                    if exists { "including one" } else { "none" },
+                    conf.playlist_name
+                ),
+            )
+        }
+        Err(e) => report(false, format!("Couldn't list playlists: {}", e)),
+    }
+
+    healthy
+}
+
+/// An album by its ID, as "Artist - Album", or failing those by searching for it, in which case
+/// there has to be only the one.
+async fn resolve(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    query: &str,
+) -> Result<api::Album, Error> {
+    if let Some((artist, name)) = query.split_once(" - ")
+        && let Some(found) = select::find(client, conf, api_ver, artist, name).await?
+    {
+        return Ok(found);
+    }
+    if let Ok(found) = album::fetch(client, conf, api_ver, query.trim()).await {
+        return Ok(found);
+    }
+
+    let mut found = album::search(client, conf, api_ver, query.trim()).await?;
+    match found.len() {
+        0 => Err(Error::NoAlbum(query.to_string())),
+        1 => Ok(found.swap_remove(0)),
+        count => Err(Error::Ambiguous {
+            query: query.to_string(),
+            count,
+        }),
+    }
+}
+
+/// Record an album, found by resolve(), in GRAPLSUB_STATE_FILE so that it's never picked again.
+pub async fn ban(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    query: &str,
+) -> Result<(), Error> {
+    let path = conf.state_file.as_ref().ok_or(Error::NoBanList)?;
+    let found = resolve(client, conf, api_ver, query).await?;
+    let title = format!(
+        "{} - {}",
+        found.artist.as_deref().unwrap_or("Unknown artist"),
+        found.name.as_deref().unwrap_or(&found.id)
+    );
+
+    let mut state = state::State::load(path)?;
+    let identity = album::identity(&found);
+    if state
+        .banned
+        .iter()
+        .any(|b| b.id == found.id || b.identity == identity)
+    {
+        println!("{} is already banned.", title);
+        return Ok(());
+    }
+
+    state.banned.push(state::Ban {
+        id: found.id,
+        identity,
+        title: title.clone(),
+    });
+    state.save(path)?;
+    println!("Banned {}.", title);
+
+    Ok(())
+}
+
+/// Let an album be picked again. It can be given as it was banned, or as it was shown when it was
+/// (ignoring case), which doesn't need the server, or found by resolve() again.
+pub async fn unban(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    query: &str,
+) -> Result<(), Error> {
+    let path = conf.state_file.as_ref().ok_or(Error::NoBanList)?;
+    let mut state = state::State::load(path)?;
+
+    let pos = match state
+        .banned
+        .iter()
+        .position(|b| b.id == query.trim() || b.title.eq_ignore_ascii_case(query.trim()))
+    {
+        Some(p) => Some(p),
+        None => {
+            let found = resolve(client, conf, api_ver, query).await?;
+            let identity = album::identity(&found);
+            state
+                .banned
+                .iter()
+                .position(|b| b.id == found.id || b.identity == identity)
+        }
+    };
+    let Some(pos) = pos else {
+        return Err(Error::NotBanned(query.to_string()));
+    };
+
+    let ban = state.banned.remove(pos);
+    state.save(path)?;
+    println!("Unbanned {}.", ban.title);
+
+    Ok(())
+}
+
+/// Print a playlist, found by ID or name, as JSON that import() can read back.
+pub async fn export(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id_or_name: &str,
+) -> Result<(), Error> {
+    let found = playlist::find(client, conf, api_ver, id_or_name).await?;
+    let full = playlist::fetch(client, conf, api_ver, &found.id).await?;
+
+    // Can't fail: everything in a Playlist serialises.
+    println!("{}", serde_json::to_string_pretty(&full).unwrap());
+
+    Ok(())
+}
+
+/// Make a new playlist from the JSON written by export(), from a file or "-" for standard input.
+/// Its songs are added by ID, which only works on the same server (or a copy of it), unless
+/// `match_songs` is set, in which case each is looked for by what it is instead.
+pub async fn import(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    file: &str,
+    name: Option<&str>,
+    match_songs: bool,
+) -> Result<(), Error> {
+    let json = if file == "-" {
+        std::io::read_to_string(std::io::stdin())
+    } else {
+        std::fs::read_to_string(file)
+    }
+    .map_err(|source| Error::Read {
+        file: file.to_string(),
+        source,
+    })?;
+
+    let imported: api::Playlist = serde_json::from_str(&json)
+        .map_err(|e| format_serde_error::SerdeError::new(json.clone(), e))?;
+    let name = name.unwrap_or(&imported.name);
+
+    if playlist::all(client, conf, api_ver)
+        .await?
+        .iter()
+        .any(|p| p.name == name)
+    {
+        return Err(Error::Exists {
+            name: name.to_string(),
+        });
+    }
+
+    let entries = imported.entry.unwrap_or_default();
+    let mut songs: Vec<String> = Vec::new();
+
+    if match_songs {
+        for entry in &entries {
+            match song::find_same(client, conf, api_ver, entry).await? {
+                Some(found) => songs.push(found.id),
+                None => output::warn(format!(
+                    "Couldn't find {} – {} here. Leaving it out.",
+                    entry.artist.as_deref().unwrap_or("Unknown artist"),
+                    entry.title.as_deref().unwrap_or("Unknown song")
+                )),
+            }
+        }
+    } else {
+        songs.extend(entries.into_iter().map(|s| s.id));
+    }
+
+    let id = playlist::create_named(client, conf, api_ver, name).await?;
+    playlist::add_songs(client, conf, api_ver, &id, &songs).await?;
+
+    output::note(format!("Imported {} songs into \"{}\".", songs.len(), name));
+
+    Ok(())
+}
+
+/// The names of the user's playlists, for shell completion. Completion happens all the time,
+/// including when there's no configuration or no server, so any failure just means there are none.
+pub async fn playlist_names(globals: &cli::Globals) -> Vec<String> {
+    let Some(mut conf) = config::load(globals.config_file().as_deref())
+        .ok()
+        .and_then(|profiles| {
+            profiles.into_iter().find(|c| {
+                globals
+                    .profile()
+                    .is_none_or(|p| c.profile.as_deref() == Some(p))
+            })
+        })
+    else {
+        return Vec::new();
+    };
+    globals.apply(&mut conf);
+
+    let Ok((client, api_ver)) = system::find_server(&mut conf, api::API_VERSION).await else {
+        return Vec::new();
+    };
+
+    match playlist::all(&client, &conf, &api_ver).await {
+        Ok(playlists) => playlists.into_iter().map(|p| p.name).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Ask a yes/no question on the terminal. Anything other than yes is no, including there being no
+/// terminal to ask on.
+fn confirm(question: &str) -> bool {
+    if !std::io::stdin().is_terminal() {
+        output::warn(format!(
+            "{} Not asking as there's no terminal; use --yes.",
+            question
+        ));
+        return false;
+    }
+
+    eprint!("{} [y/N] ", question);
+    let _ = std::io::stderr().flush();
+
+    let mut answer = String::new();
+    if std::io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Shell-style wildcard matching, where '*' matches any run of characters and '?' any one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // Where to go back to if what follows the last '*' doesn't match.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((sp, sn)) = star {
+            // Let the '*' swallow one more character and try again.
+            p = sp + 1;
+            n = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Seconds as minutes and seconds, like "4:05".
+fn clock(secs: u32) -> String {
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// Print rows lined up in columns under a heading. The last column isn't padded.
+fn print_table(heading: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = heading.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let last = cells.len() - 1;
+        let padded: Vec<String> = cells
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                if i == last {
+                    c.to_string()
+                } else {
+                    format!("{:<width$}", c, width = widths[i])
+                }
+            })
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+
+    line(heading.to_vec());
+    for row in rows {
+        line(row.iter().map(String::as_str).collect());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{self, Server};
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("graplsub_*", "graplsub_2024-01-01"));
+        assert!(glob_match("graplsub_*", "graplsub_"));
+        assert!(!glob_match("graplsub_*", "my graplsub_1"));
+        assert!(glob_match("*mix*", "Friday mix 2"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(glob_match("*a*b", "xaxxab"));
+        assert!(!glob_match("*a*b", "xaxxa"));
+        assert!(glob_match("ü*", "über"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("", "x"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+    }
+
+    #[tokio::test]
+    async fn resolving_asks_for_exactly_what_was_typed() {
+        let server = Server::start(|call| {
+            let body = match call.endpoint.as_str() {
+                "search3" => json!({ "searchResult3": { "album": [{ "id": "10" }] } }),
+                _ => json!({ "status": "failed", "error": { "code": 70, "message": "Not found" } }),
+            };
+            (Duration::ZERO, body)
+        })
+        .await;
+        let conf = testing::conf(&[("GRAPLSUB_BASE_URL", &server.base_url)]);
+        let client = api::create_client(&conf).unwrap();
+
+        let found = resolve(&client, &conf, "1.16.1", " Simon & Garfunkel #1 ")
+            .await
+            .unwrap();
+
+        assert_eq!(found.id, "10");
+        let get = &server.calls_to("getAlbum")[0];
+        assert_eq!(get.all("id"), vec!["Simon & Garfunkel #1"]);
+        assert_eq!(get.get("Garfunkel #1"), None);
+        assert_eq!(
+            server.calls_to("search3")[0].all("query"),
+            vec!["Simon & Garfunkel #1"]
+        );
+    }
+}
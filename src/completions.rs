@@ -0,0 +1,59 @@
+use std::io;
+
+use clap::{CommandFactory, ValueEnum};
+use clap_complete::env::{Bash, EnvCompleter, Fish, Zsh};
+use clap_complete::{CompleteEnv, CompletionCandidate};
+
+use crate::cli;
+use crate::commands;
+
+// Shell completion, by clap_complete. The scripts that `graplsub completions` prints only hook the
+// shell up to graplsub itself, which is run again with COMPLETE set for each completion and works
+// out what could come next from the command line's definition in cli.rs. Playlist names are asked
+// for from the server.
+
+// The variable that tells graplsub it's being run to complete something.
+const VAR: &str = "COMPLETE";
+
+/// A shell that there's completion for.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Print the script that sets up completion for a shell.
+pub fn print(shell: Shell) -> io::Result<()> {
+    let completer: &dyn EnvCompleter = match shell {
+        Shell::Bash => &Bash,
+        Shell::Zsh => &Zsh,
+        Shell::Fish => &Fish,
+    };
+    completer.write_registration(VAR, "graplsub", "graplsub", "graplsub", &mut io::stdout())
+}
+
+/// If the shell is asking for completions, print them and exit. Otherwise, do nothing.
+pub fn complete() {
+    CompleteEnv::with_factory(cli::Cli::command)
+        .var(VAR)
+        .complete();
+}
+
+/// The names of the user's playlists. This is only from the environment and the config file, as
+/// the options on the command line being completed aren't to hand.
+pub fn playlists() -> Vec<CompletionCandidate> {
+    // Completion doesn't run inside main()'s runtime, so needs one of its own.
+    let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    else {
+        return Vec::new();
+    };
+
+    runtime
+        .block_on(commands::playlist_names(&cli::Globals::default()))
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
@@ -0,0 +1,196 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use futures_util::{StreamExt, stream};
+
+use crate::api;
+use crate::api::Client;
+use crate::config;
+use crate::date;
+use crate::output;
+
+// A contact sheet of the covers of the albums in the playlist, as an HTML page. The pictures are
+// in the page itself rather than linked, so it's one file that can be copied anywhere and opened
+// on anything, like a phone, without needing to be able to log in to the server.
+
+// How big to ask for the covers, in pixels. Plenty for a phone, and keeps the page small.
+const SIZE: u32 = 300;
+
+// How many covers to fetch at once.
+const IN_FLIGHT: usize = 4;
+
+/// The cover of each album as a "data:" URL, or None where there isn't one or it couldn't be had.
+/// Albums without covers are common enough that failing to get one is only mentioned with
+/// GRAPLSUB_VERBOSE.
+pub async fn fetch_all(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    albums: &[api::Album],
+) -> Vec<Option<String>> {
+    stream::iter(albums)
+        .map(|album| async move {
+            let id = album.cover_art.as_deref()?;
+            let url = format!(
+                "{}/rest/getCoverArt?u={}&f=json&v={}&c={}&id={}&size={}",
+                conf.base_url,
+                api::encode(&conf.user),
+                api_ver,
+                api::encode(&conf.client_name),
+                id,
+                SIZE
+            );
+
+            match api::get_binary(client, &url).await {
+                Ok(bytes) => image_type(&bytes)
+                    .map(|mime| format!("data:{};base64,{}", mime, BASE64.encode(&bytes))),
+                Err(e) => {
+                    if conf.verbose {
+                        output::note(format!("No cover for {}: {}", title(album), e));
+                    }
+                    None
+                }
+            }
+        })
+        .buffered(IN_FLIGHT)
+        .collect()
+        .await
+}
+
+/// Fetch the covers and write the page to `path`.
+pub async fn write(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    albums: &[api::Album],
+    path: &Path,
+) -> io::Result<()> {
+    let covers = fetch_all(client, conf, api_ver, albums).await;
+    let heading = format!("{}, {}", conf.playlist_name, date::iso_date(date::today()));
+
+    let mut body = String::new();
+    for (album, cover) in albums.iter().zip(&covers) {
+        body.push_str(&tile(conf, album, cover.as_deref()));
+    }
+
+    fs::write(path, page(&heading, &body))
+}
+
+/// One album: its cover, or a blank square if there isn't one, with what it is underneath. Linked
+/// to GRAPLSUB_ALBUM_LINK if that's set.
+pub fn tile(conf: &config::Config, album: &api::Album, cover: Option<&str>) -> String {
+    let picture = match cover {
+        Some(src) => format!("<img src=\"{}\" alt=\"\">", src),
+        None => "<div class=\"blank\"></div>".to_string(),
+    };
+    let picture = match &conf.album_link {
+        Some(link) => format!(
+            "<a href=\"{}\">{}</a>",
+            escape(&link.replace("{id}", &album.id)),
+            picture
+        ),
+        None => picture,
+    };
+
+    let mut caption = format!(
+        "<b>{}</b><br>{}",
+        escape(album.name.as_deref().unwrap_or(&album.id)),
+        escape(album.artist.as_deref().unwrap_or_default())
+    );
+    if let Some(year) = album.year.filter(|&y| y > 0) {
+        caption.push_str(&format!(" ({})", year));
+    }
+
+    format!(
+        "<figure>{}<figcaption>{}</figcaption></figure>\n",
+        picture, caption
+    )
+}
+
+/// A whole page around `body`. Covers in a "grid" div fit as many across as there's room for.
+pub fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 1em; background: #111; color: #eee; }}
+a {{ color: inherit; }}
+.grid {{
+  display: grid; gap: 1em; grid-template-columns: repeat(auto-fill, minmax(140px, 1fr));
+}}
+figure {{ margin: 0; font-size: 0.8em; }}
+img, .blank {{ width: 100%; aspect-ratio: 1; object-fit: cover; background: #333; }}
+th {{
+  text-align: left; vertical-align: top; padding-right: 1em; font-weight: normal; color: #aaa;
+}}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<div class=\"grid\">
+{body}</div>
+</body>
+</html>
+",
+        title = escape(title),
+        body = body
+    )
+}
+
+/// Make text safe to put in HTML, in an element or a quoted attribute.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// What an album is called, for messages.
+fn title(album: &api::Album) -> String {
+    format!(
+        "\"{}\" by {}",
+        album.name.as_deref().unwrap_or(&album.id),
+        album.artist.as_deref().unwrap_or("unknown")
+    )
+}
+
+/// The MIME type of a picture, going by its first few bytes, or None if it doesn't look like one.
+/// The response's Content-Type would do, but the server doesn't always set it properly.
+fn image_type(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'E',
+            b'B',
+            b'P',
+            ..,
+        ] => Some("image/webp"),
+        _ => None,
+    }
+}
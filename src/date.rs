@@ -0,0 +1,151 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Just enough date handling for comparing against the ISO 8601 timestamps the Subsonic API uses,
+// e.g. "2025-11-14T20:31:07.123Z". Only the date and the time to the second are read: fractions of
+// a second and any time zone offset are ignored, so every timestamp is taken to be UTC.
+
+/// Days since the Unix epoch, as of now.
+pub fn today() -> i64 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    (secs / 86_400) as i64
+}
+
+/// Convert days since the Unix epoch to a (year, month, day) civil date. This is Howard Hinnant's
+/// civil_from_days algorithm.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// Convert a (year, month, day) civil date to days since the Unix epoch. This is Howard Hinnant's
+/// days_from_civil algorithm, the reverse of civil_from_days().
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+/// Seconds since the Unix epoch of an API timestamp. Any time zone is ignored, which is close
+/// enough for measuring anything in days.
+pub fn timestamp_secs(timestamp: &str) -> Option<u64> {
+    let part = |from: usize, to: usize| timestamp.get(from..to)?.parse::<u32>().ok();
+    let days = days_from_civil(i64::from(part(0, 4)?), part(5, 7)?, part(8, 10)?);
+    let secs = part(11, 13)? * 3_600 + part(14, 16)? * 60 + part(17, 19)?;
+
+    u64::try_from(days * 86_400 + i64::from(secs)).ok()
+}
+
+/// A date as "YYYY-MM-DD", which sorts the same as the start of an API timestamp.
+pub fn iso_date(days: i64) -> String {
+    let (year, month, day) = civil_from_days(days);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// The date `days` days before today, as "YYYY-MM-DD".
+pub fn days_ago(days: u32) -> String {
+    iso_date(today() - i64::from(days))
+}
+
+/// The date `months` months before today, as "YYYY-MM-DD". Days past the 28th are taken as the
+/// 28th, so that the date always exists.
+pub fn months_ago(months: u32) -> String {
+    iso_date(months_before(today(), months))
+}
+
+/// Days since the Unix epoch of `months` months before `days`, going by months_ago()'s rules.
+fn months_before(days: i64, months: u32) -> i64 {
+    let (year, month, day) = civil_from_days(days);
+    let total = year * 12 + i64::from(month) - 1 - i64::from(months);
+    let month = (total.rem_euclid(12) + 1) as u32;
+
+    days_from_civil(total.div_euclid(12), month, day.min(28))
+}
+
+/// The "YYYY-MM-DD" date part of an API timestamp.
+pub fn date_part(timestamp: &str) -> &str {
+    timestamp.get(..10).unwrap_or(timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        // 2100 isn't a leap year, while 2000 was.
+        assert_eq!(
+            civil_from_days(days_from_civil(2100, 2, 28) + 1),
+            (2100, 3, 1)
+        );
+        assert_eq!(iso_date(20_406), "2025-11-14");
+
+        for days in -800_000..800_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(timestamp_secs("1970-01-01T00:00:00Z"), Some(0));
+        // Fractions of a second and time zone offsets make no difference.
+        for timestamp in [
+            "2025-11-14T20:31:07Z",
+            "2025-11-14T20:31:07.123Z",
+            "2025-11-14T20:31:07.123456+02:00",
+            "2025-11-14T20:31:07-05:30",
+            "2025-11-14T20:31:07",
+        ] {
+            assert_eq!(
+                timestamp_secs(timestamp),
+                Some(1_763_152_267),
+                "{}",
+                timestamp
+            );
+        }
+        assert_eq!(timestamp_secs("2025-11-14"), None);
+        assert_eq!(timestamp_secs("1969-12-31T23:59:59Z"), None);
+        assert_eq!(timestamp_secs("yesterday"), None);
+        assert_eq!(timestamp_secs(""), None);
+        assert_eq!(date_part("2025-11-14T20:31:07Z"), "2025-11-14");
+    }
+
+    #[test]
+    fn months_before_a_date() {
+        let months = |(year, month, day), months| {
+            iso_date(months_before(days_from_civil(year, month, day), months))
+        };
+        assert_eq!(months((2025, 11, 14), 0), "2025-11-14");
+        assert_eq!(months((2025, 11, 14), 6), "2025-05-14");
+        assert_eq!(months((2025, 11, 14), 11), "2024-12-14");
+        assert_eq!(months((2025, 1, 14), 1), "2024-12-14");
+        assert_eq!(months((2025, 11, 14), 36), "2022-11-14");
+        // Days past the 28th are taken as the 28th, leap year or not.
+        assert_eq!(months((2025, 3, 31), 1), "2025-02-28");
+        assert_eq!(months((2024, 3, 29), 1), "2024-02-28");
+        assert_eq!(months((2025, 5, 31), 1), "2025-04-28");
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Deserialize;
+
+use crate::album;
+use crate::api;
+use crate::api::Client;
+use crate::config;
+use crate::output;
+use crate::playlist;
+use crate::state;
+
+// Learning from what actually gets listened to, for GRAPLSUB_LEARN_FROM_PLAYS. Once a playlist is
+// built, the play counts of its songs are noted down for each album in it. When the next one is
+// built, they're read again: an album whose songs were hardly played while it was in the playlist
+// was skipped. Albums that keep being skipped get less and less likely to be picked, and playing
+// one puts it back to normal.
+//
+// The play counts come from the playlist itself rather than getAlbum, as the playlist is never
+// cached and has exactly the songs that went in.
+
+// How much less likely an album is to be picked for each playlist in a row it was skipped in.
+const SKIPPED_WEIGHT: f64 = 0.5;
+
+/// An album in a finished playlist, as it was when it went in.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Placed {
+    // From album::identity(), which is what skips are kept under.
+    pub identity: String,
+    // How many of its songs went in, and how many plays they had between them.
+    pub songs: usize,
+    pub plays: u64,
+}
+
+/// Note how much each album's songs had been played, from the playlist that's just been built.
+pub async fn record(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    playlist_id: &str,
+    albums: &[api::Album],
+) -> BTreeMap<String, Placed> {
+    let plays = match plays(client, conf, api_ver, playlist_id).await {
+        Ok(p) => p,
+        Err(e) => {
+            output::warn(format!("Couldn't note play counts to learn from: {}", e));
+            return BTreeMap::new();
+        }
+    };
+
+    albums
+        .iter()
+        .filter_map(|a| {
+            let (songs, plays) = plays.get(&a.id)?;
+            Some((
+                a.id.clone(),
+                Placed {
+                    identity: album::identity(a),
+                    songs: *songs,
+                    plays: *plays,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Compare the last playlist's play counts with what they are now, and keep track of which of its
+/// albums were skipped. Each playlist is only learnt from once. Anything going wrong only means
+/// there's nothing learnt this time.
+pub async fn learn(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    state: &mut state::State,
+) {
+    let Some(run) = state
+        .runs
+        .iter_mut()
+        .rev()
+        .find(|r| r.finished.is_some() && r.playlist_name == conf.playlist_name)
+    else {
+        return;
+    };
+    let Some(id) = run.playlist_id.clone() else {
+        return;
+    };
+    if run.placed.is_empty() {
+        return;
+    }
+
+    let now = match plays(client, conf, api_ver, &id).await {
+        Ok(p) => p,
+        Err(e) => {
+            output::warn(format!(
+                "Couldn't learn from the last playlist's plays: {}",
+                e
+            ));
+            return;
+        }
+    };
+
+    let placed = std::mem::take(&mut run.placed);
+    let mut skipped = 0;
+    for (album_id, before) in placed {
+        // Gone from the playlist, so there's nothing to go on.
+        let Some((_, plays)) = now.get(&album_id) else {
+            continue;
+        };
+
+        // Played if at least half its songs' worth of plays were had while it was in there.
+        if plays.saturating_sub(before.plays) * 2 >= before.songs as u64 {
+            state.skips.remove(&before.identity);
+        } else {
+            *state.skips.entry(before.identity).or_default() += 1;
+            skipped += 1;
+        }
+    }
+
+    if conf.verbose {
+        output::note(format!(
+            "{} albums in the last playlist went unplayed.",
+            skipped
+        ));
+    }
+}
+
+/// For each album in a playlist, how many of its songs are in there and how many plays they've had.
+async fn plays(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    playlist_id: &str,
+) -> Result<HashMap<String, (usize, u64)>, api::Error> {
+    let mut plays: HashMap<String, (usize, u64)> = HashMap::new();
+
+    for song in playlist::fetch(client, conf, api_ver, playlist_id)
+        .await?
+        .entry
+        .unwrap_or_default()
+    {
+        if let Some(album_id) = song.album_id {
+            let entry = plays.entry(album_id).or_default();
+            entry.0 += 1;
+            entry.1 += song.play_count.unwrap_or(0);
+        }
+    }
+
+    Ok(plays)
+}
+
+/// How many playlists in a row each album has been skipped in, by album::identity(), from
+/// GRAPLSUB_STATE_FILE. Failing to read it is only worth a warning.
+pub fn skips(conf: &config::Config) -> HashMap<String, u32> {
+    let Some(path) = &conf.state_file else {
+        return HashMap::new();
+    };
+
+    match state::State::load(path) {
+        Ok(state) => state.skips.into_iter().collect(),
+        Err(e) => {
+            output::warn(format!("Couldn't check for skipped albums: {}", e));
+            HashMap::new()
+        }
+    }
+}
+
+/// How much less likely an album is to be picked for having been skipped `times` in a row.
+pub fn weight(times: u32) -> f64 {
+    SKIPPED_WEIGHT.powi(times.try_into().unwrap_or(i32::MAX))
+}
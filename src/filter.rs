@@ -0,0 +1,468 @@
+use std::collections::HashSet;
+
+use crate::album;
+use crate::api;
+use crate::api::Client;
+use crate::config;
+use crate::config::{Compilations, Unrated};
+use crate::output;
+use crate::podcast;
+use crate::rules::Rules;
+use crate::starred;
+use crate::state;
+
+// Genres that mean an album isn't music, matched case-insensitively anywhere in the genre.
+const SPOKEN_WORD_GENRES: &[&str] = &[
+    "audiobook",
+    "audio book",
+    "hörbuch",
+    "hörspiel",
+    "podcast",
+    "spoken",
+];
+
+// Song media types that mean it isn't music.
+const SPOKEN_WORD_TYPES: &[&str] = &["audiobook", "podcast"];
+
+/// Hard rules about which albums may be picked at all, as opposed to the rest of the Bias which
+/// only makes them more or less likely.
+#[derive(Debug, Default)]
+pub struct Filter {
+    compilations: Compilations,
+    min_rating: Option<u8>,
+    max_rating: Option<u8>,
+    unrated: Unrated,
+    skip_spoken_word: bool,
+    // Lowercased podcast channel titles, for servers that mix podcasts in with albums.
+    podcast_titles: HashSet<String>,
+    rules: Option<Rules>,
+    // With GRAPLSUB_STARRED_ARTISTS_ONLY, the IDs of the starred artists.
+    starred_artists: Option<HashSet<String>>,
+    // The IDs and identities of albums banned with graplsub ban.
+    banned: HashSet<String>,
+}
+
+impl Filter {
+    pub async fn gather(client: &Client, conf: &config::Config, api_ver: &str) -> Filter {
+        let mut filter = Filter::offline(conf);
+
+        if filter.skip_spoken_word {
+            filter.podcast_titles = podcast::channel_titles(client, conf, api_ver).await;
+        }
+        if conf.starred_artists_only {
+            filter.starred_artists = starred_artists(client, conf, api_ver).await;
+        }
+
+        filter
+    }
+
+    /// The rules that don't need the server, which is all of them apart from knowing which
+    /// albums are really podcasts and which artists are starred.
+    pub fn offline(conf: &config::Config) -> Filter {
+        Filter {
+            compilations: conf.compilations,
+            min_rating: conf.min_rating,
+            max_rating: conf.max_rating,
+            unrated: conf.unrated,
+            skip_spoken_word: !conf.include_spoken_word,
+            podcast_titles: HashSet::new(),
+            rules: conf.rules.clone(),
+            starred_artists: None,
+            banned: banned(conf),
+        }
+    }
+
+    /// Whether any rules are set that are likely to rule some albums out. Skipping spoken word
+    /// only counts when the server has podcasts, as it's on by default and most libraries are
+    /// all music. The odd audiobook that gets drawn anyway is made up for by drawing again.
+    pub fn is_active(&self) -> bool {
+        self.compilations != Compilations::Include
+            || self.min_rating.is_some()
+            || self.max_rating.is_some()
+            || self.unrated != Unrated::Include
+            || (self.skip_spoken_word && !self.podcast_titles.is_empty())
+            || self.rules.is_some()
+            || self.starred_artists.is_some()
+            || !self.banned.is_empty()
+    }
+
+    pub fn allows(&self, album: &api::Album) -> bool {
+        let compilation_ok = match self.compilations {
+            Compilations::Include => true,
+            Compilations::Exclude => !is_compilation(album),
+            Compilations::Only => is_compilation(album),
+        };
+
+        compilation_ok
+            && self.rating_ok(album)
+            && !(self.skip_spoken_word && self.is_spoken_word(album))
+            && self.rules.as_ref().is_none_or(|r| r.allows(album))
+            && self
+                .starred_artists
+                .as_ref()
+                .is_none_or(|ids| album.artist_id.as_ref().is_some_and(|id| ids.contains(id)))
+            && !self.is_banned(album)
+    }
+
+    fn is_banned(&self, album: &api::Album) -> bool {
+        !self.banned.is_empty()
+            && (self.banned.contains(&album.id) || self.banned.contains(&album::identity(album)))
+    }
+
+    /// Whether an album's rating is within GRAPLSUB_MIN_RATING and GRAPLSUB_MAX_RATING. Servers
+    /// say 0 for an album that hasn't been rated, as well as leaving it out.
+    fn rating_ok(&self, album: &api::Album) -> bool {
+        match album.user_rating.filter(|&r| r > 0) {
+            Some(r) => {
+                self.min_rating.is_none_or(|min| r >= min)
+                    && self.max_rating.is_none_or(|max| r <= max)
+            }
+            None => self.unrated == Unrated::Include,
+        }
+    }
+
+    /// Audiobooks and podcasts make for a jarring random album playlist. There's no definitive way
+    /// to tell them apart from music at the album level, so go by genre and podcast channel name.
+    fn is_spoken_word(&self, album: &api::Album) -> bool {
+        let spoken_genre = |genre: &str| {
+            let genre = genre.to_lowercase();
+            SPOKEN_WORD_GENRES.iter().any(|g| genre.contains(g))
+        };
+
+        album.genre.as_deref().is_some_and(spoken_genre)
+            || album
+                .genres
+                .as_ref()
+                .is_some_and(|genres| genres.iter().any(|g| spoken_genre(&g.name)))
+            || album
+                .name
+                .as_ref()
+                .is_some_and(|n| self.podcast_titles.contains(&n.to_lowercase()))
+    }
+}
+
+/// The starred artists' IDs, for GRAPLSUB_STARRED_ARTISTS_ONLY. Restricting to no artists at all
+/// would leave nothing to pick, so if they can't be had, or there aren't any, that's only warned
+/// about and every artist is allowed.
+async fn starred_artists(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Option<HashSet<String>> {
+    match starred::artist_ids(client, conf, api_ver).await {
+        Ok(ids) if !ids.is_empty() => Some(ids),
+        Ok(_) => {
+            output::warn("GRAPLSUB_STARRED_ARTISTS_ONLY is set but no artists are starred.");
+            None
+        }
+        Err(e) => {
+            output::warn(format!("Couldn't get the starred artists: {}", e));
+            None
+        }
+    }
+}
+
+/// The albums banned in GRAPLSUB_STATE_FILE, by both ID and identity.
+fn banned(conf: &config::Config) -> HashSet<String> {
+    let Some(path) = &conf.state_file else {
+        return HashSet::new();
+    };
+
+    match state::State::load(path) {
+        Ok(state) => state
+            .banned
+            .into_iter()
+            .flat_map(|b| [b.id, b.identity])
+            .collect(),
+        Err(e) => {
+            output::warn(format!("Couldn't check for banned albums: {}", e));
+            HashSet::new()
+        }
+    }
+}
+
+/// Why an album isn't good enough to play, going by GRAPLSUB_MIN_BITRATE and
+/// GRAPLSUB_EXCLUDE_FORMATS, or None if it is. Album lists don't say anything about the songs, so
+/// unlike the rest of the filter this can only be asked once an album has been fetched.
+///
+/// The bitrate is averaged over the album, weighted by length, as a quiet track in a VBR file can
+/// come out low on its own. A single song in an excluded format rules out the whole album, as a
+/// mixed album is usually a partial rip.
+pub fn poor_quality(conf: &config::Config, album: &api::Album) -> Option<String> {
+    let songs = album.song.as_deref().unwrap_or_default();
+
+    if let Some(formats) = &conf.exclude_formats {
+        let excluded: Vec<String> = formats
+            .split(',')
+            .map(|f| f.trim().trim_start_matches('.').to_lowercase())
+            .filter(|f| !f.is_empty())
+            .collect();
+
+        if let Some(suffix) = songs
+            .iter()
+            .filter_map(|s| s.suffix.as_deref())
+            .find(|s| excluded.contains(&s.to_lowercase()))
+        {
+            return Some(format!("has {} files", suffix));
+        }
+    }
+
+    if let Some(min) = conf.min_bitrate {
+        // Songs the server doesn't give a bitrate or length for can't be judged either way.
+        let (kbits, secs) = songs
+            .iter()
+            .filter_map(|s| Some((u64::from(s.bit_rate?), u64::from(s.duration?))))
+            .fold((0, 0), |(k, t), (rate, secs)| (k + rate * secs, t + secs));
+
+        if secs > 0 && kbits / secs < u64::from(min) {
+            return Some(format!("averages {}kbps", kbits / secs));
+        }
+    }
+
+    None
+}
+
+/// A song's media type, lowercased, going by what the server says it is. Podcast episodes aren't
+/// always given a type, but are marked as podcasts.
+fn media_type(song: &api::Song) -> Option<String> {
+    match (&song.media_type, song.is_podcast) {
+        (Some(t), _) if !t.is_empty() => Some(t.to_lowercase()),
+        (_, Some(true)) => Some("podcast".to_string()),
+        _ => None,
+    }
+}
+
+/// Whether a song is of a media type that's wanted: one of GRAPLSUB_MEDIA_TYPES if that's set, or
+/// otherwise anything but audiobooks and podcasts unless GRAPLSUB_INCLUDE_SPOKEN_WORD is. Unlike
+/// going by genre this is certain, but only where the server says, so songs it doesn't say about
+/// are let through.
+pub fn wanted_media_type(conf: &config::Config, song: &api::Song) -> bool {
+    let Some(media_type) = media_type(song) else {
+        return true;
+    };
+
+    match &conf.media_types {
+        Some(types) => types
+            .split(',')
+            .any(|t| t.trim().eq_ignore_ascii_case(&media_type)),
+        None => conf.include_spoken_word || !SPOKEN_WORD_TYPES.contains(&media_type.as_str()),
+    }
+}
+
+/// Why an album isn't wanted going by its songs' media types, or None if it is. One unwanted song
+/// rules out the whole album, as one with an audiobook chapter in it isn't really music.
+pub fn unwanted_media(conf: &config::Config, album: &api::Album) -> Option<String> {
+    album
+        .song
+        .iter()
+        .flatten()
+        .find(|s| !wanted_media_type(conf, s))
+        .and_then(media_type)
+        .map(|t| format!("has {} tracks", t))
+}
+
+/// How many songs an album says it has and how many actually came back, if that's fewer. A
+/// mismatch usually means that the server's last scan went wrong part way through the album.
+pub fn missing_songs(album: &api::Album) -> Option<(u32, usize)> {
+    let declared = album.song_count?;
+    let found = album.song.as_ref().map_or(0, Vec::len);
+
+    (found < declared as usize).then_some((declared, found))
+}
+
+/// Servers have a few different ways of saying that an album is a compilation. OpenSubsonic ones
+/// like Navidrome can say so outright or list it as a release type; otherwise the best we can do
+/// is guess from the artist name.
+fn is_compilation(album: &api::Album) -> bool {
+    album.is_compilation == Some(true)
+        || album
+            .release_types
+            .as_ref()
+            .is_some_and(|types| types.iter().any(|t| t.eq_ignore_ascii_case("compilation")))
+        || album
+            .artist
+            .as_deref()
+            .is_some_and(|a| a.eq_ignore_ascii_case("various artists"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::conf;
+
+    fn album(fields: serde_json::Value) -> api::Album {
+        let mut album = json!({ "id": "1" });
+        album
+            .as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(album).unwrap()
+    }
+
+    #[test]
+    fn compilations() {
+        let compilations = [
+            album(json!({ "isCompilation": true })),
+            album(json!({ "releaseTypes": ["Album", "Compilation"] })),
+            album(json!({ "releaseTypes": "compilation" })),
+            album(json!({ "artist": "Various Artists" })),
+        ];
+        let others = [
+            album(json!({ "isCompilation": false, "artist": "Kate Bush" })),
+            album(json!({ "releaseTypes": ["Album"] })),
+            album(json!({})),
+        ];
+        assert!(compilations.iter().all(is_compilation));
+        assert!(!others.iter().any(is_compilation));
+
+        let allows = |setting, album| {
+            Filter::offline(&conf(&[("GRAPLSUB_COMPILATIONS", setting)])).allows(album)
+        };
+        assert!(allows("include", &compilations[0]) && allows("include", &others[0]));
+        assert!(!allows("exclude", &compilations[0]) && allows("exclude", &others[0]));
+        assert!(allows("only", &compilations[0]) && !allows("only", &others[0]));
+    }
+
+    #[test]
+    fn ratings() {
+        let rated = |stars: u8| album(json!({ "userRating": stars }));
+        let allows =
+            |vars: &[(&str, &str)], album: &api::Album| Filter::offline(&conf(vars)).allows(album);
+        let between = [("GRAPLSUB_MIN_RATING", "2"), ("GRAPLSUB_MAX_RATING", "4")];
+
+        assert!((1..=5).all(|stars| allows(&[], &rated(stars))));
+        assert!(!allows(&between, &rated(1)));
+        assert!(allows(&between, &rated(2)) && allows(&between, &rated(4)));
+        assert!(!allows(&between, &rated(5)));
+        assert!(!allows(&[("GRAPLSUB_MIN_RATING", "3")], &rated(2)));
+        assert!(allows(&[("GRAPLSUB_MAX_RATING", "3")], &rated(1)));
+    }
+
+    #[test]
+    fn unrated() {
+        // A rating of 0 is no rating, as well as there being none at all.
+        let unrated = [album(json!({})), album(json!({ "userRating": 0 }))];
+        let allows =
+            |vars: &[(&str, &str)], album: &api::Album| Filter::offline(&conf(vars)).allows(album);
+
+        for album in &unrated {
+            assert!(allows(&[], album));
+            assert!(allows(&[("GRAPLSUB_MIN_RATING", "4")], album));
+            assert!(allows(&[("GRAPLSUB_UNRATED", "include")], album));
+            assert!(!allows(&[("GRAPLSUB_UNRATED", "exclude")], album));
+            assert!(!allows(
+                &[
+                    ("GRAPLSUB_MIN_RATING", "1"),
+                    ("GRAPLSUB_UNRATED", "exclude")
+                ],
+                album
+            ));
+        }
+        assert!(allows(
+            &[("GRAPLSUB_UNRATED", "exclude")],
+            &album(json!({ "userRating": 1 }))
+        ));
+    }
+
+    #[test]
+    fn media_types() {
+        let song = |fields: serde_json::Value| -> api::Song {
+            let mut song = json!({ "id": "s" });
+            song.as_object_mut()
+                .unwrap()
+                .extend(fields.as_object().unwrap().clone());
+            serde_json::from_value(song).unwrap()
+        };
+        let music = song(json!({ "type": "music" }));
+        let audiobook = song(json!({ "type": "AudioBook" }));
+        // Podcast episodes aren't always given a type.
+        let podcast = song(json!({ "isPodcast": true }));
+        let unknown = song(json!({}));
+        let video = song(json!({ "type": "video" }));
+
+        let default = conf(&[]);
+        assert!(wanted_media_type(&default, &music));
+        assert!(!wanted_media_type(&default, &audiobook));
+        assert!(!wanted_media_type(&default, &podcast));
+        assert!(wanted_media_type(&default, &unknown));
+        assert!(wanted_media_type(&default, &video));
+
+        let spoken = conf(&[("GRAPLSUB_INCLUDE_SPOKEN_WORD", "true")]);
+        assert!(wanted_media_type(&spoken, &audiobook) && wanted_media_type(&spoken, &podcast));
+
+        let chosen = conf(&[("GRAPLSUB_MEDIA_TYPES", "Music, audiobook")]);
+        assert!(wanted_media_type(&chosen, &music));
+        assert!(wanted_media_type(&chosen, &audiobook));
+        assert!(!wanted_media_type(&chosen, &podcast));
+        assert!(!wanted_media_type(&chosen, &video));
+        assert!(wanted_media_type(&chosen, &unknown));
+
+        let album = album(json!({ "song": [
+            { "id": "1", "type": "music" },
+            { "id": "2", "type": "audiobook" },
+        ] }));
+        assert_eq!(
+            unwanted_media(&default, &album).as_deref(),
+            Some("has audiobook tracks")
+        );
+        assert_eq!(unwanted_media(&spoken, &album), None);
+    }
+
+    #[test]
+    fn poor_quality_albums() {
+        let album = album(json!({ "song": [
+            { "id": "1", "suffix": "FLAC", "bitRate": 900, "duration": 300 },
+            { "id": "2", "suffix": "mp3", "bitRate": 96, "duration": 60 },
+            { "id": "3", "suffix": "mp3" },
+        ] }));
+
+        assert_eq!(poor_quality(&conf(&[]), &album), None);
+
+        // Averaged by length: (900 * 300 + 96 * 60) / 360, leaving out the song with no bitrate.
+        let bitrate = |min| poor_quality(&conf(&[("GRAPLSUB_MIN_BITRATE", min)]), &album);
+        assert_eq!(bitrate("767"), Some("averages 766kbps".to_string()));
+        assert_eq!(bitrate("766"), None);
+
+        let formats =
+            |formats| poor_quality(&conf(&[("GRAPLSUB_EXCLUDE_FORMATS", formats)]), &album);
+        assert_eq!(formats(".MP3, ogg"), Some("has mp3 files".to_string()));
+        assert_eq!(formats("flac"), Some("has FLAC files".to_string()));
+        assert_eq!(formats("ogg,,opus"), None);
+    }
+
+    #[test]
+    fn spoken_word() {
+        let mut filter = Filter::offline(&conf(&[]));
+        filter.podcast_titles = HashSet::from(["in our time".to_string()]);
+        let spoken = [
+            album(json!({ "genre": "Audiobook" })),
+            album(json!({ "genre": "Hörbuch & Hörspiel" })),
+            album(json!({ "genres": [{ "name": "Rock" }, { "name": "Spoken Word" }] })),
+            album(json!({ "name": "In Our Time" })),
+        ];
+        let music = [
+            album(json!({ "genre": "Rock", "genres": [{ "name": "Art Pop" }] })),
+            album(json!({ "name": "In Our Time (Remastered)" })),
+            album(json!({})),
+        ];
+        assert!(!spoken.iter().any(|a| filter.allows(a)));
+        assert!(music.iter().all(|a| filter.allows(a)));
+
+        let filter = Filter::offline(&conf(&[("GRAPLSUB_INCLUDE_SPOKEN_WORD", "true")]));
+        assert!(spoken.iter().all(|a| filter.allows(a)));
+    }
+
+    #[test]
+    fn active_only_when_albums_are_likely_to_be_ruled_out() {
+        // Skipping spoken word is the default, but without podcasts there's little to skip.
+        let mut filter = Filter::offline(&conf(&[]));
+        assert!(!filter.is_active());
+        filter.podcast_titles.insert("in our time".to_string());
+        assert!(filter.is_active());
+        let filter = Filter::offline(&conf(&[("GRAPLSUB_INCLUDE_SPOKEN_WORD", "true")]));
+        assert!(!filter.is_active());
+        assert!(Filter::offline(&conf(&[("GRAPLSUB_MIN_RATING", "3")])).is_active());
+    }
+}
@@ -0,0 +1,113 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::api;
+use crate::output;
+
+// How long a hook has to decide on an album before it's taken as rejecting it.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Run the user's album hook command for one candidate album, which gets the album's details as
+/// JSON on its standard input. It accepts the album by exiting 0; any other exit status rejects it,
+/// as does taking longer than TIMEOUT, when it's killed. A hook that can't even be run rejects
+/// everything, with a warning each time, as the user presumably wanted some albums kept out.
+pub async fn accepts(command: &str, album: &api::Album) -> bool {
+    run(command, album, TIMEOUT).await
+}
+
+async fn run(command: &str, album: &api::Album, timeout: Duration) -> bool {
+    let json = match serde_json::to_string(album) {
+        Ok(j) => j,
+        Err(e) => {
+            output::warn(format!(
+                "Couldn't serialise album {} for hook: {}",
+                album.id, e
+            ));
+            return false;
+        }
+    };
+
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("GRAPLSUB_ALBUM_ID", &album.id)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn();
+
+    let mut child = match child {
+        Ok(c) => c,
+        Err(e) => {
+            output::warn(format!("Couldn't run album hook: {}", e));
+            return false;
+        }
+    };
+
+    let stdin = child.stdin.take();
+    let decision = async {
+        // The hook might decide without reading all (or any) of its input, which shows up here as
+        // a broken pipe and doesn't matter. Its input is closed once written, so it sees the end.
+        if let Some(mut stdin) = stdin {
+            let _ = stdin.write_all(json.as_bytes()).await;
+        }
+        child.wait().await
+    };
+
+    match tokio::time::timeout(timeout, decision).await {
+        Ok(Ok(status)) => status.success(),
+        Ok(Err(e)) => {
+            output::warn(format!("Album hook failed: {}", e));
+            false
+        }
+        Err(_) => {
+            output::warn(format!(
+                "Album hook took more than {} seconds over album {}, so it was stopped.",
+                timeout.as_secs(),
+                album.id
+            ));
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn album() -> api::Album {
+        serde_json::from_value(json!({ "id": "al-1", "name": "Kind of Blue" })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn exit_status_decides() {
+        assert!(accepts("exit 0", &album()).await);
+        assert!(!accepts("exit 1", &album()).await);
+    }
+
+    #[tokio::test]
+    async fn gets_the_album() {
+        assert!(accepts("grep -q 'Kind of Blue'", &album()).await);
+        assert!(accepts("test \"$GRAPLSUB_ALBUM_ID\" = al-1", &album()).await);
+    }
+
+    #[tokio::test]
+    async fn need_not_read_its_input() {
+        assert!(accepts("exec 0<&-; exit 0", &album()).await);
+    }
+
+    #[tokio::test]
+    async fn too_slow_rejects() {
+        let start = Instant::now();
+
+        assert!(!run("sleep 10", &album(), Duration::from_millis(100)).await);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,109 @@
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::config;
+use crate::output;
+
+// Optional integration with Last.fm, for finding artists similar to the user's favourites. As with
+// ListenBrainz this has its own little client as it's nothing to do with the Subsonic server.
+
+const API_BASE: &str = "https://ws.audioscrobbler.com/2.0/";
+
+// How many similar artists to ask for per favourite.
+const LIMIT: u16 = 50;
+
+#[derive(Debug, Deserialize)]
+struct SimilarArtist {
+    name: String,
+    // How similar, from 0 to 1. Last.fm sends this as a string.
+    #[serde(rename(deserialize = "match"))]
+    similarity: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SimilarArtists {
+    artist: Vec<SimilarArtist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SimilarResponse {
+    #[serde(rename(deserialize = "similarartists"))]
+    similar_artists: SimilarArtists,
+}
+
+async fn similar(
+    client: &Client,
+    api_key: &str,
+    artist: &str,
+) -> Result<Vec<SimilarArtist>, reqwest::Error> {
+    let url = format!(
+        "{}?method=artist.getsimilar&artist={}&api_key={}&format=json&limit={}&autocorrect=1",
+        API_BASE,
+        form_urlencoded::byte_serialize(artist.as_bytes()).collect::<String>(),
+        api_key,
+        LIMIT
+    );
+
+    let response: SimilarResponse = client
+        .get(&url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(response.similar_artists.artist)
+}
+
+/// Artists similar to the favourites, lowercased, with how similar they are from 0 to 1. The
+/// favourites themselves are included with a similarity of 1. Failures are only worth a warning
+/// as the playlist can still be made without this.
+pub async fn similar_to_favourites(
+    conf: &config::Config,
+    api_key: &str,
+    favourites: &str,
+) -> HashMap<String, f64> {
+    let mut artists = HashMap::new();
+
+    let client = match Client::builder()
+        .timeout(Duration::from_secs(30))
+        .user_agent(&conf.user_agent)
+        .build()
+    {
+        Ok(c) => c,
+        Err(e) => {
+            output::warn(format!(
+                "Couldn't create Last.fm client, not using it: {}",
+                e
+            ));
+            return artists;
+        }
+    };
+
+    for favourite in favourites
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+    {
+        artists.insert(favourite.to_lowercase(), 1.0);
+
+        match similar(&client, api_key, favourite).await {
+            Ok(similar) => {
+                for artist in similar {
+                    let similarity = artist.similarity.parse().unwrap_or(0.0);
+                    let entry = artists.entry(artist.name.to_lowercase()).or_insert(0.0);
+                    // An artist similar to more than one favourite counts as the most similar.
+                    *entry = f64::max(*entry, similarity);
+                }
+            }
+            Err(e) => output::warn(format!(
+                "Couldn't get artists similar to \"{}\" from Last.fm: {}",
+                favourite, e
+            )),
+        }
+    }
+
+    artists
+}
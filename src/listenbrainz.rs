@@ -0,0 +1,191 @@
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::config;
+use crate::output;
+
+// Optional integration with ListenBrainz (https://listenbrainz.org/), for finding out what the user
+// has been listening to lately and what it recommends for them. This is nothing to do with the
+// Subsonic server so has its own little client.
+
+const API_BASE: &str = "https://api.listenbrainz.org/1";
+
+// How many recent listens and recommendations to look at.
+const COUNT: u16 = 100;
+
+#[derive(Debug, Deserialize)]
+struct TrackMetadata {
+    artist_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Listen {
+    track_metadata: TrackMetadata,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListensPayload {
+    listens: Vec<Listen>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListensResponse {
+    payload: ListensPayload,
+}
+
+#[derive(Debug, Deserialize)]
+struct Recommendation {
+    recording_mbid: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecommendationsPayload {
+    mbids: Vec<Recommendation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecommendationsResponse {
+    payload: RecommendationsPayload,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetadataArtist {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordingMetadata {
+    artist: Option<MetadataArtist>,
+}
+
+/// Artist names from ListenBrainz, all lowercased for matching against the library.
+#[derive(Debug, Default)]
+pub struct Artists {
+    /// Artists the user has listened to recently.
+    pub recent: HashSet<String>,
+    /// Artists of recordings ListenBrainz recommends.
+    pub recommended: HashSet<String>,
+}
+
+fn create_client(conf: &config::Config) -> Result<Client, reqwest::Error> {
+    Client::builder()
+        .timeout(Duration::from_secs(30))
+        .user_agent(&conf.user_agent)
+        .build()
+}
+
+async fn get<T: for<'de> Deserialize<'de>>(
+    client: &Client,
+    conf: &config::Config,
+    url: &str,
+) -> Result<Option<T>, reqwest::Error> {
+    let mut request = client.get(url);
+
+    if let Some(token) = &conf.listenbrainz_token {
+        request = request.header("Authorization", format!("Token {}", token));
+    }
+
+    let response = request.send().await?.error_for_status()?;
+
+    // ListenBrainz says 204 No Content when it has no recommendations yet.
+    if response.status() == reqwest::StatusCode::NO_CONTENT {
+        return Ok(None);
+    }
+
+    Ok(Some(response.json().await?))
+}
+
+async fn recent_artists(
+    client: &Client,
+    conf: &config::Config,
+    user: &str,
+) -> Result<HashSet<String>, reqwest::Error> {
+    let url = format!("{}/user/{}/listens?count={}", API_BASE, user, COUNT);
+    let listens: Option<ListensResponse> = get(client, conf, &url).await?;
+
+    Ok(listens
+        .map(|l| l.payload.listens)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|l| l.track_metadata.artist_name.to_lowercase())
+        .collect())
+}
+
+/// Recommendations only come as MusicBrainz recording IDs, so these need looking up again to find
+/// out who the artists are.
+async fn recommended_artists(
+    client: &Client,
+    conf: &config::Config,
+    user: &str,
+) -> Result<HashSet<String>, reqwest::Error> {
+    let url = format!(
+        "{}/cf/recommendation/user/{}/recording?count={}",
+        API_BASE, user, COUNT
+    );
+    let recommendations: Option<RecommendationsResponse> = get(client, conf, &url).await?;
+    let mbids: Vec<String> = recommendations
+        .map(|r| r.payload.mbids)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|r| r.recording_mbid)
+        .collect();
+
+    if mbids.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let url = format!(
+        "{}/metadata/recording/?recording_mbids={}&inc=artist",
+        API_BASE,
+        mbids.join(",")
+    );
+    let metadata: Option<HashMap<String, RecordingMetadata>> = get(client, conf, &url).await?;
+
+    Ok(metadata
+        .unwrap_or_default()
+        .into_values()
+        .filter_map(|m| m.artist.map(|a| a.name.to_lowercase()))
+        .collect())
+}
+
+/// What ListenBrainz knows about the user's taste. Any failure here is only worth a warning, as
+/// the playlist can still be made without it.
+pub async fn artists(conf: &config::Config, user: &str) -> Artists {
+    let client = match create_client(conf) {
+        Ok(c) => c,
+        Err(e) => {
+            output::warn(format!(
+                "Couldn't create ListenBrainz client, not using it: {}",
+                e
+            ));
+            return Artists::default();
+        }
+    };
+
+    let recent = recent_artists(&client, conf, user)
+        .await
+        .unwrap_or_else(|e| {
+            output::warn(format!(
+                "Couldn't get recent listens from ListenBrainz: {}",
+                e
+            ));
+            HashSet::new()
+        });
+
+    let recommended = recommended_artists(&client, conf, user)
+        .await
+        .unwrap_or_else(|e| {
+            output::warn(format!(
+                "Couldn't get recommendations from ListenBrainz: {}",
+                e
+            ));
+            HashSet::new()
+        });
+
+    Artists {
+        recent,
+        recommended,
+    }
+}
@@ -1,116 +1,1879 @@
+use std::collections::{BTreeMap, HashSet};
 use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::Parser;
+use futures_util::{StreamExt, future, stream};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::mpsc;
 
 mod album;
 mod api;
+mod artist;
+mod bias;
+mod bugreport;
+mod cache;
+mod changes;
+mod cli;
+mod commands;
+mod completions;
 mod config;
+mod covers;
+mod date;
+mod email;
+mod feedback;
+mod filter;
+mod hook;
+mod lastfm;
+mod listenbrainz;
+mod music_folder;
+mod notify;
+mod output;
 mod playlist;
+mod podcast;
+mod quirks;
+mod report;
+mod review;
+mod rules;
+mod scan;
+mod schedule;
+mod schema;
+mod script;
+mod select;
+mod similar;
+mod simulate;
+mod song;
+mod starred;
+mod state;
+mod stats;
+mod status;
+mod system;
+mod systemd;
+mod top_songs;
+
+#[cfg(test)]
+mod testing;
+
+// The exit status for a configuration problem, from sysexits.h. Trying again won't help, so a
+// systemd unit can list it in RestartPreventExitStatus=.
+const EX_CONFIG: u8 = 78;
+
+// The exit status when GRAPLSUB_MAX_RUNTIME runs out, the same as timeout(1) uses.
+const EX_TIMEOUT: u8 = 124;
+
+// How many albums to be fetching at once while songs are added to the playlist.
+const ALBUMS_IN_FLIGHT: usize = 4;
+
+fn main() -> ExitCode {
+    // When the shell is asking for completions, this prints them and exits.
+    completions::complete();
+
+    start(cli::Cli::parse())
+}
 
 #[tokio::main]
-async fn main() -> ExitCode {
-    let mut conf = envy::prefixed("GRAPLSUB_")
-        .from_env::<config::Config>()
-        .expect(
-            "Please provide all required env vars, minimum GRAPLSUB_PASS \
-            and GRAPLSUB_USER, but see also GRAPLSUB_BASE_URL, GRAPLSUB_NUM_ALBUMS \
-            and GRAPLSUB_PLAYLIST_NAME",
-        );
+async fn start(cli: cli::Cli) -> ExitCode {
+    let command = cli.command.unwrap_or_default();
+
+    // These don't need any configuration.
+    match command {
+        cli::Command::Completions { shell } => {
+            return match completions::print(shell) {
+                Ok(()) => ExitCode::from(0),
+                Err(e) => {
+                    output::error(format!("Couldn't print the completions: {}", e));
+                    ExitCode::from(1)
+                }
+            };
+        }
+        cli::Command::Config {
+            command: cli::ConfigCommand::Schema,
+        } => {
+            // Can't fail: it's made of JSON values to begin with.
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&schema::schema()).unwrap()
+            );
+            return ExitCode::from(0);
+        }
+        // Like the doctor, this wants to see what happens when the configuration is read.
+        cli::Command::Config {
+            command: cli::ConfigCommand::Check,
+        } => return check_config(&cli.globals),
+        _ => {}
+    }
 
-    // Generate a random salt and create a token from md5(password+salt).
-    config::build_secrets(&mut conf);
+    let mut profiles = match configure(&cli.globals) {
+        Ok(p) => p,
+        Err(e) => {
+            output::error(e);
+            return ExitCode::from(EX_CONFIG);
+        }
+    };
+
+    // There's only one log, so the first profile says where it goes.
+    output::log_to(profiles[0].log);
+
+    let api_ver = api::API_VERSION;
+
+    // The doctor wants to see what happens when it tries to find the server, so does so itself.
+    if command == cli::Command::Doctor {
+        let mut healthy = true;
+        for conf in &mut profiles {
+            if let Some(name) = &conf.profile {
+                println!("[{}]", name);
+            }
+            healthy &= commands::doctor(conf, api_ver).await;
+        }
+
+        return if healthy {
+            ExitCode::from(0)
+        } else {
+            ExitCode::from(1)
+        };
+    }
+
+    // Simulating doesn't go near the server at all.
+    if let cli::Command::Simulate { runs } = command {
+        let mut failed = false;
+        for conf in &profiles {
+            if let Some(name) = &conf.profile {
+                println!("[{}]", name);
+            }
+            if let Err(e) = simulate::run(conf, runs).await {
+                output::error(e);
+                failed = true;
+            }
+        }
+
+        return ExitCode::from(u8::from(failed));
+    }
+
+    // Building playlists is for every profile; anything else only makes sense for one.
+    match command {
+        cli::Command::Run {
+            interactive,
+            when,
+            report_html,
+            albums_from,
+        } => {
+            if let Some(file) = albums_from {
+                let read = if file == "-" {
+                    std::io::read_to_string(std::io::stdin())
+                } else {
+                    std::fs::read_to_string(&file)
+                };
+                let lines: Vec<String> = match read {
+                    Ok(text) => text.lines().map(String::from).collect(),
+                    Err(e) => {
+                        output::error(format!("Couldn't read {}: {}", file, e));
+                        return ExitCode::from(1);
+                    }
+                };
+                for conf in &mut profiles {
+                    conf.album_list = Some(lines.clone());
+                    // Random albums in place of ones that fail aren't what was asked for.
+                    conf.replace_failed_albums = false;
+                }
+            }
+            if let Some(path) = report_html {
+                let several = profiles.len() > 1;
+                for conf in &mut profiles {
+                    conf.report_html = Some(match &conf.profile {
+                        Some(name) if several => report::path_for(&path, name),
+                        _ => path.clone(),
+                    });
+                }
+            }
+            return run_profiles(profiles, interactive, when).await;
+        }
+        cli::Command::Daemon { every } => return daemon(profiles, &cli.globals, every).await,
+        cli::Command::Gc { dry_run } => return gc_profiles(profiles, dry_run).await,
+        cli::Command::Check { repair, replace } => {
+            return check_profiles(profiles, repair, replace).await;
+        }
+        _ => {}
+    }
+
+    if profiles.len() > 1 {
+        output::note(format!(
+            "Using the first profile, {}. Choose another with --profile.",
+            profiles[0].profile.as_deref().unwrap_or_default()
+        ));
+    }
+    let mut conf = profiles.swap_remove(0);
+
+    let (client, negotiated) = match system::find_server(&mut conf, api_ver).await {
+        Ok(c) => c,
+        Err(e) => {
+            output::error(format!("Failed to create HTTP client: {}", e));
+            return ExitCode::from(1);
+        }
+    };
+    let api_ver = negotiated.as_str();
+
+    let result = match command {
+        cli::Command::List { json } => commands::list(&client, &conf, api_ver, json).await,
+        cli::Command::Show { playlist, format } => {
+            commands::show(&client, &conf, api_ver, &playlist, &format.format()).await
+        }
+        cli::Command::Delete {
+            pattern,
+            dry_run,
+            yes,
+        } => commands::delete(&client, &conf, api_ver, &pattern, dry_run, yes).await,
+        cli::Command::Rename { playlist, name } => {
+            commands::rename(&client, &conf, api_ver, &playlist, &name).await
+        }
+        cli::Command::Ban { album } => commands::ban(&client, &conf, api_ver, &album).await,
+        cli::Command::Unban { album } => commands::unban(&client, &conf, api_ver, &album).await,
+        cli::Command::Export { playlist } => {
+            commands::export(&client, &conf, api_ver, &playlist).await
+        }
+        cli::Command::Import {
+            file,
+            name,
+            match_songs,
+        } => commands::import(&client, &conf, api_ver, &file, name.as_deref(), match_songs).await,
+        cli::Command::Completions { .. }
+        | cli::Command::Doctor
+        | cli::Command::Config { .. }
+        | cli::Command::Simulate { .. }
+        | cli::Command::Run { .. }
+        | cli::Command::Gc { .. }
+        | cli::Command::Check { .. }
+        | cli::Command::Daemon { .. } => unreachable!("handled before now"),
+    };
+
+    match result {
+        Ok(_) => ExitCode::from(0),
+        Err(e) => {
+            output::error(e);
+            ExitCode::from(1)
+        }
+    }
+}
+
+/// Check the configuration over without doing anything with it: that every GRAPLSUB_* variable in
+/// the environment and everything in the config file is a setting there is, and that each profile
+/// makes sense.
+fn check_config(globals: &cli::Globals) -> ExitCode {
+    let known = schema::variables();
+
+    let mut problems: Vec<String> = std::env::vars()
+        .filter(|(name, _)| name.starts_with("GRAPLSUB_"))
+        .filter_map(|(name, _)| schema::unknown(&known, &name))
+        .map(|p| format!("In the environment: {}", p))
+        .collect();
+    // The environment comes in no particular order.
+    problems.sort();
+
+    if let Some(path) = globals.config_file() {
+        match config::names_in(&path) {
+            Ok(names) => problems.extend(names.iter().filter_map(|(n, name)| {
+                schema::unknown(&known, name)
+                    .map(|p| format!("{} line {}: {}", path.display(), n, p))
+            })),
+            Err(e) => problems.push(e),
+        }
+    }
+
+    match configure(globals) {
+        Ok(profiles) if problems.is_empty() => {
+            match profiles.len() {
+                1 => println!("The settings are fine."),
+                n => println!("The settings of all {} profiles are fine.", n),
+            }
+            return ExitCode::from(0);
+        }
+        Ok(_) => {}
+        Err(e) => problems.push(e),
+    }
+
+    for problem in problems {
+        output::error(problem);
+    }
+    ExitCode::from(EX_CONFIG)
+}
+
+/// Read the configuration of each profile, or just the one asked for with --profile, let the
+/// command line override it and check it over.
+fn configure(globals: &cli::Globals) -> Result<Vec<config::Config>, String> {
+    let mut profiles = config::load(globals.config_file().as_deref()).map_err(|e| {
+        format!(
+            "{}. Please provide all required env vars, minimum GRAPLSUB_PASS \
+            and GRAPLSUB_USER, but see also GRAPLSUB_BASE_URL, GRAPLSUB_NUM_ALBUMS, \
+            GRAPLSUB_PLAYLIST_NAME and the other optional ones in the README",
+            e
+        )
+    })?;
+
+    // Before any are left out, so that their playlists don't look abandoned.
+    let everyone: Vec<(String, String, String)> = profiles
+        .iter()
+        .map(|c| (c.base_url.clone(), c.user.clone(), c.playlist_name.clone()))
+        .collect();
+    for conf in &mut profiles {
+        conf.siblings = everyone
+            .iter()
+            .filter(|(url, user, _)| *url == conf.base_url && *user == conf.user)
+            .map(|(_, _, name)| name.clone())
+            .collect();
+    }
+
+    if let Some(name) = globals.profile() {
+        profiles.retain(|c| c.profile.as_deref() == Some(name));
+        if profiles.is_empty() {
+            return Err(format!(
+                "There's no profile called {} in the config file",
+                name
+            ));
+        }
+    }
+
+    for conf in &mut profiles {
+        check(globals, conf).map_err(|e| match &conf.profile {
+            Some(p) => format!("Profile {}: {}", p, e),
+            None => e,
+        })?;
+    }
+
+    Ok(profiles)
+}
+
+/// Apply the command line to one profile's configuration and check it over.
+fn check(globals: &cli::Globals, conf: &mut config::Config) -> Result<(), String> {
+    // Anything given on the command line takes precedence.
+    globals.apply(conf);
+
+    match conf.auth_mode {
+        config::AuthMode::ApiKey if conf.api_key.is_none() => {
+            return Err("GRAPLSUB_AUTH_MODE is apikey but there's no GRAPLSUB_API_KEY".to_string());
+        }
+        config::AuthMode::Auto if conf.api_key.is_none() && conf.pass.is_empty() => {
+            return Err("GRAPLSUB_PASS isn't set (nor GRAPLSUB_API_KEY)".to_string());
+        }
+        config::AuthMode::Token | config::AuthMode::Password if conf.pass.is_empty() => {
+            return Err("GRAPLSUB_PASS isn't set".to_string());
+        }
+        _ => {}
+    }
 
     if conf.num_albums > 500 {
-        eprintln!(
+        output::warn(format!(
             "GRAPLSUB_NUM_ALBUMS too big ({}). Setting to 500.",
             conf.num_albums
-        );
+        ));
         conf.num_albums = 500;
     }
 
-    let api_ver: &'static str = "1.14.0";
+    if conf.salt_bytes < config::MIN_SALT_BYTES {
+        output::warn(format!(
+            "GRAPLSUB_SALT_BYTES too small ({}). Setting to {}.",
+            conf.salt_bytes,
+            config::MIN_SALT_BYTES
+        ));
+        conf.salt_bytes = config::MIN_SALT_BYTES;
+    }
 
-    let client = api::create_client().expect("Failed to create HTTP client");
+    if conf.show_changes && conf.state_file.is_none() {
+        output::warn("GRAPLSUB_SHOW_CHANGES needs GRAPLSUB_STATE_FILE to be set. Ignoring it.");
+    }
 
-    // First, check if a playlist with our desired name already exists, and delete it if it does.
-    // Then create a new one.
-    let playlist_id = match playlist::recreate(&client, &conf, api_ver).await {
-        Ok(id) => id,
+    if conf.learn_from_plays && conf.state_file.is_none() {
+        output::warn("GRAPLSUB_LEARN_FROM_PLAYS needs GRAPLSUB_STATE_FILE to be set. Ignoring it.");
+    }
+
+    if conf.include_weight.is_nan() || conf.include_weight < 0.0 {
+        return Err(format!(
+            "GRAPLSUB_INCLUDE_WEIGHT has to be 0 or more, not {}",
+            conf.include_weight
+        ));
+    }
+
+    if conf.mode == config::Mode::TopSongs && conf.top_artists.is_none() {
+        return Err("GRAPLSUB_MODE=topsongs needs GRAPLSUB_TOP_ARTISTS to be set".to_string());
+    }
+
+    for (name, rating) in [
+        ("GRAPLSUB_MIN_RATING", conf.min_rating),
+        ("GRAPLSUB_MAX_RATING", conf.max_rating),
+    ] {
+        if let Some(r) = rating
+            && !(1..=5).contains(&r)
+        {
+            return Err(format!("{} has to be from 1 to 5, not {}", name, r));
+        }
+    }
+    if let (Some(min), Some(max)) = (conf.min_rating, conf.max_rating)
+        && min > max
+    {
+        return Err(format!(
+            "GRAPLSUB_MIN_RATING ({}) is more than GRAPLSUB_MAX_RATING ({})",
+            min, max
+        ));
+    }
+
+    if conf.max_overlap_percent.is_some_and(|p| p > 100) {
+        return Err("GRAPLSUB_MAX_OVERLAP_PERCENT can't be more than 100".to_string());
+    }
+    if conf.max_overlap_percent.is_some() && conf.state_file.is_none() {
+        output::warn(
+            "GRAPLSUB_MAX_OVERLAP_PERCENT needs GRAPLSUB_STATE_FILE to be set. Ignoring it.",
+        );
+    }
+
+    if conf.mode == config::Mode::Pool {
+        match &conf.pool_playlist {
+            None => {
+                return Err("GRAPLSUB_MODE=pool needs GRAPLSUB_POOL_PLAYLIST to be set".to_string());
+            }
+            // Building it would throw away the pool.
+            Some(p) if *p == conf.playlist_name => {
+                return Err(
+                    "GRAPLSUB_POOL_PLAYLIST can't be the playlist graplsub builds".to_string(),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    if conf.top_songs_count == 0 {
+        return Err("GRAPLSUB_TOP_SONGS_COUNT has to be at least 1".to_string());
+    }
+
+    if conf.catch_up && (conf.schedule.is_none() || conf.state_file.is_none()) {
+        output::warn(
+            "GRAPLSUB_CATCH_UP needs GRAPLSUB_SCHEDULE and GRAPLSUB_STATE_FILE. Ignoring it.",
+        );
+    }
+
+    for (name, template) in [
+        ("GRAPLSUB_NOTIFY_TEMPLATE", &conf.notify_template),
+        ("GRAPLSUB_DISCORD_TEMPLATE", &conf.discord_template),
+        ("GRAPLSUB_SLACK_TEMPLATE", &conf.slack_template),
+        ("GRAPLSUB_MATRIX_TEMPLATE", &conf.matrix_template),
+    ] {
+        if let Some(t) = template {
+            notify::check_template(t).map_err(|e| format!("Bad {}: {}", name, e))?;
+        }
+    }
+
+    let matrix = [
+        &conf.matrix_homeserver,
+        &conf.matrix_room,
+        &conf.matrix_token,
+    ];
+    if matrix.iter().any(|m| m.is_some()) && !matrix.iter().all(|m| m.is_some()) {
+        output::warn(
+            "Matrix notifications need GRAPLSUB_MATRIX_HOMESERVER, GRAPLSUB_MATRIX_ROOM and \
+             GRAPLSUB_MATRIX_TOKEN. Not sending them.",
+        );
+    }
+
+    if conf.smtp_url.is_some() && (conf.email_from.is_none() || conf.email_to.is_none()) {
+        output::warn("Email needs GRAPLSUB_EMAIL_FROM and GRAPLSUB_EMAIL_TO. Not sending any.");
+        conf.smtp_url = None;
+    }
+
+    if conf.email_report && conf.report_html.is_none() {
+        output::warn("GRAPLSUB_EMAIL_REPORT needs a report to attach. Ignoring it.");
+    }
+
+    if conf.proxy_pass.is_some() && conf.proxy_user.is_none() {
+        output::warn("GRAPLSUB_PROXY_PASS is set without GRAPLSUB_PROXY_USER. Ignoring it.");
+    }
+
+    Ok(())
+}
+
+/// A profile's configuration, with a client for its server and the API version to speak to it.
+struct Profile {
+    conf: config::Config,
+    client: api::Client,
+    api_ver: String,
+}
+
+/// Find the server for a profile.
+async fn connect(mut conf: config::Config) -> Result<Profile, String> {
+    match system::find_server(&mut conf, api::API_VERSION).await {
+        Ok((client, api_ver)) => Ok(Profile {
+            conf,
+            client,
+            api_ver,
+        }),
+        Err(e) => Err(match &conf.profile {
+            Some(p) => format!("Failed to create HTTP client for profile {}: {}", p, e),
+            None => format!("Failed to create HTTP client: {}", e),
+        }),
+    }
+}
+
+/// Build every profile's playlist, all at once unless they're being reviewed interactively. One
+/// failing doesn't stop the rest, but does make for a failed exit status. Profiles whose
+/// GRAPLSUB_DAYS don't include any of `when`, which is today unless it says otherwise, are left
+/// out.
+async fn run_profiles(
+    mut profiles: Vec<config::Config>,
+    interactive: bool,
+    when: Option<schedule::Days>,
+) -> ExitCode {
+    let when = when.unwrap_or_else(schedule::Days::today);
+    profiles.retain(|c| c.days.is_none_or(|d| d.overlaps(&when)));
+    if profiles.is_empty() {
+        output::note("None of the profiles are for today.");
+        return ExitCode::from(0);
+    }
+
+    let build = |i: usize| {
+        let conf = profiles[i].clone();
+        output::for_profile(conf.profile.clone(), async move {
+            match connect(conf).await {
+                Ok(p) => run(&p.client, p.conf, &p.api_ver, interactive, state::run_id()).await,
+                Err(e) => {
+                    output::error(e);
+                    ExitCode::from(1)
+                }
+            }
+        })
+    };
+
+    let confs: Vec<&config::Config> = profiles.iter().collect();
+    let codes = if interactive {
+        let mut codes = Vec::new();
+        for i in 0..profiles.len() {
+            codes.push(build(i).await);
+        }
+        codes
+    } else {
+        concurrently(&confs, build).await
+    };
+
+    summarise(&confs, codes)
+}
+
+/// Delete stale playlists on the server of each profile, one after the other. Profiles that share
+/// a server and login have the same playlists, so only the first of them is needed.
+async fn gc_profiles(profiles: Vec<config::Config>, dry_run: bool) -> ExitCode {
+    let mut code = ExitCode::from(0);
+    let mut seen: Vec<(String, String)> = Vec::new();
+
+    for conf in profiles {
+        let server = (conf.base_url.clone(), conf.user.clone());
+        if seen.contains(&server) {
+            continue;
+        }
+        seen.push(server);
+
+        let ok = output::for_profile(conf.profile.clone(), async move {
+            match connect(conf).await {
+                Ok(p) => match commands::gc(&p.client, &p.conf, &p.api_ver, dry_run).await {
+                    Ok(_) => true,
+                    Err(e) => {
+                        output::error(e);
+                        false
+                    }
+                },
+                Err(e) => {
+                    output::error(e);
+                    false
+                }
+            }
+        })
+        .await;
+
+        if !ok {
+            code = ExitCode::from(1);
+        }
+    }
+
+    code
+}
+
+/// Check each profile's playlist against what its last run recorded, one after the other, and with
+/// `repair` put right what can be, with `replace` too. Fails if any of them isn't right by the end.
+async fn check_profiles(profiles: Vec<config::Config>, repair: bool, replace: bool) -> ExitCode {
+    let mut code = ExitCode::from(0);
+
+    for conf in profiles {
+        let ok = output::for_profile(conf.profile.clone(), async move {
+            match connect(conf).await {
+                Ok(p) => {
+                    match commands::check(&p.client, &p.conf, &p.api_ver, repair, replace).await {
+                        Ok(ok) => ok,
+                        Err(e) => {
+                            output::error(e);
+                            false
+                        }
+                    }
+                }
+                Err(e) => {
+                    output::error(e);
+                    false
+                }
+            }
+        })
+        .await;
+
+        if !ok {
+            code = ExitCode::from(1);
+        }
+    }
+
+    code
+}
+
+/// Call `f` with the index of each profile, all at once apart from profiles that share a
+/// GRAPLSUB_STATE_FILE, which take turns so as not to write over each other's records. Each has its
+/// own client, so one server being slow or throttled doesn't hold up the others. Returns what each
+/// call returned, in profile order.
+async fn concurrently<F, Fut>(profiles: &[&config::Config], f: F) -> Vec<ExitCode>
+where
+    F: Fn(usize) -> Fut,
+    Fut: Future<Output = ExitCode>,
+{
+    let mut lanes: Vec<Vec<usize>> = Vec::new();
+    for (i, conf) in profiles.iter().enumerate() {
+        let shared = conf.state_file.as_ref().and_then(|path| {
+            lanes
+                .iter()
+                .position(|l| profiles[l[0]].state_file.as_ref() == Some(path))
+        });
+        match shared {
+            Some(l) => lanes[l].push(i),
+            None => lanes.push(vec![i]),
+        }
+    }
+
+    let f = &f;
+    let mut codes: Vec<(usize, ExitCode)> =
+        future::join_all(lanes.into_iter().map(|lane| async move {
+            let mut codes = Vec::new();
+            for i in lane {
+                codes.push((i, f(i).await));
+            }
+            codes
+        }))
+        .await
+        .into_iter()
+        .flatten()
+        .collect();
+
+    codes.sort_by_key(|(i, _)| *i);
+    codes.into_iter().map(|(_, c)| c).collect()
+}
+
+/// Say how each profile got on, if there's more than one, and sum it up in one exit status: the
+/// first failure's, or success if there wasn't one.
+fn summarise(profiles: &[&config::Config], codes: Vec<ExitCode>) -> ExitCode {
+    let ok = ExitCode::from(0);
+
+    if profiles.len() > 1 {
+        for (conf, code) in profiles.iter().zip(&codes) {
+            let name = conf.profile.as_deref().unwrap_or_default();
+            if *code == ok {
+                output::note(format!("Profile {}: built the playlist.", name));
+            } else {
+                output::warn(format!("Profile {}: failed.", name));
+            }
+        }
+    }
+
+    codes.into_iter().find(|c| *c != ok).unwrap_or(ok)
+}
+
+/// Build the playlists over and over until told to stop. A run that fails is reported but doesn't
+/// stop the next one from being tried. Under systemd this tells it when we're ready and keeps its
+/// watchdog fed.
+async fn daemon(
+    profiles: Vec<config::Config>,
+    globals: &cli::Globals,
+    every: Duration,
+) -> ExitCode {
+    let mut profiles = match connect_all(profiles).await {
+        Ok(p) => p,
         Err(e) => {
-            eprintln!("{}", e);
+            output::error(e);
             return ExitCode::from(1);
         }
     };
 
-    // Get a random list of albums.
-    let (subsonic_response, json) = match album::random_list(&client, &conf, api_ver).await {
-        Ok(r) => r,
-        Err(e) => {
-            eprintln!("{}", e);
+    let (mut terminate, mut hangup) = match (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::hangup()),
+    ) {
+        (Ok(t), Ok(h)) => (t, h),
+        (Err(e), _) | (_, Err(e)) => {
+            output::error(format!("Couldn't listen for signals: {}", e));
             return ExitCode::from(1);
         }
     };
 
-    match album::check_list_response(&subsonic_response, &json) {
-        Ok(_) => {}
+    // The watchdog is fed on its own so that a long run doesn't starve it. If the whole process
+    // hangs then so does this.
+    if let Some(interval) = systemd::watchdog_interval() {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                systemd::notify("WATCHDOG=1");
+            }
+        });
+    }
+
+    // There's only one status server, so the first profile says where it goes.
+    let status = status::Shared::default();
+    if let Some(listen) = &profiles[0].conf.status_listen {
+        let addr = match listen.parse() {
+            Ok(a) => a,
+            Err(e) => {
+                output::error(format!("Bad GRAPLSUB_STATUS_LISTEN \"{}\": {}", listen, e));
+                return ExitCode::from(EX_CONFIG);
+            }
+        };
+        if let Err(e) = status::serve(addr, status.clone()).await {
+            output::error(format!("Couldn't serve status on {}: {}", addr, e));
+            return ExitCode::from(1);
+        }
+    }
+
+    systemd::notify("READY=1");
+
+    // When each profile is next due, in seconds since the Unix epoch.
+    let mut due: Vec<u64> = profiles
+        .iter()
+        .map(|p| first_due(&p.conf, state::now()))
+        .collect();
+
+    'runs: loop {
+        let started = state::now();
+
+        // Profiles whose GRAPLSUB_DAYS don't include today are passed over as if they'd been
+        // built.
+        let today = schedule::Days::today();
+        let (ready, idle): (Vec<usize>, Vec<usize>) = (0..profiles.len())
+            .filter(|&i| due[i] <= started)
+            .partition(|&i| profiles[i].conf.days.is_none_or(|d| d.overlaps(&today)));
+        for &i in &idle {
+            due[i] = next_due(&profiles[i].conf, started, every);
+        }
+
+        if !ready.is_empty() {
+            systemd::notify("STATUS=Building the playlist");
+
+            let (ok, ids) = tokio::select! {
+                built = build_each(&profiles, &ready) => built,
+                _ = terminate.recv() => break,
+            };
+
+            // Runs are every so often from the start of one to the start of the next, not the
+            // end.
+            for &i in &ready {
+                due[i] = next_due(&profiles[i].conf, started, every);
+            }
+            let next = due.iter().copied().min().unwrap_or(u64::MAX);
+
+            let finished = state::now();
+            status.lock().unwrap_or_else(|e| e.into_inner()).record(
+                status::LastRun {
+                    started,
+                    finished,
+                    ok,
+                    ids,
+                },
+                next,
+            );
+
+            let wait = stats::duration(next.saturating_sub(finished));
+            if ok {
+                systemd::notify(&format!("STATUS=Built the playlist. Next run in {}.", wait));
+            } else {
+                output::warn(format!("Run failed. Next run in {}.", wait));
+                systemd::notify(&format!("STATUS=Run failed. Next run in {}.", wait));
+            }
+        }
+
+        // A SIGHUP that came in during the run is picked up here, so runs always see one
+        // configuration from start to finish.
+        let next = due.iter().copied().min().unwrap_or(u64::MAX);
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(next.saturating_sub(state::now()))) => {}
+            _ = hangup.recv() => {
+                let old: Vec<(Option<String>, Option<schedule::Schedule>, u64)> = profiles
+                    .iter()
+                    .zip(&due)
+                    .map(|(p, &d)| (p.conf.profile.clone(), p.conf.schedule.clone(), d))
+                    .collect();
+                reload(globals, &mut profiles).await;
+
+                // Profiles that were there before and are on the same schedule carry on as they
+                // were. Anything else starts afresh.
+                due = profiles
+                    .iter()
+                    .map(|p| {
+                        old.iter()
+                            .find(|(name, schedule, _)| {
+                                *name == p.conf.profile && *schedule == p.conf.schedule
+                            })
+                            .map_or_else(|| first_due(&p.conf, state::now()), |(_, _, d)| *d)
+                    })
+                    .collect();
+            }
+            _ = terminate.recv() => break 'runs,
+        }
+    }
+
+    systemd::notify("STOPPING=1");
+    ExitCode::from(0)
+}
+
+/// When a profile should first be built after the daemon starts at `now`: straight away if it's
+/// built every so often, or else at the next time on its GRAPLSUB_SCHEDULE. With GRAPLSUB_CATCH_UP
+/// that's also straight away if a scheduled time went by since the state file says it was last
+/// built.
+fn first_due(conf: &config::Config, now: u64) -> u64 {
+    let Some(schedule) = &conf.schedule else {
+        return now;
+    };
+
+    let missed = conf.catch_up
+        && conf
+            .state_file
+            .as_ref()
+            .and_then(|path| state::State::load(path).ok())
+            .and_then(|s| s.last_started(conf))
+            .and_then(|last| schedule.next_after(last))
+            .is_some_and(|missed| missed <= now);
+
+    if missed {
+        output::note(format!(
+            "Catching up on a missed scheduled run of {}.",
+            conf.playlist_name
+        ));
+        now
+    } else {
+        scheduled(conf, schedule, now)
+    }
+}
+
+/// The next time on the schedule after `after`, plus a random part of GRAPLSUB_SCHEDULE_WINDOW so
+/// that lots of daemons on the same schedule don't all descend on a server at once.
+fn scheduled(conf: &config::Config, schedule: &schedule::Schedule, after: u64) -> u64 {
+    let window = conf.schedule_window.map_or(0, |w| w.as_secs());
+
+    schedule
+        .next_after(after)
+        .map_or(u64::MAX, |t| t + rand::random_range(0..=window))
+}
+
+/// When a profile should be built next, after a run that started at `started`.
+fn next_due(conf: &config::Config, started: u64, every: Duration) -> u64 {
+    match &conf.schedule {
+        Some(s) => scheduled(conf, s, started),
+        None => started + every.as_secs(),
+    }
+}
+
+/// Build the playlists of the profiles at the `ready` indexes at once, returning whether they all
+/// worked and the IDs their runs were given.
+async fn build_each(profiles: &[Profile], ready: &[usize]) -> (bool, Vec<String>) {
+    let confs: Vec<&config::Config> = ready.iter().map(|&i| &profiles[i].conf).collect();
+    let ids: Vec<String> = ready.iter().map(|_| state::run_id()).collect();
+    let codes = concurrently(&confs, |i| {
+        let p = &profiles[ready[i]];
+        output::for_profile(
+            p.conf.profile.clone(),
+            run(&p.client, p.conf.clone(), &p.api_ver, false, ids[i].clone()),
+        )
+    })
+    .await;
+
+    (summarise(&confs, codes) == ExitCode::from(0), ids)
+}
+
+/// Find the server for every profile, or fail if any can't be.
+async fn connect_all(profiles: Vec<config::Config>) -> Result<Vec<Profile>, String> {
+    let mut connected = Vec::new();
+    for conf in profiles {
+        connected.push(connect(conf).await?);
+    }
+    Ok(connected)
+}
+
+/// Re-read the configuration on SIGHUP. If the new one is no good, or no client can be made for a
+/// server it names, the old one is kept.
+async fn reload(globals: &cli::Globals, profiles: &mut Vec<Profile>) {
+    systemd::notify("RELOADING=1");
+
+    let new = match configure(globals) {
+        Ok(c) => c,
         Err(e) => {
-            eprintln!("{}", e);
+            output::warn(format!("Keeping the old configuration: {}", e));
+            systemd::notify("READY=1");
+            return;
+        }
+    };
+
+    // This always starts from the latest version, in case a new server is newer than the old one.
+    match connect_all(new).await {
+        Ok(p) => {
+            *profiles = p;
+            output::note("Reloaded the configuration.");
+        }
+        Err(e) => output::warn(format!("Keeping the old configuration: {}", e)),
+    }
+
+    systemd::notify("READY=1");
+}
+
+/// Build the playlist as run_in_time() does, as the run `id`. If that fails, write out the details
+/// for a bug report and send a notification saying why. Successful runs send their own
+/// notifications, as they know what's in the playlist.
+async fn run(
+    client: &api::Client,
+    mut conf: config::Config,
+    api_ver: &str,
+    interactive: bool,
+    id: String,
+) -> ExitCode {
+    conf.run_id = id.clone();
+
+    output::for_run(id, async {
+        if conf.verbose {
+            output::note(format!("Run {}.", conf.run_id));
+        }
+
+        let run_conf = conf.clone();
+        bugreport::scope(async {
+            let (code, error) =
+                output::with_last_error(run_in_time(client, run_conf, api_ver, interactive)).await;
+
+            if code != ExitCode::from(0) {
+                match bugreport::write(client, &conf, api_ver, error.as_deref()) {
+                    Ok(path) => output::note(format!(
+                        "Details for a bug report are in {}",
+                        path.display()
+                    )),
+                    Err(e) => output::warn(format!("Couldn't write the error report: {}", e)),
+                }
+                notify::send(&conf, &notify::Outcome::Failed(error)).await;
+            }
+
+            code
+        })
+        .await
+    })
+    .await
+}
+
+/// Build the playlist, first letting the user review the albums if `interactive` is set, giving up
+/// if it takes longer than GRAPLSUB_MAX_RUNTIME. Songs are added as they go, so giving up leaves a
+/// playlist of whatever was added by then. Interactive runs aren't limited, as most of the time
+/// would be the user's.
+async fn run_in_time(
+    client: &api::Client,
+    conf: config::Config,
+    api_ver: &str,
+    interactive: bool,
+) -> ExitCode {
+    let Some(limit) = conf.max_runtime.filter(|_| !interactive) else {
+        return build(client, conf, api_ver, interactive).await;
+    };
+    let state_file = conf.state_file.clone();
+
+    match tokio::time::timeout(limit, build(client, conf, api_ver, interactive)).await {
+        Ok(code) => code,
+        Err(_) => {
+            output::error(format!(
+                "Gave up after GRAPLSUB_MAX_RUNTIME ({}s). The playlist has only the songs added \
+                 by then.",
+                limit.as_secs()
+            ));
+
+            // Count it as finished, so that the next run starts afresh rather than resuming
+            // something that has already had its chance.
+            if let Some(path) = state_file
+                && let Ok(mut state) = state::State::load(&path)
+            {
+                if let Some(run) = state.current()
+                    && run.finished.is_none()
+                {
+                    run.finished = Some(state::now());
+                }
+                if let Err(e) = state.save(&path) {
+                    output::warn(e);
+                }
+            }
+
+            ExitCode::from(EX_TIMEOUT)
+        }
+    }
+}
+
+/// Build the playlist, first letting the user review the albums if `interactive` is set.
+async fn build(
+    client: &api::Client,
+    mut conf: config::Config,
+    api_ver: &str,
+    interactive: bool,
+) -> ExitCode {
+    // Load what's remembered from previous runs, if there's somewhere to keep it.
+    let mut state = match &conf.state_file {
+        Some(path) => match state::State::load(path) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                output::error(e);
+                return ExitCode::from(1);
+            }
+        },
+        None => None,
+    };
+
+    // Don't make the server turn down songs being added all over again.
+    if let Some(size) = state
+        .as_ref()
+        .and_then(|s| s.batch_sizes.get(&conf.base_url))
+    {
+        client.set_batch_size(*size);
+    }
+
+    // Work out which music folder to restrict the selection to, if any. This is done before
+    // touching the playlist so that a typo doesn't leave us with an empty one.
+    conf.music_folder_id = match music_folder::resolve(client, &conf, api_ver).await {
+        Ok(id) => id,
+        Err(e) => {
+            output::error(e);
             return ExitCode::from(1);
         }
+    };
+
+    if conf.scan_first {
+        match scan::start(client, &conf, api_ver).await {
+            Ok(_) => {}
+            Err(e) => {
+                output::error(e);
+                return ExitCode::from(1);
+            }
+        }
+    }
+
+    // There's no point starting a scan and then not waiting for it.
+    if conf.wait_for_scan || conf.scan_first {
+        match scan::wait_for_idle(client, &conf, api_ver).await {
+            Ok(_) => {}
+            Err(e) => {
+                output::error(e);
+                return ExitCode::from(1);
+            }
+        }
+    }
+
+    if let Some(state) = &mut state {
+        remove_leftovers(client, &conf, api_ver, state).await;
+    }
+
+    if conf.auto_gc {
+        match playlist::stale(client, &conf, api_ver).await {
+            Ok(stale) => {
+                for (p, why) in stale {
+                    match playlist::remove(client, &conf, api_ver, &p.id).await {
+                        Ok(_) => {
+                            output::note(format!("Removed playlist \"{}\" ({}).", p.name, why))
+                        }
+                        Err(e) => output::warn(format!("Couldn't remove a stale playlist: {}", e)),
+                    }
+                }
+            }
+            Err(e) => output::warn(format!("Couldn't look for stale playlists: {}", e)),
+        }
     }
 
-    // Safe to unwrap() album_list because we already checked it was Some(), but album can still be
-    // None.
-    if let Some(albums) = &subsonic_response
-        .subsonic_response
-        .album_list
-        .unwrap()
-        .album
+    // If the last run died part way through building this same playlist then pick up where it
+    // left off, rather than throwing away what it did.
+    let resumed = match state.as_ref().and_then(|s| s.unfinished(&conf)) {
+        Some((id, album_ids)) => match playlist::song_ids(client, &conf, api_ver, &id).await {
+            Ok(songs) => {
+                output::note(format!(
+                    "Resuming the previous run, which stopped after adding {} songs.",
+                    songs.len()
+                ));
+                Some((id, album_ids, songs.into_iter().collect()))
+            }
+            Err(e) => {
+                output::warn(format!(
+                    "Couldn't resume the previous run ({}). Starting again.",
+                    e
+                ));
+                None
+            }
+        },
+        None => None,
+    };
+
+    // Whether the playlist is being built under a temporary name, to be swapped in once it's
+    // complete. A resumed run carries on the way it started.
+    let staged = match &resumed {
+        Some(_) => state
+            .as_ref()
+            .and_then(|s| s.runs.last())
+            .is_some_and(|r| r.temporary.is_some()),
+        None => conf.staged_swap,
+    };
+
+    // IDs of every song added so far.
+    let (playlist_id, album_ids, mut added): (String, Vec<String>, HashSet<String>) = match resumed
     {
-        // Get the details of each album from the random list.
-        for album in albums {
-            let (subsonic_response, json) =
-                match album::get(&client, &conf, api_ver, &album.id).await {
-                    Ok(r) => r,
+        Some(r) => r,
+        None => {
+            // Before the old playlist goes, see what was made of it, in time for the selection.
+            if conf.learn_from_plays
+                && let Some(state) = &mut state
+            {
+                feedback::learn(client, &conf, api_ver, state).await;
+                save_state(&conf, state);
+            }
+
+            // Choose the albums before touching the playlist, so if that goes wrong the old one is
+            // left alone.
+            let albums = match select::albums(client, &conf, api_ver).await {
+                Ok(a) => a,
+                Err(e) => {
+                    output::error(e);
+                    return ExitCode::from(1);
+                }
+            };
+
+            // Don't repeat too much of the last playlist.
+            let last = state.as_ref().and_then(|s| s.last_finished(&conf));
+            let albums = match (conf.max_overlap_percent, last) {
+                (Some(max), Some(last)) => {
+                    match select::limit_overlap(client, &conf, api_ver, albums, &last.albums, max)
+                        .await
+                    {
+                        Ok(a) => a,
+                        Err(e) => {
+                            output::error(e);
+                            return ExitCode::from(1);
+                        }
+                    }
+                }
+                _ => albums,
+            };
+
+            // Modes that pick songs rather than albums have nothing to review.
+            let albums = if interactive && !albums.is_empty() {
+                // Another selection makes a reserve to swap albums for. Some modes pick the same
+                // albums every time, in which case there won't be much in it.
+                let reserve: Vec<api::Album> = match select::albums(client, &conf, api_ver).await {
+                    Ok(r) => r
+                        .into_iter()
+                        .filter(|r| !albums.iter().any(|a| a.id == r.id))
+                        .collect(),
                     Err(e) => {
-                        eprintln!("{}", e);
+                        output::error(e);
                         return ExitCode::from(1);
                     }
                 };
 
-            match album::check_get_response(&subsonic_response, &json) {
-                Ok(_) => {}
+                match review::albums(albums, reserve) {
+                    Ok(Some(a)) => a,
+                    Ok(None) => return ExitCode::from(0),
+                    Err(e) => {
+                        output::error(e);
+                        return ExitCode::from(1);
+                    }
+                }
+            } else {
+                albums
+            };
+            let album_ids: Vec<String> = albums.into_iter().map(|a| a.id).collect();
+
+            if let Some(state) = &mut state {
+                state.begin(&conf).albums = album_ids.clone();
+            }
+
+            let created = if staged {
+                let name = playlist::temporary_name();
+
+                // Record the name before making it, so that it can be found and tidied up even if
+                // the run dies straight after.
+                if let Some(state) = &mut state {
+                    if let Some(run) = state.current() {
+                        run.temporary = Some(name.clone());
+                    }
+                    save_state(&conf, state);
+                }
+
+                playlist::create_managed(client, &conf, api_ver, &name).await
+            } else {
+                // First, check if a playlist with our desired name already exists, and delete it
+                // if it does. Then create a new one.
+                playlist::recreate(client, &conf, api_ver).await
+            };
+
+            let playlist_id = match created {
+                Ok(id) => id,
                 Err(e) => {
-                    eprintln!("{}", e);
+                    output::error(e);
                     return ExitCode::from(1);
                 }
+            };
+
+            // Record the playlist straight away, so that the run can be resumed if it dies part
+            // way.
+            if let Some(state) = &mut state {
+                if let Some(run) = state.current() {
+                    run.playlist_id = Some(playlist_id.clone());
+                }
+                save_state(&conf, state);
             }
 
-            // Safe to unwrap() song because we already checked it was Some().
-            if let Some(songs) = &subsonic_response.subsonic_response.album.unwrap().song {
-                // For each song on that album, update our playlist to add that song's ID.
-                for song in songs {
-                    // eprintln!("Song: {}", song.id);
-                    let (subsonic_response, json) =
-                        match playlist::update(&client, &conf, api_ver, &playlist_id, &song.id)
-                            .await
-                        {
-                            Ok(s) => s,
-                            Err(e) => {
-                                eprintln!("{}", e);
-                                return ExitCode::from(1);
-                            }
-                        };
-
-                    match playlist::check_update_response(&subsonic_response, &json) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            eprintln!("{}", e);
-                            return ExitCode::from(1);
-                        }
-                    }
+            (playlist_id, album_ids, HashSet::new())
+        }
+    };
+
+    let mut dropped = Dropped::default();
+
+    // Get the details of each album that was chosen, adding their songs as they come in.
+    let mut albums = match add_albums(
+        client,
+        &conf,
+        api_ver,
+        &playlist_id,
+        &album_ids,
+        &mut added,
+        &mut dropped,
+    )
+    .await
+    {
+        Ok(a) => a,
+        Err(e) => {
+            output::error(e);
+            return ExitCode::from(1);
+        }
+    };
+
+    if conf.replace_failed_albums && dropped.count() > 0 {
+        // The playlist is usable without them, so failing to find any is only worth a warning.
+        let replacements: Vec<String> =
+            match select::replacements(client, &conf, api_ver, dropped.count(), &album_ids).await {
+                Ok(r) => r.into_iter().map(|a| a.id).collect(),
+                Err(e) => {
+                    output::warn(format!(
+                        "Couldn't find albums to replace dropped ones: {}",
+                        e
+                    ));
+                    Vec::new()
                 }
+            };
+
+        if let Some(state) = &mut state {
+            if let Some(run) = state.current() {
+                run.albums.extend(replacements.iter().cloned());
+            }
+            save_state(&conf, state);
+        }
+
+        match add_albums(
+            client,
+            &conf,
+            api_ver,
+            &playlist_id,
+            &replacements,
+            &mut added,
+            &mut dropped,
+        )
+        .await
+        {
+            Ok(a) => albums.extend(a),
+            Err(e) => {
+                output::error(e);
+                return ExitCode::from(1);
+            }
+        }
+    }
+
+    // Songs added other than as part of an album, for reporting: those chosen by modes that pick
+    // songs rather than albums, then any padding.
+    let mut padded: Vec<api::Song> = match select::songs(client, &conf, api_ver).await {
+        Ok(s) => s,
+        Err(e) => {
+            output::error(e);
+            return ExitCode::from(1);
+        }
+    };
+
+    // A resumed run will already have some of them.
+    let ids: Vec<String> = padded
+        .iter()
+        .map(|s| s.id.clone())
+        .filter(|id| !added.contains(id))
+        .collect();
+    if !ids.is_empty() {
+        match playlist::add_songs(client, &conf, api_ver, &playlist_id, &ids).await {
+            Ok(_) => {}
+            Err(e) => {
+                output::error(e);
+                return ExitCode::from(1);
+            }
+        }
+
+        added.extend(ids);
+    }
+
+    // Optionally top the playlist up with songs similar to what's already in it.
+    if let Some(target) = conf.pad_to_songs
+        && target > added.len()
+    {
+        let padding = match similar::padding(
+            client,
+            &conf,
+            api_ver,
+            &albums,
+            target - added.len(),
+            &added,
+        )
+        .await
+        {
+            Ok(s) => s,
+            Err(e) => {
+                output::error(e);
+                return ExitCode::from(1);
+            }
+        };
+
+        let ids: Vec<String> = padding.iter().map(|s| s.id.clone()).collect();
+        padded.extend(padding);
+
+        match playlist::add_songs(client, &conf, api_ver, &playlist_id, &ids).await {
+            Ok(_) => {}
+            Err(e) => {
+                output::error(e);
+                return ExitCode::from(1);
             }
         }
+
+        added.extend(ids);
+    }
+
+    if conf.show_stats {
+        stats::Stats::new(&albums, &padded).print();
+    }
+
+    // The playlist is done by now, so this going wrong isn't worth failing over.
+    if let Some(path) = &conf.covers_html
+        && let Err(e) = covers::write(client, &conf, api_ver, &albums, path).await
+    {
+        output::warn(format!("Couldn't write {}: {}", path.display(), e));
+    }
+
+    if let Some(path) = &conf.report_html {
+        let built = report::Playlist {
+            id: &playlist_id,
+            albums: &albums,
+            padded: &padded,
+            added: &added,
+        };
+        if let Err(e) = report::write(client, &conf, api_ver, &built, path).await {
+            output::warn(format!("Couldn't write {}: {}", path.display(), e));
+        }
+    }
+
+    if !dropped.problems.is_empty() {
+        output::warn(format!(
+            "Library problems found with {} albums:",
+            dropped.problems.len()
+        ));
+        for p in &dropped.problems {
+            output::warn(format!("  {}: {}", p.album, p.problem));
+        }
     }
 
+    if staged {
+        match playlist::swap_in(client, &conf, api_ver, &playlist_id).await {
+            Ok(_) => {}
+            Err(e) => {
+                output::error(e);
+                return ExitCode::from(1);
+            }
+        }
+    }
+
+    if let Some(state) = &mut state {
+        let placed = if conf.learn_from_plays {
+            feedback::record(client, &conf, api_ver, &playlist_id, &albums).await
+        } else {
+            BTreeMap::new()
+        };
+        if let Some(run) = state.current() {
+            run.finished = Some(state::now());
+            run.temporary = None;
+            run.songs = albums
+                .iter()
+                .flat_map(|a| a.song.iter().flatten())
+                .chain(&padded)
+                .filter(|s| added.contains(&s.id))
+                .map(|s| s.id.clone())
+                .collect();
+            run.identities = albums.iter().map(album::identity).collect();
+            run.problems = dropped.problems;
+            run.placed = placed;
+        }
+        if client.batch_size() < playlist::BATCH_SIZE {
+            state
+                .batch_sizes
+                .insert(conf.base_url.clone(), client.batch_size());
+        }
+        save_state(&conf, state);
+
+        if conf.show_changes {
+            match state.previous_run(&conf) {
+                Some(previous) => changes::print(&changes::between(previous, &albums)),
+                None => println!("No previous run to compare with."),
+            }
+        }
+    }
+
+    notify::send(&conf, &notify::Outcome::built(&albums, &padded)).await;
+
     ExitCode::from(0)
 }
+
+/// Delete temporary playlists left behind by GRAPLSUB_STAGED_SWAP runs that died before swapping
+/// them in, apart from one that's about to be resumed. They're found by the name recorded in the
+/// state file, which is unique, so nothing else can be caught up in it. Failing to is only a
+/// warning, and they'll be tried again next time.
+async fn remove_leftovers(
+    client: &api::Client,
+    conf: &config::Config,
+    api_ver: &str,
+    state: &mut state::State,
+) {
+    let resuming = state.unfinished(conf).is_some();
+    let last = state.runs.len().saturating_sub(1);
+    let leftovers: Vec<usize> = (0..state.runs.len())
+        .filter(|&i| state.runs[i].temporary.is_some() && !(resuming && i == last))
+        .collect();
+
+    if leftovers.is_empty() {
+        return;
+    }
+
+    let playlists = match playlist::all(client, conf, api_ver).await {
+        Ok(p) => p,
+        Err(e) => {
+            output::warn(format!(
+                "Couldn't look for leftover temporary playlists: {}",
+                e
+            ));
+            return;
+        }
+    };
+
+    for i in leftovers {
+        let run = &mut state.runs[i];
+        // Can't fail: only runs with one were picked.
+        let name = run.temporary.clone().unwrap();
+
+        let mut removed = true;
+        for p in playlists.iter().filter(|p| p.name == name) {
+            match playlist::remove(client, conf, api_ver, &p.id).await {
+                Ok(_) => output::note(format!("Removed leftover playlist \"{}\".", name)),
+                Err(e) => {
+                    output::warn(format!("Couldn't remove leftover playlist: {}", e));
+                    removed = false;
+                }
+            }
+        }
+
+        if removed {
+            run.temporary = None;
+        }
+    }
+
+    save_state(conf, state);
+}
+
+/// Albums that were chosen but didn't make it into the playlist, and what was wrong with them.
+#[derive(Default)]
+struct Dropped {
+    // Couldn't be fetched, when GRAPLSUB_ON_ALBUM_ERROR lets them be skipped.
+    failed: usize,
+    // Not up to GRAPLSUB_MIN_BITRATE or GRAPLSUB_EXCLUDE_FORMATS.
+    poor: usize,
+    // Missing songs, when GRAPLSUB_INCOMPLETE_ALBUMS is skip.
+    incomplete: usize,
+    // No songs at all.
+    empty: usize,
+    // Songs of a media type that isn't wanted, like audiobooks.
+    unwanted: usize,
+    // Turned down by GRAPLSUB_SCRIPT once its songs could be seen.
+    scripted: usize,
+    // Albums that couldn't be fetched or had songs missing, including incomplete ones that were
+    // added anyway. A library health check, of sorts.
+    problems: Vec<state::Problem>,
+}
+
+impl Dropped {
+    fn count(&self) -> usize {
+        self.failed + self.poor + self.incomplete + self.empty + self.unwanted + self.scripted
+    }
+
+    fn problem(&mut self, album: &str, problem: String) {
+        self.problems.push(state::Problem {
+            album: album.to_string(),
+            problem,
+        });
+    }
+}
+
+/// Fetch the albums and add their songs to the playlist, apart from any already in `added`. Albums
+/// are fetched a few at a time while songs are being added, rather than one after the other, which
+/// makes a big difference on a big playlist. Returns the full details of each album, for padding
+/// and reporting.
+///
+/// Songs still go in in album and track order, however the fetches finish. Adding to a playlist
+/// only ever appends, so two updates in flight at once could land either way round; instead
+/// fetches can finish in any order but are handed over in the order they were started, and only
+/// one update is ever in flight.
+///
+/// An album that can't be fetched is added to `dropped` and left out if GRAPLSUB_ON_ALBUM_ERROR
+/// allows, and otherwise fails the lot. So is one that turns out not to be good enough quality, or
+/// to be missing songs when GRAPLSUB_INCOMPLETE_ALBUMS says to skip those, or to have no songs, or
+/// songs of an unwanted media type, or to be turned down by GRAPLSUB_SCRIPT now that its songs can
+/// be seen.
+async fn add_albums(
+    client: &api::Client,
+    conf: &config::Config,
+    api_ver: &str,
+    playlist_id: &str,
+    album_ids: &[String],
+    added: &mut HashSet<String>,
+    dropped: &mut Dropped,
+) -> Result<Vec<api::Album>, api::Error> {
+    let (tx, mut rx) = mpsc::channel(ALBUMS_IN_FLIGHT);
+
+    let fetch = async move {
+        let mut fetched = stream::iter(album_ids.iter().enumerate())
+            .map(|(i, id)| async move {
+                let album = album::fetch(client, conf, api_ver, id)
+                    .await
+                    .map_err(|e| e.context(format!("album {} of {}", i + 1, album_ids.len())));
+                (id, album)
+            })
+            // Not buffer_unordered(): an album that comes back early waits for the ones before
+            // it, which is what keeps the playlist in order.
+            .buffered(ALBUMS_IN_FLIGHT);
+
+        while let Some(album) = fetched.next().await {
+            // The other end only goes away if adding songs failed, so there's no point going on.
+            if tx.send(album).await.is_err() {
+                break;
+            }
+        }
+    };
+
+    // This owns the receiving end so that it goes away as soon as adding fails, letting the
+    // fetching stop too.
+    let add = async move {
+        let mut albums: Vec<api::Album> = Vec::new();
+        // Songs waiting to go in, so that the playlist is updated a batch at a time rather than an
+        // album at a time.
+        let mut pending: Vec<String> = Vec::new();
+        // Every recording seen so far, for GRAPLSUB_DEDUPE_SONGS.
+        let mut recordings: HashSet<String> = HashSet::new();
+
+        while let Some((id, album)) = rx.recv().await {
+            let album = match album {
+                Ok(a) => a,
+                Err(e) if conf.on_album_error.tolerates(dropped.failed + 1) => {
+                    output::warn(format!("Skipping an album: {}", e));
+                    dropped.failed += 1;
+                    dropped.problem(id, format!("couldn't be fetched: {}", e.root()));
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let title = format!(
+                "{} – {}",
+                album.artist.as_deref().unwrap_or("Unknown artist"),
+                album.name.as_deref().unwrap_or("Unknown album")
+            );
+
+            if album.song.as_ref().is_none_or(Vec::is_empty) {
+                output::warn(format!("Leaving out {}, which has no songs", title));
+                dropped.empty += 1;
+                dropped.problem(id, format!("{} has no songs", title));
+                continue;
+            }
+
+            if let Some(reason) = filter::poor_quality(conf, &album) {
+                if conf.verbose {
+                    output::note(format!("Leaving out {}, which {}", title, reason));
+                }
+                dropped.poor += 1;
+                continue;
+            }
+
+            if let Some(reason) = filter::unwanted_media(conf, &album) {
+                if conf.verbose {
+                    output::note(format!("Leaving out {}, which {}", title, reason));
+                }
+                dropped.unwanted += 1;
+                continue;
+            }
+
+            if let Some(script) = &conf.script
+                && script.weight(&album) == 0.0
+            {
+                if conf.verbose {
+                    output::note(format!(
+                        "Leaving out {}, which GRAPLSUB_SCRIPT turned down",
+                        title
+                    ));
+                }
+                dropped.scripted += 1;
+                continue;
+            }
+
+            if let Some((declared, found)) = filter::missing_songs(&album)
+                .filter(|_| conf.incomplete_albums != config::IncompleteAlbums::Include)
+            {
+                let skip = conf.incomplete_albums == config::IncompleteAlbums::Skip;
+                output::warn(format!(
+                    "{} {} ({}), which should have {} songs but only {} were found. The server \
+                     might need to rescan it.",
+                    if skip { "Leaving out" } else { "Adding" },
+                    title,
+                    album.id,
+                    declared,
+                    found
+                ));
+                dropped.problem(
+                    id,
+                    format!("{} has {} of its {} songs", title, found, declared),
+                );
+                if skip {
+                    dropped.incomplete += 1;
+                    continue;
+                }
+            }
+
+            let new: Vec<String> = album
+                .song
+                .iter()
+                .flatten()
+                .filter(|song| {
+                    // Songs that are already in go on the record too, when resuming.
+                    let first = recordings.insert(song::recording(song));
+                    let wanted = first || !conf.dedupe_songs;
+
+                    if !wanted && conf.verbose && !added.contains(&song.id) {
+                        output::note(format!(
+                            "Leaving out {} – {}, which is already in",
+                            song.artist.as_deref().unwrap_or("Unknown artist"),
+                            song.title.as_deref().unwrap_or("Unknown song")
+                        ));
+                    }
+
+                    wanted && !added.contains(&song.id) && !pending.contains(&song.id)
+                })
+                .map(|song| song.id.clone())
+                .collect();
+
+            if conf.verbose {
+                output::note(format!(
+                    "Adding {} songs from {} – {}",
+                    new.len(),
+                    album.artist.as_deref().unwrap_or("Unknown artist"),
+                    album.name.as_deref().unwrap_or("Unknown album")
+                ));
+            }
+
+            pending.extend(new);
+            albums.push(album);
+
+            if pending.len() >= playlist::BATCH_SIZE {
+                playlist::add_songs(client, conf, api_ver, playlist_id, &pending).await?;
+                added.extend(pending.drain(..));
+            }
+        }
+
+        playlist::add_songs(client, conf, api_ver, playlist_id, &pending).await?;
+        added.extend(pending);
+
+        Ok::<_, api::Error>(albums)
+    };
+
+    let ((), albums) = tokio::join!(fetch, add);
+    albums
+}
+
+/// Failing to save state isn't worth abandoning a playlist over, so this only warns.
+fn save_state(conf: &config::Config, state: &mut state::State) {
+    if let Some(path) = &conf.state_file
+        && let Err(e) = state.save(path)
+    {
+        output::warn(e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{self, Server};
+
+    // Each album's songs are "ALBUM-1" up to "ALBUM-SONGS".
+    const SONGS: usize = 20;
+
+    fn songs(album: &str) -> Vec<String> {
+        (1..=SONGS).map(|n| format!("{}-{}", album, n)).collect()
+    }
+
+    /// A server whose albums come back in the opposite order to the one they're asked for in, as
+    /// the first ones take the longest.
+    async fn backwards(albums: usize) -> Server {
+        Server::start(move |call| match call.endpoint.as_str() {
+            "getAlbum" => {
+                let id: usize = call.get("id").unwrap().parse().unwrap();
+                let wait = Duration::from_millis(20 * (albums - id) as u64);
+                let songs: Vec<_> = songs(&id.to_string())
+                    .into_iter()
+                    .map(|s| json!({ "id": s }))
+                    .collect();
+                (
+                    wait,
+                    json!({ "album": { "id": id.to_string(), "song": songs } }),
+                )
+            }
+            _ => (Duration::ZERO, json!({})),
+        })
+        .await
+    }
+
+    /// The songs that went into the playlist, in the order they went in.
+    fn added_in_order(server: &Server) -> Vec<String> {
+        server
+            .calls_to("updatePlaylist")
+            .iter()
+            .flat_map(|c| c.all("songIdToAdd"))
+            .map(String::from)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn albums_go_in_in_order() {
+        let server = backwards(8).await;
+        let conf = testing::conf(&[("GRAPLSUB_BASE_URL", &server.base_url)]);
+        let client = api::create_client(&conf).unwrap();
+        let ids: Vec<String> = (0..8).map(|i| i.to_string()).collect();
+        let mut added = HashSet::new();
+
+        let albums = add_albums(
+            &client,
+            &conf,
+            "1.16.1",
+            "pl",
+            &ids,
+            &mut added,
+            &mut Dropped::default(),
+        )
+        .await
+        .unwrap();
+
+        let finished: Vec<&str> = albums.iter().map(|a| a.id.as_str()).collect();
+        let expected: Vec<String> = ids.iter().flat_map(|id| songs(id)).collect();
+
+        // They really were fetched at the same time, or there'd be nothing to prove.
+        assert!(server.most_at_once() > 1);
+        assert_eq!(finished, ids);
+        assert_eq!(added_in_order(&server), expected);
+        assert!(server.calls_to("updatePlaylist").len() > 1);
+        assert_eq!(added.len(), expected.len());
+    }
+
+    #[tokio::test]
+    async fn resuming_keeps_the_order() {
+        let server = backwards(4).await;
+        let conf = testing::conf(&[("GRAPLSUB_BASE_URL", &server.base_url)]);
+        let client = api::create_client(&conf).unwrap();
+        let ids: Vec<String> = (0..4).map(|i| i.to_string()).collect();
+        // As if it got as far as the first album and a half last time.
+        let mut added: HashSet<String> = songs("0")
+            .into_iter()
+            .chain(songs("1").into_iter().take(SONGS / 2))
+            .collect();
+
+        add_albums(
+            &client,
+            &conf,
+            "1.16.1",
+            "pl",
+            &ids,
+            &mut added,
+            &mut Dropped::default(),
+        )
+        .await
+        .unwrap();
+
+        let expected: Vec<String> = songs("1")
+            .into_iter()
+            .skip(SONGS / 2)
+            .chain(songs("2"))
+            .chain(songs("3"))
+            .collect();
+        assert_eq!(added_in_order(&server), expected);
+    }
+
+    #[test]
+    fn catching_up_only_after_a_missed_time() {
+        // Last built at 2024-03-01 05:00, and due again at 05:00 the next day.
+        let (last, due) = (1709269200, 1709355600);
+        let path = testing::dir().join("state.db");
+        let vars = [
+            ("GRAPLSUB_SCHEDULE", "0 5 * * *"),
+            ("GRAPLSUB_STATE_FILE", path.to_str().unwrap()),
+        ];
+        let conf = testing::conf(&vars);
+        let mut state = state::State::load(&path).unwrap();
+        state.begin(&conf).started = last;
+        state.save(&path).unwrap();
+
+        // Before the next time comes round there's nothing to catch up on.
+        assert_eq!(first_due(&conf, due - 3600), due);
+        // After, there is, but only with GRAPLSUB_CATCH_UP.
+        assert_eq!(first_due(&conf, due + 3600), due + 86400);
+        let catch_up = testing::conf(&[vars[0], vars[1], ("GRAPLSUB_CATCH_UP", "true")]);
+        assert_eq!(first_due(&catch_up, due - 3600), due);
+        assert_eq!(first_due(&catch_up, due + 3600), due + 3600);
+
+        // Nor is there with no run of this playlist on record.
+        let other = testing::conf(&[
+            vars[0],
+            vars[1],
+            ("GRAPLSUB_CATCH_UP", "true"),
+            ("GRAPLSUB_PLAYLIST_NAME", "other"),
+        ]);
+        assert_eq!(first_due(&other, due + 3600), due + 86400);
+    }
+
+    #[test]
+    fn scheduled_runs_are_spread_over_the_window() {
+        let schedule = schedule::Schedule::parse("0 4 * * *").unwrap();
+        // 2024-03-02 04:00.
+        let at = 1709352000;
+        let after = at - 3600;
+
+        assert_eq!(scheduled(&testing::conf(&[]), &schedule, after), at);
+
+        let conf = testing::conf(&[("GRAPLSUB_SCHEDULE_WINDOW", "2h")]);
+        let times: HashSet<u64> = (0..100)
+            .map(|_| scheduled(&conf, &schedule, after))
+            .collect();
+        assert!(times.iter().all(|t| (at..=at + 7200).contains(t)));
+        assert!(times.len() > 1);
+    }
+}
@@ -0,0 +1,69 @@
+use crate::api;
+use crate::api::Client;
+use crate::config;
+
+async fn list_all(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/getMusicFolders?u={}&f=json&v={}&c={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name)
+    );
+
+    api::get(client, &url).await
+}
+
+fn check_list_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
+    api::check_generic_response(resp)?;
+
+    if resp.subsonic_response.music_folders.is_none() {
+        return Err(api::RespParseError::MissingMusicFolders {
+            excerpt: resp.excerpt.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Work out the ID of the music folder named in GRAPLSUB_MUSIC_FOLDER, which can be either its ID or
+/// its name. An exact ID match wins over a name match in case some folder is named after another
+/// one's ID.
+pub async fn resolve(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<Option<String>, api::Error> {
+    let wanted = match &conf.music_folder {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+
+    let subsonic_response = list_all(client, conf, api_ver).await?;
+
+    check_list_response(&subsonic_response)?;
+
+    // Safe to unwrap() as this was already checked, though there might still be no folders in it.
+    let folders = subsonic_response
+        .subsonic_response
+        .music_folders
+        .unwrap()
+        .music_folder
+        .unwrap_or_default();
+
+    let found = folders
+        .iter()
+        .find(|f| &f.id == wanted)
+        .or_else(|| folders.iter().find(|f| f.name.as_ref() == Some(wanted)));
+
+    match found {
+        Some(f) => Ok(Some(f.id.clone())),
+        None => Err(api::Error::NotFound {
+            resource: format!("music folder \"{}\"", wanted),
+        }),
+    }
+}
@@ -0,0 +1,391 @@
+use std::time::Duration;
+
+use reqwest::{Client, RequestBuilder};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::api;
+use crate::config;
+use crate::config::NotifyOn;
+use crate::email;
+use crate::output;
+use crate::state;
+use crate::stats;
+
+// Announcing how a run went somewhere people will actually see it, like a Matrix room or a Discord
+// or Slack channel. Each service is a Notifier that knows how to turn a message into a request to
+// it; sending the requests and what to say are the same for all of them. Like Last.fm this has its
+// own little client, as it's nothing to do with the Subsonic server. Email is different enough to
+// have a module of its own, but is sent from here along with the rest.
+//
+// What's said can be changed with templates, in minijinja's flavour of Jinja, over a Context. The
+// message itself can be changed for all of them, and the whole body of the request for each of the
+// services that take JSON, for things like Discord's embeds.
+
+/// How a run went.
+pub enum Outcome {
+    /// The playlist was built. There's a one line summary of what's in it and then more, for email.
+    Built {
+        summary: String,
+        details: String,
+        songs: usize,
+        seconds: u64,
+        albums: Vec<Album>,
+    },
+    /// It wasn't, with the last error if there was one.
+    Failed(Option<String>),
+}
+
+/// An album in the playlist, for templates.
+#[derive(Serialize)]
+pub struct Album {
+    pub artist: Option<String>,
+    pub name: Option<String>,
+    pub year: Option<u32>,
+}
+
+impl Outcome {
+    /// A run that built the playlist, from the albums that went in and any songs added after them.
+    pub fn built(albums: &[api::Album], padded: &[api::Song]) -> Outcome {
+        let stats = stats::Stats::new(albums, padded);
+
+        let mut details: Vec<String> = stats
+            .breakdown()
+            .into_iter()
+            .map(|(label, text)| format!("{} {}", label, text))
+            .collect();
+        details.push(String::new());
+        details.push("Albums:".to_string());
+        for (i, album) in albums.iter().enumerate() {
+            details.push(format!("{:>4}. {}", i + 1, stats::describe(album)));
+        }
+
+        Outcome::Built {
+            summary: stats.summary(),
+            details: details.join("\n"),
+            songs: stats.song_count(),
+            seconds: stats.seconds(),
+            albums: albums
+                .iter()
+                .map(|a| Album {
+                    artist: a.artist.clone(),
+                    name: a.name.clone(),
+                    year: a.year.filter(|&y| y > 0),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// What templates have to work with.
+#[derive(Serialize)]
+pub struct Context<'a> {
+    pub playlist: &'a str,
+    pub profile: Option<&'a str>,
+    pub run_id: &'a str,
+    // Whether the playlist was built.
+    pub built: bool,
+    pub error: Option<&'a str>,
+    // The message that would have been sent without a template, or that was made by
+    // GRAPLSUB_NOTIFY_TEMPLATE, for the body templates.
+    pub message: String,
+    pub summary: Option<&'a str>,
+    pub details: Option<&'a str>,
+    pub songs: usize,
+    pub seconds: u64,
+    // Like "1d 2h 3m".
+    pub duration: String,
+    pub albums: &'a [Album],
+}
+
+impl<'a> Context<'a> {
+    fn new(conf: &'a config::Config, outcome: &'a Outcome) -> Context<'a> {
+        let mut context = Context {
+            playlist: &conf.playlist_name,
+            profile: conf.profile.as_deref(),
+            run_id: &conf.run_id,
+            built: false,
+            error: None,
+            message: message(conf, outcome),
+            summary: None,
+            details: None,
+            songs: 0,
+            seconds: 0,
+            duration: stats::duration(0),
+            albums: &[],
+        };
+
+        match outcome {
+            Outcome::Built {
+                summary,
+                details,
+                songs,
+                seconds,
+                albums,
+            } => {
+                context.built = true;
+                context.summary = Some(summary.as_str());
+                context.details = Some(details.as_str());
+                context.songs = *songs;
+                context.seconds = *seconds;
+                context.duration = stats::duration(*seconds);
+                context.albums = albums.as_slice();
+            }
+            Outcome::Failed(error) => context.error = error.as_deref(),
+        }
+
+        context
+    }
+}
+
+/// Fill in a template.
+pub fn render(template: &str, context: &Context) -> Result<String, minijinja::Error> {
+    minijinja::Environment::new().render_str(template, context)
+}
+
+/// Check that a template at least makes sense, so that a mistake in one is found straight away
+/// rather than after the first run.
+pub fn check_template(template: &str) -> Result<(), minijinja::Error> {
+    minijinja::Environment::new()
+        .template_from_str(template)
+        .map(|_| ())
+}
+
+/// Somewhere to send announcements.
+pub trait Notifier: Send + Sync {
+    /// What it is, for warnings.
+    fn name(&self) -> &'static str;
+
+    /// The template for the whole body of the request, if there is one.
+    fn template(&self) -> Option<&str>;
+
+    /// The request that posts `text`.
+    fn request(&self, client: &Client, text: &str) -> RequestBuilder;
+}
+
+/// A Matrix room, by the client-server API, as a user that's already in it.
+struct Matrix {
+    homeserver: String,
+    room: String,
+    token: String,
+    template: Option<String>,
+}
+
+impl Notifier for Matrix {
+    fn name(&self) -> &'static str {
+        "Matrix"
+    }
+
+    fn template(&self) -> Option<&str> {
+        self.template.as_deref()
+    }
+
+    fn request(&self, client: &Client, text: &str) -> RequestBuilder {
+        // Each message needs a transaction ID that's new to the homeserver, or it's taken as a
+        // repeat of an earlier one and dropped.
+        let txn = format!("graplsub.{}.{}", state::now(), rand::random::<u32>());
+        let url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            self.homeserver.trim_end_matches('/'),
+            form_urlencoded::byte_serialize(self.room.as_bytes()).collect::<String>(),
+            txn
+        );
+
+        client
+            .put(url)
+            .bearer_auth(&self.token)
+            .json(&json!({ "msgtype": "m.notice", "body": text }))
+    }
+}
+
+/// A Discord channel's webhook.
+struct Discord {
+    webhook: String,
+    template: Option<String>,
+}
+
+impl Notifier for Discord {
+    fn name(&self) -> &'static str {
+        "Discord"
+    }
+
+    fn template(&self) -> Option<&str> {
+        self.template.as_deref()
+    }
+
+    fn request(&self, client: &Client, text: &str) -> RequestBuilder {
+        client.post(&self.webhook).json(&json!({ "content": text }))
+    }
+}
+
+/// A Slack channel's incoming webhook.
+struct Slack {
+    webhook: String,
+    template: Option<String>,
+}
+
+impl Notifier for Slack {
+    fn name(&self) -> &'static str {
+        "Slack"
+    }
+
+    fn template(&self) -> Option<&str> {
+        self.template.as_deref()
+    }
+
+    fn request(&self, client: &Client, text: &str) -> RequestBuilder {
+        client.post(&self.webhook).json(&json!({ "text": text }))
+    }
+}
+
+/// Everywhere the profile says to send announcements.
+pub fn notifiers(conf: &config::Config) -> Vec<Box<dyn Notifier>> {
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+
+    if let (Some(homeserver), Some(room), Some(token)) = (
+        &conf.matrix_homeserver,
+        &conf.matrix_room,
+        &conf.matrix_token,
+    ) {
+        notifiers.push(Box::new(Matrix {
+            homeserver: homeserver.clone(),
+            room: room.clone(),
+            token: token.clone(),
+            template: conf.matrix_template.clone(),
+        }));
+    }
+    if let Some(webhook) = &conf.discord_webhook {
+        notifiers.push(Box::new(Discord {
+            webhook: webhook.clone(),
+            template: conf.discord_template.clone(),
+        }));
+    }
+    if let Some(webhook) = &conf.slack_webhook {
+        notifiers.push(Box::new(Slack {
+            webhook: webhook.clone(),
+            template: conf.slack_template.clone(),
+        }));
+    }
+
+    notifiers
+}
+
+/// The playlist, and the profile if there is one.
+fn playlist(conf: &config::Config) -> String {
+    match &conf.profile {
+        Some(p) => format!("\"{}\" ({})", conf.playlist_name, p),
+        None => format!("\"{}\"", conf.playlist_name),
+    }
+}
+
+/// What to say about how a run went, in a line.
+pub fn message(conf: &config::Config, outcome: &Outcome) -> String {
+    let name = playlist(conf);
+
+    match outcome {
+        Outcome::Built { summary, .. } => format!("Built {}. {}", name, summary),
+        Outcome::Failed(Some(error)) => format!("Couldn't build {}: {}", name, error),
+        Outcome::Failed(None) => format!("Couldn't build {}.", name),
+    }
+}
+
+/// Whether GRAPLSUB_NOTIFY_ON says to say anything about this outcome.
+pub fn wanted(conf: &config::Config, outcome: &Outcome) -> bool {
+    matches!(
+        (conf.notify_on, outcome),
+        (NotifyOn::Always, _)
+            | (NotifyOn::Success, Outcome::Built { .. })
+            | (NotifyOn::Failure, Outcome::Failed(_))
+    )
+}
+
+/// Tell everyone who wants to know how a run went. The playlist is what matters, so a notifier
+/// that doesn't work is only worth a warning.
+pub async fn send(conf: &config::Config, outcome: &Outcome) {
+    if !wanted(conf, outcome) {
+        return;
+    }
+
+    let mut context = Context::new(conf, outcome);
+    if let Some(template) = &conf.notify_template {
+        match render(template, &context) {
+            Ok(text) => context.message = text,
+            Err(e) => output::warn(format!("Couldn't use GRAPLSUB_NOTIFY_TEMPLATE: {}", e)),
+        }
+    }
+    let text = context.message.clone();
+
+    let notifiers = notifiers(conf);
+    if !notifiers.is_empty() {
+        post(conf, &notifiers, &context).await;
+    }
+
+    if let Some(url) = &conf.smtp_url {
+        let (subject, body) = match outcome {
+            Outcome::Built { details, .. } => (
+                format!("graplsub: built {}", playlist(conf)),
+                format!("{}\n\n{}\n\nRun: {}\n", text, details, conf.run_id),
+            ),
+            Outcome::Failed(_) => (
+                format!("graplsub: couldn't build {}", playlist(conf)),
+                format!("{}\n\nRun: {}\n", text, conf.run_id),
+            ),
+        };
+        // Only a report from this run is worth sending, and there's only one if it worked.
+        let report = match outcome {
+            Outcome::Built { .. } if conf.email_report => conf.report_html.as_deref(),
+            _ => None,
+        };
+
+        if let Err(e) = email::send(conf, url, &subject, &body, report).await {
+            output::warn(format!("Couldn't send email: {}", e));
+        }
+    }
+}
+
+/// Send the message to each of the notifiers, or what their templates make of it.
+async fn post(conf: &config::Config, notifiers: &[Box<dyn Notifier>], context: &Context<'_>) {
+    let client = match Client::builder()
+        .timeout(Duration::from_secs(30))
+        .user_agent(&conf.user_agent)
+        .build()
+    {
+        Ok(c) => c,
+        Err(e) => {
+            output::warn(format!("Couldn't create notification client: {}", e));
+            return;
+        }
+    };
+
+    for notifier in notifiers {
+        let mut request = notifier
+            .request(&client, &context.message)
+            .header("X-Graplsub-Run-Id", context.run_id);
+
+        // The template's JSON goes in place of the usual, keeping the rest of the request.
+        if let Some(template) = notifier.template() {
+            match render(template, context) {
+                Ok(body) => request = request.body(body),
+                Err(e) => {
+                    output::warn(format!(
+                        "Couldn't use the {} template: {}",
+                        notifier.name(),
+                        e
+                    ));
+                    continue;
+                }
+            }
+        }
+
+        let sent = request.send().await.and_then(|r| r.error_for_status());
+
+        // Webhook URLs are secrets, so they're kept out of the warning.
+        if let Err(e) = sent {
+            output::warn(format!(
+                "Couldn't notify {}: {}",
+                notifier.name(),
+                e.without_url()
+            ));
+        }
+    }
+}
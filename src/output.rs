@@ -0,0 +1,211 @@
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::os::unix::net::UnixDatagram;
+use std::sync::OnceLock;
+
+use crate::config::Log;
+
+// Colours for what's printed to the terminal. Colour is only used when the stream in question is
+// a terminal and NO_COLOR (https://no-color.org/) isn't set, so piped and logged output stays
+// plain.
+//
+// Errors, warnings and notes can go to syslog or the journal instead, for when we're run from cron
+// or a systemd unit and stderr would otherwise be lost or lumped together.
+
+// Where messages go. Until it's set, that's stderr.
+static LOG: OnceLock<Log> = OnceLock::new();
+
+tokio::task_local! {
+    // The profile whose playlist is being built, when there's more than one going at once.
+    static PROFILE: String;
+
+    // The ID of the run, for syslog and the journal.
+    static RUN_ID: String;
+
+    // The last error reported, for something that wants to pass it on.
+    static LAST_ERROR: RefCell<Option<String>>;
+}
+
+/// A way of painting some text.
+#[derive(Clone, Copy)]
+pub enum Style {
+    Bold,
+    Dim,
+    Green,
+    Red,
+    Yellow,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Bold => "1",
+            Style::Dim => "2",
+            Style::Green => "32",
+            Style::Red => "1;31",
+            Style::Yellow => "33",
+        }
+    }
+}
+
+/// Which stream some text is going to, as each may or may not be a terminal.
+#[derive(Clone, Copy)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn coloured(self) -> bool {
+        static STDOUT: OnceLock<bool> = OnceLock::new();
+        static STDERR: OnceLock<bool> = OnceLock::new();
+
+        match self {
+            Stream::Stdout => *STDOUT.get_or_init(|| wanted(std::io::stdout().is_terminal())),
+            Stream::Stderr => *STDERR.get_or_init(|| wanted(std::io::stderr().is_terminal())),
+        }
+    }
+}
+
+fn wanted(terminal: bool) -> bool {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let dumb = std::env::var_os("TERM").is_some_and(|t| t == "dumb");
+
+    terminal && !no_color && !dumb
+}
+
+/// The text painted in the style, if the stream is coloured at all.
+pub fn paint(stream: Stream, style: Style, text: impl Display) -> String {
+    if stream.coloured() {
+        format!("\x1b[{}m{}\x1b[0m", style.code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Run `future`, with every error, warning and note from it labelled with the profile's name if it
+/// has one, so that messages from profiles built at the same time can be told apart.
+pub async fn for_profile<F: Future>(profile: Option<String>, future: F) -> F::Output {
+    match profile {
+        Some(p) => PROFILE.scope(p, future).await,
+        None => future.await,
+    }
+}
+
+/// Run `future`, with every error, warning and note from it that goes to syslog or the journal
+/// tagged with the run's ID. On stderr it would only be clutter.
+pub async fn for_run<F: Future>(id: String, future: F) -> F::Output {
+    RUN_ID.scope(id, future).await
+}
+
+/// Run `future`, and return what it returns along with the last error that it reported, if any.
+pub async fn with_last_error<F: Future>(future: F) -> (F::Output, Option<String>) {
+    LAST_ERROR
+        .scope(RefCell::new(None), async {
+            let output = future.await;
+            (output, LAST_ERROR.with(RefCell::take))
+        })
+        .await
+}
+
+/// The message, labelled with the profile it's about if there is one.
+fn labelled(message: impl Display) -> String {
+    PROFILE
+        .try_with(|p| format!("[{}] {}", p, message))
+        .unwrap_or_else(|_| message.to_string())
+}
+
+/// Send messages to `log` from now on. Only the first call counts.
+pub fn log_to(log: Log) {
+    let _ = LOG.set(log);
+}
+
+/// Report something that stopped us.
+pub fn error(message: impl Display) {
+    let message = message.to_string();
+    let _ = LAST_ERROR.try_with(|e| e.replace(Some(message.clone())));
+    let message = labelled(message);
+    if !logged(Priority::Err, &message) {
+        eprintln!(
+            "{} {}",
+            paint(Stream::Stderr, Style::Red, "error:"),
+            message
+        );
+    }
+}
+
+/// Report something that went wrong but that we can carry on from.
+pub fn warn(message: impl Display) {
+    let message = labelled(message);
+    if !logged(Priority::Warning, &message) {
+        eprintln!(
+            "{} {}",
+            paint(Stream::Stderr, Style::Yellow, "warning:"),
+            message
+        );
+    }
+}
+
+/// Report progress that's worth knowing about but isn't a problem.
+pub fn note(message: impl Display) {
+    let message = labelled(message);
+    if !logged(Priority::Info, &message) {
+        eprintln!("{}", paint(Stream::Stderr, Style::Dim, message));
+    }
+}
+
+/// Syslog priorities, which the journal uses too.
+#[derive(Clone, Copy)]
+enum Priority {
+    Err = 3,
+    Warning = 4,
+    Info = 6,
+}
+
+// LOG_USER, as we're not a system daemon.
+const FACILITY: u8 = 1;
+
+/// Send the message to syslog or the journal, if that's where messages go. False if it still
+/// needs printing, either because they go to stderr or because sending it failed.
+fn logged(priority: Priority, message: &impl Display) -> bool {
+    let message = message.to_string();
+    let run_id = RUN_ID.try_with(String::clone).ok();
+    let (path, datagram) = match LOG.get() {
+        None | Some(Log::Stderr) => return false,
+        Some(Log::Syslog) => (
+            "/dev/log",
+            format!(
+                "<{}>graplsub[{}]: {}{}",
+                FACILITY * 8 + priority as u8,
+                std::process::id(),
+                run_id.map_or(String::new(), |id| format!("run {}: ", id)),
+                message
+            )
+            .into_bytes(),
+        ),
+        Some(Log::Journald) => {
+            let mut datagram =
+                format!("PRIORITY={}\nSYSLOG_IDENTIFIER=graplsub\n", priority as u8).into_bytes();
+            // Its own field, so that "journalctl GRAPLSUB_RUN_ID=..." finds everything from a run.
+            if let Some(id) = run_id {
+                datagram.extend(format!("GRAPLSUB_RUN_ID={}\n", id).as_bytes());
+            }
+            // The simple "KEY=value" form can't have a newline in the value, and some of our
+            // messages do. Those need the length-prefixed form instead.
+            if message.contains('\n') {
+                datagram.extend(b"MESSAGE\n");
+                datagram.extend((message.len() as u64).to_le_bytes());
+                datagram.extend(message.as_bytes());
+                datagram.push(b'\n');
+            } else {
+                datagram.extend(format!("MESSAGE={}\n", message).as_bytes());
+            }
+            ("/run/systemd/journal/socket", datagram)
+        }
+    };
+
+    UnixDatagram::unbound()
+        .and_then(|socket| socket.send_to(&datagram, path))
+        .is_ok()
+}
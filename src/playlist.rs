@@ -1,29 +1,53 @@
-use reqwest::Client;
+use std::collections::HashSet;
+use std::time::Duration;
 
 use crate::api;
+use crate::api::Client;
 use crate::config;
+use crate::date;
+use crate::output;
+use crate::state;
+
+// Most songs to add to a playlist in one request, unless the server turns out not to take that
+// many. See add_songs().
+pub const BATCH_SIZE: usize = 50;
+
+// How many times to try adding each batch of songs, and how long to wait before the first retry.
+// Later retries wait longer.
+const ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+// What the names of playlists being built with GRAPLSUB_STAGED_SWAP start with.
+pub const TEMPORARY_PREFIX: &str = "graplsub_tmp_";
+
+// The comment put on every playlist graplsub makes, so that "graplsub gc" can tell them apart from
+// anyone else's. The run's ID goes after it, when there is one.
+pub const MARKER: &str = "Made by graplsub";
 
 async fn list_all(
     client: &Client,
     conf: &config::Config,
     api_ver: &str,
-) -> Result<(api::TopLevel, String), api::Error> {
+) -> Result<api::TopLevel, api::Error> {
     let url = format!(
-        "{}/rest/getPlaylists?u={}&t={}&s={}&f=json&v={}&c=graplsub",
-        conf.base_url, conf.user, conf.md5_pass_salt, conf.salt, api_ver
+        "{}/rest/getPlaylists?u={}&f=json&v={}&c={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name)
     );
 
     api::get(client, &url).await
 }
 
-fn check_playlist_response(resp: &api::TopLevel, json: &str) -> Result<(), api::RespParseError> {
-    api::check_generic_response(resp, json)?;
+fn check_playlist_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
+    api::check_generic_response(resp)?;
 
     // I think we only need to check that resp.subsonic_response.playlists is not None as
     // everything else is enforced by the JSON structure.
     if resp.subsonic_response.playlists.is_none() {
         return Err(api::RespParseError::MissingPlaylists {
-            response: json.to_string(),
+            excerpt: resp.excerpt.clone(),
         });
     }
 
@@ -35,18 +59,39 @@ async fn delete(
     conf: &config::Config,
     api_ver: &str,
     id: &str,
-) -> Result<(api::TopLevel, String), api::Error> {
+) -> Result<api::TopLevel, api::Error> {
     let url = format!(
-        "{}/rest/deletePlaylist?u={}&t={}&s={}&f=json&v={}&c=graplsub&id={}",
-        conf.base_url, conf.user, conf.md5_pass_salt, conf.salt, api_ver, id
+        "{}/rest/deletePlaylist?u={}&f=json&v={}&c={}&id={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        id
     );
 
     api::get(client, &url).await
 }
 
-fn check_delete_response(resp: &api::TopLevel, json: &str) -> Result<(), api::RespParseError> {
+fn check_delete_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
     // An empty response is expected here so just do the basic checks.
-    api::check_generic_response(resp, json)?;
+    api::check_generic_response(resp)?;
+
+    Ok(())
+}
+
+/// Delete a playlist.
+pub async fn remove(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id: &str,
+) -> Result<(), api::Error> {
+    let context = |e: api::Error| e.context(format!("deleting playlist {}", id));
+    let subsonic_response = delete(client, conf, api_ver, id).await.map_err(context)?;
+
+    check_delete_response(&subsonic_response)
+        .map_err(api::Error::from)
+        .map_err(context)?;
 
     Ok(())
 }
@@ -55,37 +100,62 @@ async fn create(
     client: &Client,
     conf: &config::Config,
     api_ver: &str,
-) -> Result<(api::TopLevel, String), api::Error> {
+    name: &str,
+) -> Result<api::TopLevel, api::Error> {
     let url = format!(
-        "{}/rest/createPlaylist?u={}&t={}&s={}&f=json&v={}&c=graplsub&name={}",
-        conf.base_url, conf.user, conf.md5_pass_salt, conf.salt, api_ver, conf.playlist_name
+        "{}/rest/createPlaylist?u={}&f=json&v={}&c={}&name={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        api::encode(name)
     );
 
     api::get(client, &url).await
 }
 
-fn check_create_response(resp: &api::TopLevel, json: &str) -> Result<(), api::RespParseError> {
-    api::check_generic_response(resp, json)?;
+fn check_create_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
+    api::check_generic_response(resp)?;
 
     // I think we only need to check that resp.subsonic_response.playlist is not None as
     // everything else is enforced by the JSON structure.
     if resp.subsonic_response.playlist.is_none() {
         return Err(api::RespParseError::MissingPlaylist {
-            response: json.to_string(),
+            excerpt: resp.excerpt.clone(),
         });
     }
 
     Ok(())
 }
 
+/// All of the playlists that the user can see.
+pub async fn all(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<Vec<api::Playlist>, api::Error> {
+    let subsonic_response = list_all(client, conf, api_ver).await?;
+
+    check_playlist_response(&subsonic_response)?;
+
+    // Safe to unwrap() because we already checked it, but there's no "playlist" in it when there
+    // are no playlists.
+    Ok(subsonic_response
+        .subsonic_response
+        .playlists
+        .unwrap()
+        .playlist
+        .unwrap_or_default())
+}
+
 pub async fn recreate(
     client: &Client,
     conf: &config::Config,
     api_ver: &str,
 ) -> Result<String, api::Error> {
-    let (subsonic_response, json) = list_all(client, conf, api_ver).await?;
+    let subsonic_response = list_all(client, conf, api_ver).await?;
 
-    check_playlist_response(&subsonic_response, &json)?;
+    check_playlist_response(&subsonic_response)?;
 
     let mut my_list_id: Option<String> = None;
 
@@ -107,39 +177,586 @@ pub async fn recreate(
 
     if let Some(id) = my_list_id {
         // Our playlist did already exist, so delete it.
-        let (subsonic_response, json) = delete(client, conf, api_ver, &id).await?;
-
-        check_delete_response(&subsonic_response, &json)?;
+        remove(client, conf, api_ver, &id).await?;
     } else {
         // Our playlist did NOT already exist, so we can just go ahead and create it as new.
     }
 
-    let (subsonic_response, json) = create(client, conf, api_ver).await?;
+    create_managed(client, conf, api_ver, &conf.playlist_name).await
+}
+
+/// Make a new, empty playlist marked as one of graplsub's, and return its ID.
+pub async fn create_managed(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    name: &str,
+) -> Result<String, api::Error> {
+    let id = create_named(client, conf, api_ver, name).await?;
+
+    let context = |e: api::Error| e.context(format!("marking playlist \"{}\"", name));
+    let comment = match conf.run_id.as_str() {
+        "" => MARKER.to_string(),
+        run_id => format!("{}, run {}", MARKER, run_id),
+    };
+    let subsonic_response = set_comment(client, conf, api_ver, &id, &comment)
+        .await
+        .map_err(context)?;
+
+    check_update_response(&subsonic_response)
+        .map_err(api::Error::from)
+        .map_err(context)?;
+
+    Ok(id)
+}
+
+/// Whether graplsub made a playlist, going by its comment.
+pub fn is_managed(playlist: &api::Playlist) -> bool {
+    playlist
+        .comment
+        .as_deref()
+        .and_then(|c| c.strip_prefix(MARKER))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(", run "))
+}
+
+/// graplsub's own playlists that aren't wanted any more, and why: those that haven't changed for
+/// GRAPLSUB_GC_RETENTION, if that's set, and those that aren't the playlist of any profile using
+/// the same server and login. Temporary ones from GRAPLSUB_STAGED_SWAP only go by age, as one
+/// might be being built right now.
+pub async fn stale(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<Vec<(api::Playlist, String)>, api::Error> {
+    let now = state::now();
+
+    Ok(all(client, conf, api_ver)
+        .await?
+        .into_iter()
+        // Other people's can show up if they're public, but aren't ours to delete.
+        .filter(|p| {
+            is_managed(p)
+                && p.owner
+                    .as_deref()
+                    .is_none_or(|o| conf.user.is_empty() || o == conf.user)
+        })
+        .filter_map(|p| {
+            let age = p
+                .changed
+                .as_deref()
+                .and_then(date::timestamp_secs)
+                .map(|t| now.saturating_sub(t));
+
+            if let (Some(limit), Some(age)) = (conf.gc_retention, age)
+                && age > limit.as_secs()
+            {
+                let why = format!("unchanged for {} days", age / 86_400);
+                return Some((p, why));
+            }
+
+            let wanted = p.name == conf.playlist_name
+                || conf.siblings.contains(&p.name)
+                || p.name.starts_with(TEMPORARY_PREFIX);
+
+            (!wanted).then(|| (p, "not any profile's playlist".to_string()))
+        })
+        .collect())
+}
+
+/// A name to build a playlist under before swapping it in. It ends in a random (version 4) UUID so
+/// that it can't clash with anything, even another graplsub running at the same time.
+pub fn temporary_name() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+
+    format!(
+        "{}{}-{}-{}-{}-{}",
+        TEMPORARY_PREFIX,
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Put a playlist that was built under a temporary name in place of the real one. It's renamed
+/// before the old one is deleted, so there's never a moment without the playlist at all.
+pub async fn swap_in(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id: &str,
+) -> Result<(), api::Error> {
+    let old: Vec<String> = all(client, conf, api_ver)
+        .await?
+        .into_iter()
+        .filter(|p| p.name == conf.playlist_name && p.id != id)
+        .map(|p| p.id)
+        .collect();
+
+    rename(client, conf, api_ver, id, &conf.playlist_name).await?;
+
+    for old_id in old {
+        remove(client, conf, api_ver, &old_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Make a new, empty playlist and return its ID.
+pub async fn create_named(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    name: &str,
+) -> Result<String, api::Error> {
+    let context = |e: api::Error| e.context(format!("creating playlist \"{}\"", name));
+    let subsonic_response = create(client, conf, api_ver, name).await.map_err(context)?;
 
-    check_create_response(&subsonic_response, &json)?;
+    check_create_response(&subsonic_response)
+        .map_err(api::Error::from)
+        .map_err(context)?;
 
     // Safe to unwrap() because we already checked that it wasn't None.
     Ok(subsonic_response.subsonic_response.playlist.unwrap().id)
 }
 
-pub async fn update(
+async fn update(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    playlist_id: &str,
+    song_ids: &[String],
+) -> Result<api::TopLevel, api::Error> {
+    let mut url = format!(
+        "{}/rest/updatePlaylist?u={}&f=json&v={}&c={}&playlistId={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        playlist_id
+    );
+
+    for id in song_ids {
+        url.push_str("&songIdToAdd=");
+        url.push_str(id);
+    }
+
+    api::get(client, &url).await
+}
+
+async fn set_name(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    playlist_id: &str,
+    name: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/updatePlaylist?u={}&f=json&v={}&c={}&playlistId={}&name={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        playlist_id,
+        api::encode(name)
+    );
+
+    api::get(client, &url).await
+}
+
+async fn set_comment(
     client: &Client,
     conf: &config::Config,
     api_ver: &str,
     playlist_id: &str,
-    song_id: &str,
-) -> Result<(api::TopLevel, String), api::Error> {
+    comment: &str,
+) -> Result<api::TopLevel, api::Error> {
     let url = format!(
-        "{}/rest/updatePlaylist?u={}&t={}&s={}&f=json&v={}&c=graplsub&playlistId={}&songIdToAdd={}",
-        conf.base_url, conf.user, conf.md5_pass_salt, conf.salt, api_ver, playlist_id, song_id
+        "{}/rest/updatePlaylist?u={}&f=json&v={}&c={}&playlistId={}&comment={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        playlist_id,
+        api::encode(comment)
     );
 
     api::get(client, &url).await
 }
 
-pub fn check_update_response(resp: &api::TopLevel, json: &str) -> Result<(), api::RespParseError> {
+fn check_update_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
     // An empty response is expected here so just do the basic checks.
-    api::check_generic_response(resp, json)?;
+    api::check_generic_response(resp)?;
+
+    Ok(())
+}
+
+/// Give a playlist a new name.
+pub async fn rename(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    playlist_id: &str,
+    name: &str,
+) -> Result<(), api::Error> {
+    let context =
+        |e: api::Error| e.context(format!("renaming playlist {} to \"{}\"", playlist_id, name));
+    let subsonic_response = set_name(client, conf, api_ver, playlist_id, name)
+        .await
+        .map_err(context)?;
+
+    check_update_response(&subsonic_response)
+        .map_err(api::Error::from)
+        .map_err(context)?;
+
+    Ok(())
+}
+
+/// Whether a request might have failed only because of the network or an overloaded server, so
+/// is worth trying again.
+fn transient(err: &api::Error) -> bool {
+    match err.root() {
+        api::Error::Network(e) => {
+            e.is_timeout() || e.is_connect() || e.status().is_some_and(|s| s.is_server_error())
+        }
+        api::Error::UnixSocket { .. } => true,
+        _ => false,
+    }
+}
+
+/// Whether a request was turned down for being too long, which for adding songs means too many of
+/// them at once. Some servers, or proxies in front of them, say so with 414 and some just with 400.
+fn too_long(err: &api::Error) -> bool {
+    matches!(err.status(), Some(400 | 414))
+}
+
+/// Add songs to the end of the playlist, in order, a batch at a time.
+///
+/// If a batch fails in a way that might be temporary then it's tried again, but first the playlist
+/// is checked to see which of the batch's songs are already in it. A request that timed out may
+/// well have worked on the server, and blindly repeating it would add those songs twice.
+///
+/// If a batch is turned down for being too long, it's tried again half the size, and that size is
+/// kept for the rest of the run (and remembered in the state file, if there is one). A server that
+/// turns the request down has added none of it, so there's no need to check.
+pub async fn add_songs(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    playlist_id: &str,
+    songs: &[String],
+) -> Result<(), api::Error> {
+    let mut done = 0;
+
+    while done < songs.len() {
+        // How many of the songs from `done` on this batch is for.
+        let mut taken = client.batch_size().min(songs.len() - done);
+        let mut pending = songs[done..done + taken].to_vec();
+        let mut attempt = 1;
+
+        loop {
+            let err = match update(client, conf, api_ver, playlist_id, &pending).await {
+                Ok(subsonic_response) => match check_update_response(&subsonic_response) {
+                    Ok(_) => break,
+                    Err(e) => api::Error::from(e),
+                },
+                Err(e) => e,
+            };
+
+            if too_long(&err) && taken > 1 {
+                output::warn(format!(
+                    "The server wouldn't take {} songs at once. Trying {}.",
+                    taken,
+                    taken / 2
+                ));
+                taken /= 2;
+                client.set_batch_size(taken);
+
+                // The rest are left for the next batch. Any found to be in already stay out.
+                let batch = &songs[done..done + taken];
+                pending.retain(|id| batch.contains(id));
+                if pending.is_empty() {
+                    break;
+                }
+                continue;
+            }
+
+            if attempt >= ATTEMPTS || !transient(&err) {
+                return Err(err.context(format!("adding songs to playlist {}", playlist_id)));
+            }
+
+            output::warn(format!(
+                "Failed to add songs to the playlist ({}). Trying again.",
+                err
+            ));
+            tokio::time::sleep(RETRY_DELAY * attempt).await;
+            attempt += 1;
+
+            let present: HashSet<String> = song_ids(client, conf, api_ver, playlist_id)
+                .await?
+                .into_iter()
+                .collect();
+            pending.retain(|id| !present.contains(id));
+
+            if pending.is_empty() {
+                break;
+            }
+        }
+
+        done += taken;
+    }
+
+    Ok(())
+}
+
+/// Indexes to take out of a playlist, in batches of at most `size`, so that removing them one
+/// request at a time takes out the songs that were at those indexes to begin with.
+///
+/// Servers take each request's indexes as they were before that request, but the next request sees
+/// the playlist after it, with everything past what was taken out shifted down. Going from the end
+/// backwards means nothing still to be taken out has moved. Each index only counts once, as a
+/// repeat would take out whichever song had shifted into its place.
+fn removal_batches(indexes: &[usize], size: usize) -> Vec<Vec<usize>> {
+    let mut indexes = indexes.to_vec();
+    indexes.sort_unstable_by(|a, b| b.cmp(a));
+    indexes.dedup();
+
+    indexes.chunks(size.max(1)).map(<[usize]>::to_vec).collect()
+}
+
+/// The indexes of every entry in `entries` (a playlist's song IDs, in order) that's one of `ids`,
+/// including every copy of a song that's in more than once.
+fn indexes_of(entries: &[String], ids: &HashSet<String>) -> Vec<usize> {
+    entries
+        .iter()
+        .enumerate()
+        .filter(|(_, id)| ids.contains(*id))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Take the songs at `indexes`, counting from 0, out of a playlist.
+pub async fn remove_songs(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    playlist_id: &str,
+    indexes: &[usize],
+) -> Result<(), api::Error> {
+    for batch in removal_batches(indexes, BATCH_SIZE) {
+        let mut url = format!(
+            "{}/rest/updatePlaylist?u={}&f=json&v={}&c={}&playlistId={}",
+            conf.base_url,
+            api::encode(&conf.user),
+            api_ver,
+            api::encode(&conf.client_name),
+            playlist_id
+        );
+        for i in &batch {
+            url.push_str(&format!("&songIndexToRemove={}", i));
+        }
+
+        let context =
+            |e: api::Error| e.context(format!("removing songs from playlist {}", playlist_id));
+        let subsonic_response = api::get(client, &url).await.map_err(context)?;
+        check_update_response(&subsonic_response)
+            .map_err(api::Error::from)
+            .map_err(context)?;
+    }
 
     Ok(())
 }
+
+async fn get(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/getPlaylist?u={}&f=json&v={}&c={}&id={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        id
+    );
+
+    api::get(client, &url).await
+}
+
+/// Take every copy of each of the songs `ids` out of a playlist, returning how many entries went.
+pub async fn remove_ids(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    playlist_id: &str,
+    ids: &HashSet<String>,
+) -> Result<usize, api::Error> {
+    let entries = song_ids(client, conf, api_ver, playlist_id).await?;
+    let indexes = indexes_of(&entries, ids);
+
+    remove_songs(client, conf, api_ver, playlist_id, &indexes).await?;
+
+    Ok(indexes.len())
+}
+
+/// A playlist including its songs. Fails if there's no such playlist.
+pub async fn fetch(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id: &str,
+) -> Result<api::Playlist, api::Error> {
+    let context = |e: api::Error| e.context(format!("playlist {}", id));
+    let subsonic_response = get(client, conf, api_ver, id).await.map_err(context)?;
+
+    // Same check as after creating one.
+    check_create_response(&subsonic_response)
+        .map_err(api::Error::from)
+        .map_err(context)?;
+
+    // Safe to unwrap() because we already checked that it wasn't None.
+    Ok(subsonic_response.subsonic_response.playlist.unwrap())
+}
+
+/// IDs of the songs in a playlist, in order. Fails if there's no such playlist.
+pub async fn song_ids(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id: &str,
+) -> Result<Vec<String>, api::Error> {
+    // An empty playlist has no entries at all.
+    Ok(fetch(client, conf, api_ver, id)
+        .await?
+        .entry
+        .unwrap_or_default()
+        .into_iter()
+        .map(|s| s.id)
+        .collect())
+}
+
+/// Find a playlist by its ID or, failing that, its name. Doesn't include its songs.
+pub async fn find(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id_or_name: &str,
+) -> Result<api::Playlist, api::Error> {
+    let mut playlists = all(client, conf, api_ver).await?;
+
+    let pos = playlists
+        .iter()
+        .position(|p| p.id == id_or_name)
+        .or_else(|| playlists.iter().position(|p| p.name == id_or_name))
+        .ok_or_else(|| api::Error::NotFound {
+            resource: format!("playlist \"{}\"", id_or_name),
+        })?;
+
+    Ok(playlists.swap_remove(pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{self, Server};
+
+    #[test]
+    fn removal_batches_go_from_the_end() {
+        assert_eq!(removal_batches(&[1, 7, 3], 50), vec![vec![7, 3, 1]]);
+    }
+
+    #[test]
+    fn removal_batches_are_at_most_size() {
+        let indexes: Vec<usize> = (0..BATCH_SIZE + 1).collect();
+        let batches = removal_batches(&indexes, BATCH_SIZE);
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].len(), BATCH_SIZE);
+        assert_eq!(batches[0][0], BATCH_SIZE);
+        assert_eq!(batches[1], vec![0]);
+    }
+
+    #[test]
+    fn removal_batches_of_exactly_size() {
+        let indexes: Vec<usize> = (0..BATCH_SIZE).collect();
+
+        assert_eq!(removal_batches(&indexes, BATCH_SIZE).len(), 1);
+        assert_eq!(removal_batches(&indexes[1..], BATCH_SIZE).len(), 1);
+        assert!(removal_batches(&[], BATCH_SIZE).is_empty());
+    }
+
+    #[test]
+    fn removal_batches_drop_repeats() {
+        assert_eq!(removal_batches(&[4, 2, 4, 2, 2], 50), vec![vec![4, 2]]);
+        // A repeat across what would have been a batch boundary doesn't leave a gap.
+        assert_eq!(removal_batches(&[0, 1, 1, 2], 2), vec![vec![2, 1], vec![0]]);
+    }
+
+    #[test]
+    fn removal_batches_size_zero_is_one() {
+        assert_eq!(removal_batches(&[0, 1], 0), vec![vec![1], vec![0]]);
+    }
+
+    #[tokio::test]
+    async fn awkward_names_reach_the_server() {
+        let server = Server::start(|_| (Duration::ZERO, json!({ "playlists": {} }))).await;
+        let conf = testing::conf(&[
+            ("GRAPLSUB_BASE_URL", &server.base_url),
+            ("GRAPLSUB_USER", "a&b=c d"),
+            ("GRAPLSUB_CLIENT_NAME", "graplsub & co?f=xml"),
+        ]);
+        let client = api::create_client(&conf).unwrap();
+
+        all(&client, &conf, "1.16.1").await.unwrap();
+
+        let call = &server.calls_to("getPlaylists")[0];
+        assert_eq!(call.all("u"), vec!["a&b=c d"]);
+        assert_eq!(call.all("c"), vec!["graplsub & co?f=xml"]);
+        assert_eq!(call.all("f"), vec!["json"]);
+    }
+
+    #[tokio::test]
+    async fn fetch_gets_the_songs_in_order() {
+        let server = Server::start(|call| {
+            let body = match call.get("id") {
+                Some("800") => json!({ "playlist": {
+                    "id": "800",
+                    "name": "graplsub",
+                    "entry": [{ "id": "3" }, { "id": "1" }, { "id": "3" }],
+                }}),
+                _ => json!({
+                    "status": "failed",
+                    "error": { "code": 70, "message": "Playlist not found" },
+                }),
+            };
+            (Duration::ZERO, body)
+        })
+        .await;
+        let conf = testing::conf(&[("GRAPLSUB_BASE_URL", &server.base_url)]);
+        let client = api::create_client(&conf).unwrap();
+
+        let ids = song_ids(&client, &conf, "1.16.1", "800").await.unwrap();
+        assert_eq!(ids, vec!["3", "1", "3"]);
+
+        let missing = fetch(&client, &conf, "1.16.1", "801").await.unwrap_err();
+        assert!(missing.to_string().contains("Playlist not found"));
+    }
+
+    #[test]
+    fn indexes_of_every_copy() {
+        let entries: Vec<String> = ["a", "b", "a", "c"].map(String::from).to_vec();
+        let ids = HashSet::from(["a".to_string(), "c".to_string()]);
+
+        assert_eq!(indexes_of(&entries, &ids), vec![0, 2, 3]);
+    }
+}
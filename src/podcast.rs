@@ -0,0 +1,62 @@
+use std::collections::HashSet;
+
+use crate::api;
+use crate::api::Client;
+use crate::config;
+
+async fn list_all(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/getPodcasts?u={}&f=json&v={}&c={}&includeEpisodes=false",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name)
+    );
+
+    api::get(client, &url).await
+}
+
+fn check_list_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
+    api::check_generic_response(resp)?;
+
+    if resp.subsonic_response.podcasts.is_none() {
+        return Err(api::RespParseError::MissingPodcasts {
+            excerpt: resp.excerpt.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+/// The titles of all the podcast channels on the server, lowercased. Some servers present podcast
+/// episodes as albums named after their channel, so this lets us spot them. Plenty of servers
+/// don't do podcasts at all, in which case there just aren't any.
+pub async fn channel_titles(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> HashSet<String> {
+    let subsonic_response = match list_all(client, conf, api_ver).await {
+        Ok(r) => r,
+        Err(_) => return HashSet::new(),
+    };
+
+    if check_list_response(&subsonic_response).is_err() {
+        return HashSet::new();
+    }
+
+    // Safe to unwrap() as we already checked it was Some().
+    subsonic_response
+        .subsonic_response
+        .podcasts
+        .unwrap()
+        .channel
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|c| c.title.map(|t| t.to_lowercase()))
+        .collect()
+}
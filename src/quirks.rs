@@ -0,0 +1,65 @@
+use crate::api;
+
+// Workarounds for the ways particular servers stray from what the API says. Which ones apply is
+// decided by what the server says it is when it's first pinged. OpenSubsonic servers (Navidrome,
+// gonic, LMS, Supysonic and others) say what they are, and their JSON is JSON from the start.
+// Servers that don't say are taken to be Subsonic itself or one of its forks, like
+// Airsonic-Advanced, which make their JSON from XML and have the quirks that go with that.
+
+/// Which workarounds a server needs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    // Leaves out a list altogether when it would be empty, rather than sending an empty one, so a
+    // user with no playlists gets no "playlists" at all.
+    pub omits_empty_lists: bool,
+}
+
+/// The quirks of a server, going by the type it says it is, if any.
+pub fn detect(server_type: Option<&str>) -> Quirks {
+    match server_type.map(str::to_lowercase).as_deref() {
+        Some("navidrome" | "gonic" | "lms" | "supysonic") => Quirks::default(),
+        Some(t) if t.starts_with("airsonic") => Quirks {
+            omits_empty_lists: true,
+        },
+        // Some other OpenSubsonic server, which there's no reason to think is odd.
+        Some(_) => Quirks::default(),
+        None => Quirks {
+            omits_empty_lists: true,
+        },
+    }
+}
+
+impl Quirks {
+    /// Put back whatever the server left out of its response to `endpoint`, so that it looks like
+    /// any other server's.
+    pub fn fill_in(&self, endpoint: &str, resp: &mut api::SubsonicResponse) {
+        if !self.omits_empty_lists {
+            return;
+        }
+
+        match endpoint {
+            "getAlbumList" => {
+                resp.album_list.get_or_insert_default();
+            }
+            "getArtistInfo2" => {
+                resp.artist_info.get_or_insert_default();
+            }
+            "getMusicFolders" => {
+                resp.music_folders.get_or_insert_default();
+            }
+            "getPlaylists" => {
+                resp.playlists.get_or_insert_default();
+            }
+            "getPodcasts" => {
+                resp.podcasts.get_or_insert_default();
+            }
+            "getSimilarSongs2" => {
+                resp.similar_songs.get_or_insert_default();
+            }
+            "search3" => {
+                resp.search_result.get_or_insert_default();
+            }
+            _ => {}
+        }
+    }
+}
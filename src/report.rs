@@ -0,0 +1,167 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::api;
+use crate::api::Client;
+use crate::config;
+use crate::covers;
+use crate::covers::escape;
+use crate::date;
+use crate::stats;
+
+// A report of a run as one HTML page, covers and all, to pass round the household: what went into
+// the playlist, what it adds up to and what it was asked for. Like the covers page it has
+// everything in it, so it can be opened anywhere.
+
+/// Where the report for a profile goes when there are several of them, so that they don't write
+/// over each other: the profile's name goes before the extension, as in "report.home.html".
+pub fn path_for(path: &Path, profile: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(profile);
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+
+    path.with_file_name(name)
+}
+
+/// What went into the playlist.
+pub struct Playlist<'a> {
+    pub id: &'a str,
+    // In order, with their songs.
+    pub albums: &'a [api::Album],
+    // Songs added on the end by GRAPLSUB_PAD_TO_SONGS.
+    pub padded: &'a [api::Song],
+    // The IDs of every song that actually went in.
+    pub added: &'a HashSet<String>,
+}
+
+/// Write the report to `path`.
+pub async fn write(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    playlist: &Playlist<'_>,
+    path: &Path,
+) -> io::Result<()> {
+    let Playlist {
+        id,
+        albums,
+        padded,
+        added,
+    } = *playlist;
+    let covers = covers::fetch_all(client, conf, api_ver, albums).await;
+    let stats = stats::Stats::new(albums, padded);
+    let mut body = String::new();
+
+    let name = escape(&conf.playlist_name);
+    body.push_str(&match &conf.playlist_link {
+        Some(link) => format!(
+            "<p><a href=\"{}\">{}</a></p>\n",
+            escape(&link.replace("{id}", id)),
+            name
+        ),
+        None => format!("<p>{}</p>\n", name),
+    });
+
+    body.push_str(&format!(
+        "<p><b>{}</b></p>\n<table>\n",
+        escape(&stats.summary())
+    ));
+    for (label, text) in stats.breakdown() {
+        body.push_str(&row(label, &text));
+    }
+    body.push_str("</table>\n");
+
+    body.push_str("<h2>Albums</h2>\n<div class=\"grid\">\n");
+    for (album, cover) in albums.iter().zip(&covers) {
+        body.push_str(&covers::tile(conf, album, cover.as_deref()));
+    }
+    body.push_str("</div>\n");
+
+    body.push_str("<h2>Songs</h2>\n<ol>\n");
+    for album in albums {
+        for song in album.song.iter().flatten() {
+            if added.contains(&song.id) {
+                body.push_str(&item(song, Some(album)));
+            }
+        }
+    }
+    for song in padded {
+        body.push_str(&item(song, None));
+    }
+    body.push_str("</ol>\n");
+
+    body.push_str("<h2>Asked for</h2>\n<table>\n");
+    for (label, text) in parameters(conf) {
+        body.push_str(&row(label, &text));
+    }
+    body.push_str("</table>\n");
+
+    let title = format!("{}, {}", conf.playlist_name, date::iso_date(date::today()));
+
+    fs::write(path, covers::page(&title, &body))
+}
+
+/// The settings that decided what went into the playlist, or at least the ones that are set.
+fn parameters(conf: &config::Config) -> Vec<(&'static str, String)> {
+    let mut params = vec![
+        ("Mode:", format!("{:?}", conf.mode).to_lowercase()),
+        ("Albums:", conf.num_albums.to_string()),
+    ];
+
+    let optional = [
+        ("Profile:", conf.profile.clone()),
+        ("Seed artists:", conf.seed_artists.clone()),
+        ("Top artists:", conf.top_artists.clone()),
+        ("Pool playlist:", conf.pool_playlist.clone()),
+        ("Music folder:", conf.music_folder.clone()),
+        (
+            "Added within:",
+            conf.added_within.map(|d| format!("{} days", d)),
+        ),
+        ("Minimum rating:", conf.min_rating.map(|r| r.to_string())),
+        ("Maximum rating:", conf.max_rating.map(|r| r.to_string())),
+        (
+            "Minimum bitrate:",
+            conf.min_bitrate.map(|b| format!("{}kbps", b)),
+        ),
+        (
+            "Padded to:",
+            conf.pad_to_songs.map(|n| format!("{} songs", n)),
+        ),
+    ];
+    params.extend(optional.into_iter().filter_map(|(l, v)| Some((l, v?))));
+
+    params
+}
+
+fn row(label: &str, text: &str) -> String {
+    format!(
+        "<tr><th>{}</th><td>{}</td></tr>\n",
+        escape(label),
+        escape(text)
+    )
+}
+
+/// A song in the listing, with the album's artist if it doesn't have its own.
+fn item(song: &api::Song, album: Option<&api::Album>) -> String {
+    let artist = song
+        .artist
+        .as_deref()
+        .or_else(|| album.and_then(|a| a.artist.as_deref()))
+        .unwrap_or("Unknown artist");
+    let secs = song.duration.unwrap_or(0);
+
+    format!(
+        "<li>{} – {} ({}:{:02})</li>\n",
+        escape(artist),
+        escape(song.title.as_deref().unwrap_or(&song.id)),
+        secs / 60,
+        secs % 60
+    )
+}
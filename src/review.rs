@@ -0,0 +1,195 @@
+use std::io::{self, IsTerminal};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Text};
+use ratatui::widgets::{Block, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use thiserror::Error;
+
+use crate::api;
+use crate::output;
+use crate::stats;
+
+const HELP: &str = "↑/↓ move  space drop/keep  r replace  enter build  q quit";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Couldn't review the albums: {0}")]
+    Terminal(#[from] io::Error),
+}
+
+/// Puts the terminal back how it was when it goes, whether the review finished, failed part way
+/// or panicked. ratatui's own panic hook does the same, which doesn't hurt.
+struct Restore;
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+/// The albums being reviewed, and which of them are still wanted.
+struct Review {
+    albums: Vec<(api::Album, bool)>,
+    reserve: Vec<api::Album>,
+    table: TableState,
+    // What happened last, shown at the bottom.
+    status: String,
+}
+
+/// Let the user look over the chosen albums before the playlist is built, dropping some and
+/// swapping others for albums from the reserve. Returns None if they'd rather not build it at all,
+/// and an error if the terminal couldn't be used, which isn't the same thing.
+pub fn albums(
+    chosen: Vec<api::Album>,
+    reserve: Vec<api::Album>,
+) -> Result<Option<Vec<api::Album>>, Error> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        output::note("Not reviewing the albums as there's no terminal to do it on.");
+        return Ok(Some(chosen));
+    }
+
+    let mut review = Review {
+        albums: chosen.into_iter().map(|a| (a, true)).collect(),
+        status: format!("{} more albums in reserve.", reserve.len()),
+        reserve,
+        table: TableState::default().with_selected(Some(0)),
+    };
+
+    // Before starting, as it can fail part way.
+    let restore = Restore;
+    let mut terminal = ratatui::try_init()?;
+    let accepted = review.run(&mut terminal)?;
+    drop(restore);
+
+    Ok(accepted.then(|| {
+        review
+            .albums
+            .into_iter()
+            .filter_map(|(a, keep)| keep.then_some(a))
+            .collect()
+    }))
+}
+
+impl Review {
+    /// Handle keys until the user accepts (true) or quits (false).
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<bool> {
+        loop {
+            terminal.draw(|f| self.draw(f))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+                KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+                KeyCode::Home | KeyCode::Char('g') => self.table.select_first(),
+                KeyCode::End | KeyCode::Char('G') => self.table.select_last(),
+                KeyCode::Char(' ') | KeyCode::Char('d') => self.toggle(),
+                KeyCode::Char('r') => self.replace(),
+                KeyCode::Enter | KeyCode::Char('y') => return Ok(true),
+                KeyCode::Esc | KeyCode::Char('q') => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+
+    fn toggle(&mut self) {
+        let Some((album, keep)) = self.table.selected().and_then(|i| self.albums.get_mut(i)) else {
+            return;
+        };
+        *keep = !*keep;
+        self.status = format!(
+            "{} {}",
+            if *keep { "Kept" } else { "Dropped" },
+            describe(album)
+        );
+    }
+
+    fn replace(&mut self) {
+        let Some(i) = self.table.selected().filter(|&i| i < self.albums.len()) else {
+            return;
+        };
+        if self.reserve.is_empty() {
+            self.status = "The reserve is empty.".to_string();
+            return;
+        }
+
+        let new = self.reserve.remove(0);
+        self.status = format!(
+            "Replaced {} with {} ({} left in reserve)",
+            describe(&self.albums[i].0),
+            describe(&new),
+            self.reserve.len()
+        );
+        self.albums[i] = (new, true);
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [top, bottom] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(2)]).areas(frame.area());
+
+        let rows = self.albums.iter().map(|(album, keep)| {
+            let row = Row::new(vec![
+                if *keep { "✓" } else { " " }.to_string(),
+                describe(album),
+                album.year.map(|y| y.to_string()).unwrap_or_default(),
+                album.genre.clone().unwrap_or_default(),
+                album.song_count.map(|n| n.to_string()).unwrap_or_default(),
+                album
+                    .duration
+                    .map(|d| stats::duration(u64::from(d)))
+                    .unwrap_or_default(),
+            ]);
+            if *keep {
+                row
+            } else {
+                row.style(Style::new().add_modifier(Modifier::DIM | Modifier::CROSSED_OUT))
+            }
+        });
+
+        let kept = self.albums.iter().filter(|(_, keep)| *keep).count();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(1),
+                Constraint::Fill(1),
+                Constraint::Length(4),
+                Constraint::Length(16),
+                Constraint::Length(5),
+                Constraint::Length(8),
+            ],
+        )
+        .header(Row::new(["", "Album", "Year", "Genre", "Songs", "Length"]).bold())
+        .block(Block::bordered().title(format!(
+            " {} of {} albums, {} in reserve ",
+            kept,
+            self.albums.len(),
+            self.reserve.len()
+        )))
+        .row_highlight_style(Style::new().reversed());
+
+        frame.render_stateful_widget(table, top, &mut self.table);
+        frame.render_widget(
+            Text::from(vec![
+                Line::from(self.status.as_str()),
+                Line::from(HELP).dim(),
+            ]),
+            bottom,
+        );
+    }
+}
+
+fn describe(album: &api::Album) -> String {
+    format!(
+        "{} – {}",
+        album.artist.as_deref().unwrap_or("Unknown artist"),
+        album.name.as_deref().unwrap_or("Unknown album")
+    )
+}
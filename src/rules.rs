@@ -0,0 +1,540 @@
+use std::fmt;
+
+use crate::api;
+
+/// Rules that every picked album has to satisfy, like:
+///
+/// `year >= 1990 && genre != "Holiday" && duration < 80min`
+///
+/// They're parsed once when the config is read, then checked against each candidate album.
+///
+/// A comparison with something the server didn't say about an album, like the year of one whose
+/// year isn't known, is only true for `!=`. So `year != 1990` lets such an album through, but
+/// `year < 1990` and `!(year >= 1990)` are different things for it.
+#[derive(Clone, Debug)]
+pub struct Rules {
+    exprs: Vec<Expr>,
+}
+
+impl Rules {
+    /// Parse rules, one per line. An album has to satisfy all of them. Blank lines and lines
+    /// starting with '#' are ignored.
+    pub fn parse(spec: &str) -> Result<Rules, ParseError> {
+        let exprs = spec
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| Parser::new(line)?.parse())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Rules { exprs })
+    }
+
+    pub fn allows(&self, album: &api::Album) -> bool {
+        self.exprs.iter().all(|e| e.eval(album))
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    rule: String,
+    reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bad rule \"{}\": {}", self.rule, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The album fields that rules can look at.
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    Artist,
+    // Seconds.
+    Duration,
+    Genre,
+    Name,
+    Plays,
+    Rating,
+    Songs,
+    Year,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        match name {
+            "artist" => Some(Field::Artist),
+            "duration" => Some(Field::Duration),
+            "genre" => Some(Field::Genre),
+            "name" | "album" => Some(Field::Name),
+            "plays" => Some(Field::Plays),
+            "rating" => Some(Field::Rating),
+            "songs" => Some(Field::Songs),
+            "year" => Some(Field::Year),
+            _ => None,
+        }
+    }
+
+    fn is_text(self) -> bool {
+        matches!(self, Field::Artist | Field::Genre | Field::Name)
+    }
+
+    fn number(self, album: &api::Album) -> Option<u64> {
+        match self {
+            Field::Duration => album.duration.map(u64::from),
+            Field::Plays => album.play_count,
+            Field::Rating => album.user_rating.map(u64::from),
+            Field::Songs => album.song_count.map(u64::from),
+            Field::Year => album.year.map(u64::from),
+            Field::Artist | Field::Genre | Field::Name => None,
+        }
+    }
+
+    /// Text values of the field. Albums can have more than one genre.
+    fn text(self, album: &api::Album) -> Vec<&str> {
+        match self {
+            Field::Artist => album.artist.as_deref().into_iter().collect(),
+            Field::Name => album.name.as_deref().into_iter().collect(),
+            Field::Genre => album
+                .genre
+                .as_deref()
+                .into_iter()
+                .chain(album.genres.iter().flatten().map(|g| g.name.as_str()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Number(u64),
+    Text(String),
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Field, Op, Value),
+}
+
+impl Expr {
+    fn eval(&self, album: &api::Album) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(album) && b.eval(album),
+            Expr::Or(a, b) => a.eval(album) || b.eval(album),
+            Expr::Not(e) => !e.eval(album),
+            Expr::Compare(field, op, Value::Number(n)) => match field.number(album) {
+                Some(v) => match op {
+                    Op::Eq => v == *n,
+                    Op::Ne => v != *n,
+                    Op::Lt => v < *n,
+                    Op::Le => v <= *n,
+                    Op::Gt => v > *n,
+                    Op::Ge => v >= *n,
+                },
+                // Nothing is known about it, so it can't be said to match, but it can be said
+                // not to. See Rules.
+                None => *op == Op::Ne,
+            },
+            // Text is only compared for (in)equality, ignoring case. With several genres, "=="
+            // means any of them and "!=" means none of them.
+            Expr::Compare(field, op, Value::Text(s)) => {
+                let found = field
+                    .text(album)
+                    .iter()
+                    .any(|t| t.to_lowercase() == s.to_lowercase());
+
+                if *op == Op::Eq { found } else { !found }
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Op(Op),
+    Word(String),
+    Number(u64),
+    Text(String),
+}
+
+/// A recursive descent parser for:
+///
+/// ```text
+/// or      = and ("||" and)*
+/// and     = unary ("&&" unary)*
+/// unary   = "!" unary | "(" or ")" | field op value
+/// value   = number [unit] | "quoted text"
+/// ```
+struct Parser<'a> {
+    rule: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(rule: &'a str) -> Result<Parser<'a>, ParseError> {
+        let mut parser = Parser {
+            rule,
+            tokens: Vec::new(),
+            pos: 0,
+        };
+        parser.tokens = parser.tokenise()?;
+
+        Ok(parser)
+    }
+
+    fn error(&self, reason: impl Into<String>) -> ParseError {
+        ParseError {
+            rule: self.rule.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    fn tokenise(&self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = Vec::new();
+        let mut chars = self.rule.chars().peekable();
+
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+
+            if c == '"' {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(ch) => text.push(ch),
+                        None => return Err(self.error("unterminated quote")),
+                    }
+                }
+                tokens.push(Token::Text(text));
+                continue;
+            }
+
+            if c.is_ascii_alphanumeric() || c == '_' {
+                let mut word = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if !(ch.is_ascii_alphanumeric() || ch == '_') {
+                        break;
+                    }
+                    word.push(ch);
+                    chars.next();
+                }
+                tokens.push(if c.is_ascii_digit() {
+                    Token::Number(self.number(&word)?)
+                } else {
+                    Token::Word(word.to_lowercase())
+                });
+                continue;
+            }
+
+            chars.next();
+            let next_is = |chars: &mut std::iter::Peekable<std::str::Chars>, want: char| {
+                chars.next_if_eq(&want).is_some()
+            };
+            tokens.push(match c {
+                '(' => Token::Open,
+                ')' => Token::Close,
+                '&' if next_is(&mut chars, '&') => Token::And,
+                '|' if next_is(&mut chars, '|') => Token::Or,
+                '=' if next_is(&mut chars, '=') => Token::Op(Op::Eq),
+                '!' if next_is(&mut chars, '=') => Token::Op(Op::Ne),
+                '!' => Token::Not,
+                '<' if next_is(&mut chars, '=') => Token::Op(Op::Le),
+                '<' => Token::Op(Op::Lt),
+                '>' if next_is(&mut chars, '=') => Token::Op(Op::Ge),
+                '>' => Token::Op(Op::Gt),
+                _ => return Err(self.error(format!("unexpected '{}'", c))),
+            });
+        }
+
+        Ok(tokens)
+    }
+
+    /// A number, optionally followed by a unit of time, which is converted to seconds.
+    fn number(&self, word: &str) -> Result<u64, ParseError> {
+        let split = word
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(word.len());
+        let (digits, unit) = word.split_at(split);
+        let n: u64 = digits
+            .parse()
+            .map_err(|_| self.error(format!("bad number \"{}\"", word)))?;
+
+        let scale = match unit.to_lowercase().as_str() {
+            "" | "s" | "sec" | "secs" => 1,
+            "m" | "min" | "mins" => 60,
+            "h" | "hr" | "hrs" => 3600,
+            _ => return Err(self.error(format!("unknown unit \"{}\"", unit))),
+        };
+
+        n.checked_mul(scale)
+            .ok_or_else(|| self.error(format!("number \"{}\" is too big", word)))
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, want: &Token) -> bool {
+        if self.tokens.get(self.pos) == Some(want) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse(mut self) -> Result<Expr, ParseError> {
+        let expr = self.or()?;
+
+        if self.pos < self.tokens.len() {
+            return Err(self.error("unexpected text at the end"));
+        }
+
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.and()?;
+
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.unary()?;
+
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.eat(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+
+        if self.eat(&Token::Open) {
+            let expr = self.or()?;
+            if !self.eat(&Token::Close) {
+                return Err(self.error("missing ')'"));
+            }
+            return Ok(expr);
+        }
+
+        self.compare()
+    }
+
+    fn compare(&mut self) -> Result<Expr, ParseError> {
+        let field = match self.next() {
+            Some(Token::Word(w)) => {
+                let w = w.clone();
+                Field::from_name(&w)
+                    .ok_or_else(|| self.error(format!("unknown field \"{}\"", w)))?
+            }
+            _ => return Err(self.error("expected a field name")),
+        };
+
+        let op = match self.next() {
+            Some(Token::Op(op)) => *op,
+            _ => return Err(self.error("expected a comparison after field")),
+        };
+
+        let value = match self.next() {
+            Some(Token::Number(n)) if !field.is_text() => Value::Number(*n),
+            Some(Token::Text(t)) if field.is_text() => Value::Text(t.clone()),
+            _ if field.is_text() => return Err(self.error("expected quoted text")),
+            _ => return Err(self.error("expected a number")),
+        };
+
+        if field.is_text() && !matches!(op, Op::Eq | Op::Ne) {
+            return Err(self.error("text can only be compared with == or !="));
+        }
+
+        Ok(Expr::Compare(field, op, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn album(fields: serde_json::Value) -> api::Album {
+        let mut album = json!({ "id": "1" });
+        album
+            .as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(album).unwrap()
+    }
+
+    fn allows(rule: &str, fields: serde_json::Value) -> bool {
+        Rules::parse(rule).unwrap().allows(&album(fields))
+    }
+
+    fn error(rule: &str) -> String {
+        Rules::parse(rule).unwrap_err().reason
+    }
+
+    #[test]
+    fn compares_numbers() {
+        let year = || json!({ "year": 1990 });
+
+        assert!(allows("year == 1990", year()));
+        assert!(allows("year >= 1990", year()));
+        assert!(allows("year <= 1990", year()));
+        assert!(allows("year > 1989", year()));
+        assert!(allows("year < 1991", year()));
+        assert!(!allows("year != 1990", year()));
+        assert!(!allows("year > 1990", year()));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        // year == 1 || (year == 2 && songs == 3)
+        let rule = "year == 1 || year == 2 && songs == 3";
+
+        assert!(allows(rule, json!({ "year": 1, "songCount": 9 })));
+        assert!(!allows(rule, json!({ "year": 2, "songCount": 9 })));
+        assert!(allows(rule, json!({ "year": 2, "songCount": 3 })));
+    }
+
+    #[test]
+    fn brackets_come_first() {
+        let rule = "(year == 1 || year == 2) && songs == 3";
+
+        assert!(!allows(rule, json!({ "year": 1, "songCount": 9 })));
+        assert!(allows(rule, json!({ "year": 1, "songCount": 3 })));
+        assert!(allows("!(year == 1 || year == 2)", json!({ "year": 3 })));
+        assert!(!allows("!(year == 1 || year == 2)", json!({ "year": 2 })));
+    }
+
+    #[test]
+    fn not_applies_to_the_next_comparison() {
+        // (!(year == 1)) && songs == 3
+        let rule = "!year == 1 && songs == 3";
+
+        assert!(allows(rule, json!({ "year": 2, "songCount": 3 })));
+        assert!(!allows(rule, json!({ "year": 1, "songCount": 3 })));
+        assert!(!allows(rule, json!({ "year": 2, "songCount": 4 })));
+    }
+
+    #[test]
+    fn quoted_text_ignores_case() {
+        let jazz = || json!({ "genre": "Jazz", "artist": "Miles Davis" });
+
+        assert!(allows("genre == \"jazz\"", jazz()));
+        assert!(allows("artist == \"MILES DAVIS\"", jazz()));
+        assert!(allows("artist != \"Miles\"", jazz()));
+        assert!(allows(
+            "name == \"a && b || (c)\"",
+            json!({ "name": "A && B || (c)" })
+        ));
+    }
+
+    #[test]
+    fn any_of_several_genres() {
+        let genres = || json!({ "genres": [{ "name": "Rock" }, { "name": "Jazz" }] });
+
+        assert!(allows("genre == \"jazz\"", genres()));
+        assert!(!allows("genre != \"jazz\"", genres()));
+        assert!(allows("genre != \"Pop\"", genres()));
+    }
+
+    #[test]
+    fn units_are_seconds() {
+        let long = || json!({ "duration": 4800 });
+
+        assert!(allows("duration == 80min", long()));
+        assert!(allows("duration == 80m", long()));
+        assert!(allows("duration == 4800s", long()));
+        assert!(allows("duration < 2h", long()));
+        assert!(allows("duration > 1HR", long()));
+    }
+
+    #[test]
+    fn missing_fields_only_differ() {
+        assert!(allows("year != 1990", json!({})));
+        assert!(!allows("year == 1990", json!({})));
+        assert!(!allows("year < 1990", json!({})));
+        assert!(!allows("year >= 1990", json!({})));
+        assert!(allows("!(year >= 1990)", json!({})));
+        assert!(allows("genre != \"Jazz\"", json!({})));
+        assert!(!allows("genre == \"Jazz\"", json!({})));
+    }
+
+    #[test]
+    fn blank_lines_and_comments() {
+        let rules = Rules::parse("# Nothing old\n\n  year >= 1990\nsongs > 2\n").unwrap();
+
+        assert_eq!(rules.exprs.len(), 2);
+        assert!(rules.allows(&album(json!({ "year": 1990, "songCount": 3 }))));
+        assert!(!rules.allows(&album(json!({ "year": 1990, "songCount": 2 }))));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(error("genre == \"Jazz"), "unterminated quote");
+        assert_eq!(error("colour == \"red\""), "unknown field \"colour\"");
+        assert_eq!(error("(year == 1"), "missing ')'");
+        assert_eq!(error("year == 1 )"), "unexpected text at the end");
+        assert_eq!(error("year 1990"), "expected a comparison after field");
+        assert_eq!(error("year == \"1990\""), "expected a number");
+        assert_eq!(error("genre == 1990"), "expected quoted text");
+        assert_eq!(
+            error("genre < \"Jazz\""),
+            "text can only be compared with == or !="
+        );
+        assert_eq!(error("year = 1990"), "unexpected '='");
+        assert_eq!(error("== 1990"), "expected a field name");
+        assert_eq!(error("duration < 80days"), "unknown unit \"days\"");
+        assert_eq!(error("year >= 1990 &&"), "expected a field name");
+    }
+
+    #[test]
+    fn numbers_too_big() {
+        assert_eq!(
+            error("duration < 99999999999999999999"),
+            "bad number \"99999999999999999999\""
+        );
+        assert_eq!(
+            error("duration < 18446744073709551615h"),
+            "number \"18446744073709551615h\" is too big"
+        );
+    }
+}
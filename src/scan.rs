@@ -0,0 +1,120 @@
+use std::time::{Duration, Instant};
+
+use crate::api;
+use crate::api::Client;
+use crate::config;
+use crate::output;
+
+// How long to wait between checks on a scan that's in progress.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+async fn status(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/getScanStatus?u={}&f=json&v={}&c={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name)
+    );
+
+    api::get(client, &url).await
+}
+
+fn check_status_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
+    api::check_generic_response(resp)?;
+
+    if resp.subsonic_response.scan_status.is_none() {
+        return Err(api::RespParseError::MissingScanStatus {
+            excerpt: resp.excerpt.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+async fn start_scan(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/startScan?u={}&f=json&v={}&c={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name)
+    );
+
+    api::get(client, &url).await
+}
+
+/// Ask the server to start scanning the library. This returns straight away; use wait_for_idle()
+/// to wait for the scan to finish.
+pub async fn start(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<(), api::Error> {
+    let subsonic_response = start_scan(client, conf, api_ver).await?;
+
+    // startScan returns the scan status, same as getScanStatus.
+    check_status_response(&subsonic_response)?;
+
+    Ok(())
+}
+
+/// What the server says about its library scan.
+pub async fn current(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<api::ScanStatus, api::Error> {
+    let subsonic_response = status(client, conf, api_ver).await?;
+
+    check_status_response(&subsonic_response)?;
+
+    // Safe to unwrap() because we already checked it was Some().
+    Ok(subsonic_response.subsonic_response.scan_status.unwrap())
+}
+
+/// If a library scan is in progress then wait for it to finish, so that albums which are only half
+/// imported don't get picked. Gives up waiting after conf.scan_wait_secs, with a warning, and
+/// carries on anyway as a playlist from a slightly stale library is better than none.
+pub async fn wait_for_idle(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<(), api::Error> {
+    let deadline = Instant::now() + Duration::from_secs(conf.scan_wait_secs);
+    let mut announced = false;
+
+    loop {
+        let scan = current(client, conf, api_ver).await?;
+
+        if !scan.scanning {
+            return Ok(());
+        }
+
+        if Instant::now() >= deadline {
+            output::warn(format!(
+                "Library scan still running after {} seconds. Carrying on without waiting.",
+                conf.scan_wait_secs
+            ));
+            return Ok(());
+        }
+
+        if !announced {
+            output::note(format!(
+                "Waiting for library scan to finish ({} files so far)…",
+                scan.count.unwrap_or(0)
+            ));
+            announced = true;
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,313 @@
+use crate::date;
+
+// Cron-style schedules for daemon mode, as "minute hour day-of-month month day-of-week". For
+// example "0 5 * * *" is five in the morning every day and "30 6 * * mon" is half past six every
+// Monday. Each field can be "*", a number, a range like "1-5", any of those with a step like
+// "*/15" or "0-30/10", or a comma-separated list of them. Months can also be "jan" to "dec" and
+// days of the week "sun" to "sat", or 0 to 7 where both 0 and 7 are Sunday. As in cron, if both
+// the day of the month and the day of the week are restricted then a day matching either will do.
+//
+// Days, for profiles that only apply on some days of the week, are the same as the day of the
+// week field, plus "weekdays" and "weekends".
+//
+// There's no time zone handling to speak of, so times are UTC.
+
+const MONTHS: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// How far ahead to look for the next time before giving up. Far enough for the 29th of February.
+const SEARCH_DAYS: i64 = 366 * 8;
+
+/// When something should happen. Each field is a bitmask of the values it matches.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Whether the day fields were anything but "*", for the either-will-do rule.
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Schedule {
+    pub fn parse(spec: &str) -> Result<Schedule, String> {
+        let spec = match spec.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            s => s,
+        };
+
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "expected 5 fields (minute hour day month weekday) in \"{}\"",
+                spec
+            ));
+        };
+
+        // Sunday can be 7 as well as 0.
+        let weekdays_mask = field(weekdays, 0, 7, WEEKDAYS)?;
+        let schedule = Schedule {
+            minutes: field(minutes, 0, 59, &[])?,
+            hours: field(hours, 0, 23, &[])?,
+            days: field(days, 1, 31, &[])?,
+            months: field(months, 1, 12, MONTHS)?,
+            weekdays: (weekdays_mask | (weekdays_mask >> 7)) & 0x7f,
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !weekdays.starts_with('*'),
+        };
+
+        if schedule.next_after(0).is_none() {
+            return Err(format!("\"{}\" never happens", spec));
+        }
+
+        Ok(schedule)
+    }
+
+    /// The first time the schedule matches after `after`, both in seconds since the Unix epoch.
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        let start = (after / 60 + 1) as i64;
+        let first_day = start / 1440;
+
+        for day in first_day..first_day + SEARCH_DAYS {
+            if !self.matches_day(day) {
+                continue;
+            }
+
+            let from = if day == first_day { start % 1440 } else { 0 };
+            for minute in from..1440 {
+                if bit(self.hours, minute / 60) && bit(self.minutes, minute % 60) {
+                    return Some(((day * 1440 + minute) * 60) as u64);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn matches_day(&self, day: i64) -> bool {
+        let (_, month, dom) = date::civil_from_days(day);
+        // The epoch was a Thursday.
+        let weekday = (day + 4).rem_euclid(7);
+
+        let dom_ok = bit(self.days, i64::from(dom));
+        let weekday_ok = bit(self.weekdays, weekday);
+        let day_ok = if self.days_restricted && self.weekdays_restricted {
+            dom_ok || weekday_ok
+        } else {
+            dom_ok && weekday_ok
+        };
+
+        day_ok && bit(self.months, i64::from(month))
+    }
+}
+
+/// Some days of the week.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Days(u64);
+
+impl Days {
+    pub fn parse(spec: &str) -> Result<Days, String> {
+        let spec = match spec.trim() {
+            "weekdays" => "mon-fri",
+            "weekends" | "weekend" => "sat,sun",
+            s => s,
+        };
+        let mask = field(spec, 0, 7, WEEKDAYS)?;
+
+        Ok(Days((mask | (mask >> 7)) & 0x7f))
+    }
+
+    /// Just the day it is now.
+    pub fn today() -> Days {
+        Days(1 << (date::today() + 4).rem_euclid(7))
+    }
+
+    /// Whether any day is in both.
+    pub fn overlaps(&self, other: &Days) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+fn bit(mask: u64, n: i64) -> bool {
+    mask & (1 << n) != 0
+}
+
+/// Parse one field into a bitmask of the values from `lo` to `hi` that it matches. `names` are
+/// alternatives to the numbers, starting from `lo`.
+fn field(spec: &str, lo: u32, hi: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |v: &str| -> Result<u32, String> {
+        let n = names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(v))
+            .map(|i| i as u32 + lo)
+            .or_else(|| v.parse().ok())
+            .ok_or_else(|| format!("bad value \"{}\" in \"{}\"", v, spec))?;
+
+        if n < lo || n > hi {
+            return Err(format!(
+                "{} is out of range ({} to {}) in \"{}\"",
+                n, lo, hi, spec
+            ));
+        }
+        Ok(n)
+    };
+
+    let mut mask = 0;
+    for part in spec.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((r, s)) => {
+                let step: u32 = s
+                    .parse()
+                    .ok()
+                    .filter(|&s| s > 0)
+                    .ok_or_else(|| format!("bad step \"{}\" in \"{}\"", s, spec))?;
+                (r, Some(step))
+            }
+            None => (part, None),
+        };
+
+        let (from, to) = match range.split_once('-') {
+            _ if range == "*" => (lo, hi),
+            Some((a, b)) => (value(a)?, value(b)?),
+            // "5/15" means from 5 to the end, every 15.
+            None if step.is_some() => (value(range)?, hi),
+            None => {
+                let v = value(range)?;
+                (v, v)
+            }
+        };
+
+        if from > to {
+            return Err(format!("backwards range \"{}\" in \"{}\"", range, spec));
+        }
+
+        for n in (from..=to).step_by(step.unwrap_or(1) as usize) {
+            mask |= 1 << n;
+        }
+    }
+
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn next(spec: &str, after: u64) -> Option<u64> {
+        Schedule::parse(spec).unwrap().next_after(after)
+    }
+
+    // 2024-03-02 06:00 UTC, a Saturday.
+    const SATURDAY: u64 = 1709359200;
+
+    #[test]
+    fn next_time() {
+        // 2024-03-01 05:00, then 05:00 the next day, as it has to be after.
+        assert_eq!(next("0 5 * * *", 1709269200 - 1), Some(1709269200));
+        assert_eq!(next("0 5 * * *", 1709269200), Some(1709355600));
+        assert_eq!(next("@daily", SATURDAY), Some(1709424000));
+        assert_eq!(next("30 6 * * *", SATURDAY), Some(SATURDAY + 30 * 60));
+    }
+
+    #[test]
+    fn months_and_years_roll_over() {
+        // From 2024-01-31 23:59 to 2024-02-01 00:00.
+        assert_eq!(next("0 0 1 * *", 1706745540), Some(1706745600));
+        // From 2024-12-31 23:59:30 to 2025-01-01 00:00.
+        assert_eq!(next("* * * * *", 1735689570), Some(1735689600));
+        assert_eq!(next("@yearly", 1735689570), Some(1735689600));
+    }
+
+    #[test]
+    fn leap_days() {
+        // From 2023-02-28 to 2024-02-29, then 2028-02-29.
+        assert_eq!(next("0 0 29 feb *", 1677585600), Some(1709164800));
+        assert_eq!(next("0 0 29 2 *", 1709164800), Some(1835395200));
+    }
+
+    #[test]
+    fn steps() {
+        let parse = |spec| Schedule::parse(spec).unwrap();
+
+        assert_eq!(parse("5/15 * * * *"), parse("5,20,35,50 * * * *"));
+        assert_eq!(parse("*/15 * * * *"), parse("0,15,30,45 * * * *"));
+        assert_eq!(parse("0-30/10 * * * *"), parse("0,10,20,30 * * * *"));
+        assert_eq!(next("5/15 * * * *", SATURDAY), Some(SATURDAY + 5 * 60));
+    }
+
+    #[test]
+    fn sunday_is_0_or_7() {
+        let parse = |spec| Schedule::parse(spec).unwrap();
+
+        assert_eq!(parse("0 0 * * 7"), parse("0 0 * * 0"));
+        assert_eq!(parse("0 0 * * sun"), parse("@weekly"));
+        assert_eq!(parse("0 0 * * 5-7"), parse("0 0 * * fri,sat,sun"));
+        // Sunday 2024-03-03.
+        assert_eq!(next("0 0 * * 7", SATURDAY), Some(1709424000));
+    }
+
+    #[test]
+    fn either_day_will_do_when_both_are_given() {
+        // Monday 2024-03-04 comes before the 1st of April.
+        assert_eq!(next("0 0 1 * mon", SATURDAY), Some(1709510400));
+        assert_eq!(next("0 0 1 * *", SATURDAY), Some(1711929600));
+        // A "*" day of the month doesn't count as given, even with a step, so both have to match:
+        // the first Monday on an odd day is the 11th.
+        assert_eq!(next("0 0 */2 * mon", SATURDAY), Some(1710115200));
+        assert_eq!(next("0 0 */2 * sun", SATURDAY), Some(1709424000));
+    }
+
+    #[test]
+    fn mistakes() {
+        for spec in [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "x * * * *",
+            "* * * smarch *",
+        ] {
+            assert!(Schedule::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn never() {
+        for spec in ["0 0 30 feb *", "0 0 31 4 *"] {
+            let e = Schedule::parse(spec).unwrap_err();
+            assert!(e.contains("never happens"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn days() {
+        let parse = |spec| Days::parse(spec).unwrap();
+
+        assert_eq!(parse("weekdays"), parse("mon-fri"));
+        assert_eq!(parse("weekdays"), parse("1,2,3,4,5"));
+        assert_eq!(parse("weekends"), parse("sat,sun"));
+        assert_eq!(parse("weekend"), parse("6-7"));
+        assert_eq!(parse("Sun"), parse("0"));
+
+        assert!(parse("weekends").overlaps(&parse("sun")));
+        assert!(!parse("weekends").overlaps(&parse("weekdays")));
+        assert!(parse("mon,sat").overlaps(&parse("weekdays")));
+
+        assert!(Days::parse("someday").is_err());
+        assert!(Days::parse("8").is_err());
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde::de::{self, Deserializer, Visitor};
+use serde_json::{Map, Value, json};
+
+use crate::config;
+
+// What settings there are, for "graplsub config check" to spot ones it doesn't know and "graplsub
+// config schema" to describe as a JSON Schema. Rather than being a list to keep up to date, it's
+// all had from Config itself: the names of the settings are what serde's derived code says its
+// fields are, and what each will take is found by trying values on it. Anything that takes more
+// than a plain string, number, length of time, choice or true or false, like GRAPLSUB_RULES, is
+// only a string here, and left to "config check" to find fault with.
+
+// What a setting that takes anything at all is tried with.
+const PROBE: &str = "graplsub probe";
+
+/// A Deserializer that only notes the names that a struct's fields or an enum's variants are
+/// deserialised from, which serde hands over before asking for anything else.
+struct Names<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for Names<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct or an enum"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("only wanted the names"))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        variants: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = variants;
+        Err(de::Error::custom("only wanted the names"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map identifier ignored_any
+    }
+}
+
+/// The names of T's fields, or its variants if it's an enum.
+fn names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut names: &'static [&'static str] = &[];
+    // It's meant to fail, once it has the names.
+    let _ = T::deserialize(Names(&mut names));
+    names
+}
+
+/// The variable for a field of Config.
+fn variable(field: &str) -> String {
+    format!("GRAPLSUB_{}", field.to_uppercase())
+}
+
+/// Every GRAPLSUB_* variable there is, including GRAPLSUB_CONFIG_FILE, which is only looked for in
+/// the environment.
+pub fn variables() -> Vec<String> {
+    names::<config::Config>()
+        .iter()
+        .map(|f| variable(f))
+        .chain(["GRAPLSUB_CONFIG_FILE".to_string()])
+        .collect()
+}
+
+/// What a setting takes.
+enum Kind {
+    Text,
+    Bool,
+    Integer,
+    Number,
+    // Like "90", "10m" or "2h".
+    Duration,
+    Choice(&'static [&'static str]),
+    // Something with its own format, like GRAPLSUB_SCHEDULE.
+    Other,
+}
+
+/// The choices for the settings that are one of an enum's variants.
+fn choices(field: &str) -> Option<&'static [&'static str]> {
+    Some(match field {
+        "auth_mode" => names::<config::AuthMode>(),
+        "compilations" => names::<config::Compilations>(),
+        "incomplete_albums" => names::<config::IncompleteAlbums>(),
+        "log" => names::<config::Log>(),
+        "mode" => names::<config::Mode>(),
+        "notify_on" => names::<config::NotifyOn>(),
+        "unrated" => names::<config::Unrated>(),
+        _ => return None,
+    })
+}
+
+/// Enough settings for a configuration to be complete, and the fields that have to be set for it
+/// to be.
+fn minimal() -> (HashMap<String, String>, Vec<&'static str>) {
+    let mut vars = HashMap::new();
+    let mut required = Vec::new();
+
+    while let Err(envy::Error::MissingValue(field)) = config::from_vars(vars.clone()) {
+        let Some(&field) = names::<config::Config>().iter().find(|&&f| f == field) else {
+            break;
+        };
+        vars.insert(variable(field), PROBE.to_string());
+        required.push(field);
+    }
+
+    (vars, required)
+}
+
+/// Find out what a field takes by trying values on it, on top of a configuration that's otherwise
+/// complete.
+fn kind(base: &HashMap<String, String>, field: &str) -> Kind {
+    if let Some(variants) = choices(field) {
+        return Kind::Choice(variants);
+    }
+
+    let takes = |value: &str| {
+        let mut vars = base.clone();
+        vars.insert(variable(field), value.to_string());
+        config::from_vars(vars).is_ok()
+    };
+
+    if takes(PROBE) {
+        Kind::Text
+    } else if takes("true") && takes("false") {
+        Kind::Bool
+    } else if takes("7m") {
+        Kind::Duration
+    } else if takes("0.5") {
+        Kind::Number
+    } else if takes("7") {
+        Kind::Integer
+    } else {
+        Kind::Other
+    }
+}
+
+/// The JSON Schema for one setting. Values are strings, as they are in the config file.
+fn property(kind: Kind) -> Value {
+    match kind {
+        Kind::Text | Kind::Other => json!({ "type": "string" }),
+        Kind::Bool => json!({ "type": "string", "enum": ["true", "false"] }),
+        Kind::Integer => json!({ "type": "string", "pattern": "^[0-9]+$" }),
+        Kind::Number => json!({ "type": "string", "pattern": "^[0-9]+(\\.[0-9]+)?$" }),
+        Kind::Duration => json!({ "type": "string", "pattern": "^[0-9]+[smhd]?$" }),
+        Kind::Choice(variants) => json!({ "type": "string", "enum": variants }),
+    }
+}
+
+/// A JSON Schema for the settings, as an object of GRAPLSUB_* variables and their values. The
+/// shared settings go at the top, and each profile's under "profiles", by name. Every setting is
+/// optional there, as the ones that are needed can come from the environment or either place;
+/// the schema's description says which.
+pub fn schema() -> Value {
+    let (base, required) = minimal();
+
+    let mut properties = Map::new();
+    for field in names::<config::Config>() {
+        properties.insert(variable(field), property(kind(&base, field)));
+    }
+
+    let mut top = properties.clone();
+    top.insert(
+        "profiles".to_string(),
+        json!({
+            "type": "object",
+            "additionalProperties": { "$ref": "#/$defs/settings" },
+        }),
+    );
+
+    let needed: Vec<String> = required.iter().map(|f| variable(f)).collect();
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "graplsub settings",
+        "description": format!(
+            "graplsub {}'s GRAPLSUB_* settings, shared at the top and for each profile under \
+            \"profiles\". Every profile needs {} from somewhere.",
+            env!("CARGO_PKG_VERSION"),
+            needed.join(" and ")
+        ),
+        "$defs": {
+            "settings": {
+                "type": "object",
+                "properties": properties,
+                "additionalProperties": false,
+            },
+        },
+        "type": "object",
+        "properties": top,
+        "additionalProperties": false,
+    })
+}
+
+/// What's wrong with the name of a variable, if it's not one of `known`: that it isn't a setting
+/// at all, or which setting it might be a misspelling of.
+pub fn unknown(known: &[String], name: &str) -> Option<String> {
+    if known.iter().any(|k| k == name) {
+        return None;
+    }
+    if !name.starts_with("GRAPLSUB_") {
+        return Some(format!(
+            "{} isn't a GRAPLSUB_* setting, so does nothing",
+            name
+        ));
+    }
+
+    let closest = known
+        .iter()
+        .map(|k| (distance(k, name), k))
+        .min()
+        .filter(|(d, _)| *d <= 3);
+
+    Some(match closest {
+        Some((_, k)) => format!("{} isn't a setting. Did you mean {}?", name, k),
+        None => format!("{} isn't a setting", name),
+    })
+}
+
+/// How many characters have to be added, taken away or changed to turn one string into the other.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1)
+                .min(row[j] + 1)
+                .min(diagonal + usize::from(ca != cb));
+            diagonal = above;
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_of_fields_and_variants() {
+        let fields = names::<config::Config>();
+        for field in ["base_url", "user", "verbose", "max_runtime"] {
+            assert!(fields.contains(&field), "{}", field);
+        }
+        assert_eq!(
+            names::<config::AuthMode>(),
+            ["auto", "token", "password", "apikey", "header"]
+        );
+        assert!(names::<String>().is_empty());
+    }
+
+    #[test]
+    fn every_variable() {
+        let vars = variables();
+        assert!(vars.contains(&"GRAPLSUB_USER".to_string()));
+        assert!(vars.contains(&"GRAPLSUB_BASE_URL".to_string()));
+        assert!(vars.contains(&"GRAPLSUB_CONFIG_FILE".to_string()));
+        assert_eq!(vars.len(), names::<config::Config>().len() + 1);
+    }
+
+    #[test]
+    fn what_settings_take() {
+        let (base, required) = minimal();
+        assert_eq!(required, ["user"]);
+
+        let takes = |field| property(kind(&base, field));
+        assert_eq!(takes("base_url"), json!({ "type": "string" }));
+        assert_eq!(takes("verbose")["enum"], json!(["true", "false"]));
+        assert_eq!(takes("max_runtime")["pattern"], "^[0-9]+[smhd]?$");
+        assert_eq!(takes("cache_ttl_secs")["pattern"], "^[0-9]+$");
+        assert_eq!(
+            takes("auth_mode")["enum"],
+            json!(["auto", "token", "password", "apikey", "header"])
+        );
+
+        let schema = schema();
+        assert_eq!(
+            schema["properties"]["GRAPLSUB_VERBOSE"],
+            schema["$defs"]["settings"]["properties"]["GRAPLSUB_VERBOSE"]
+        );
+        assert!(schema["properties"]["profiles"].is_object());
+    }
+
+    #[test]
+    fn unknown_variables() {
+        let known = variables();
+
+        assert_eq!(unknown(&known, "GRAPLSUB_USER"), None);
+        assert_eq!(
+            unknown(&known, "GRAPLSUB_VERBOES"),
+            Some("GRAPLSUB_VERBOES isn't a setting. Did you mean GRAPLSUB_VERBOSE?".to_string())
+        );
+        assert_eq!(
+            unknown(&known, "GRAPLSUB_NOTHING_LIKE_IT"),
+            Some("GRAPLSUB_NOTHING_LIKE_IT isn't a setting".to_string())
+        );
+        assert!(
+            unknown(&known, "HOME")
+                .unwrap()
+                .contains("isn't a GRAPLSUB_* setting")
+        );
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(distance("", ""), 0);
+        assert_eq!(distance("abc", ""), 3);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("VERBOSE", "VERBOES"), 2);
+        assert_eq!(distance("café", "cafe"), 1);
+    }
+}
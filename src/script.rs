@@ -0,0 +1,216 @@
+use std::fmt;
+#[cfg(feature = "scripting")]
+use std::sync::Arc;
+#[cfg(feature = "scripting")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::api;
+#[cfg(feature = "scripting")]
+use crate::output;
+
+// GRAPLSUB_SCRIPT: a Rhai (https://rhai.rs/) script with the last word on each album, for when
+// GRAPLSUB_RULES and the rest can't say what's wanted. It's compiled once when the configuration
+// is read, then run for each album with the album, as the Subsonic API has it, in `album`. What it
+// comes to decides: true or false to keep the album or not, or a number to weigh it by, where 1 is
+// as usual and 0 is never. It's run during the selection, when only what album lists say is known,
+// and again on each chosen album once it has been fetched, when its songs are there too.
+//
+// Scripting needs graplsub built with the "scripting" feature. Without it, GRAPLSUB_SCRIPT being
+// set is a mistake in the configuration.
+
+// How much a script can do for one album before it's stopped, so that a loop that never ends
+// can't hang the selection.
+#[cfg(feature = "scripting")]
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// A compiled GRAPLSUB_SCRIPT.
+#[derive(Clone)]
+pub struct Script {
+    path: String,
+    #[cfg(feature = "scripting")]
+    engine: Arc<rhai::Engine>,
+    #[cfg(feature = "scripting")]
+    ast: Arc<rhai::AST>,
+    // Whether a failure has been warned about yet. There'd be one for every album otherwise.
+    #[cfg(feature = "scripting")]
+    warned: Arc<AtomicBool>,
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Script").field("path", &self.path).finish()
+    }
+}
+
+#[cfg(feature = "scripting")]
+impl Script {
+    /// Compile the script at `path`.
+    pub fn load(path: &str) -> Result<Script, String> {
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| output::note(text));
+
+        let ast = engine
+            .compile_file(path.into())
+            .map_err(|e| format!("couldn't load script {}: {}", path, e))?;
+
+        Ok(Script {
+            path: path.to_string(),
+            engine: Arc::new(engine),
+            ast: Arc::new(ast),
+            warned: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// How much more or less likely the script says an album is to be picked, where 1.0 is normal
+    /// and 0.0 means never. An album the script fails on is never picked, as the script presumably
+    /// wanted some albums kept out.
+    pub fn weight(&self, album: &api::Album) -> f64 {
+        match self.run(album) {
+            Ok(w) => w,
+            Err(e) => {
+                if !self.warned.swap(true, Ordering::Relaxed) {
+                    output::warn(format!(
+                        "GRAPLSUB_SCRIPT {} failed on album {} ({}), so it's left out. So is any \
+                        other album it fails on, without saying.",
+                        self.path, album.id, e
+                    ));
+                }
+                0.0
+            }
+        }
+    }
+
+    fn run(&self, album: &api::Album) -> Result<f64, String> {
+        let mut scope = rhai::Scope::new();
+        scope.push_dynamic(
+            "album",
+            rhai::serde::to_dynamic(album).map_err(|e| e.to_string())?,
+        );
+
+        let result: rhai::Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| e.to_string())?;
+
+        let weight = if let Ok(keep) = result.as_bool() {
+            if keep { 1.0 } else { 0.0 }
+        } else if let Ok(n) = result.as_int() {
+            n as f64
+        } else if let Ok(n) = result.as_float() {
+            n
+        } else {
+            return Err(format!(
+                "it came to {} rather than true, false or a number",
+                result.type_name()
+            ));
+        };
+
+        if weight >= 0.0 {
+            Ok(weight)
+        } else {
+            Err(format!("it came to {}, which is less than 0", weight))
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+impl Script {
+    pub fn load(path: &str) -> Result<Script, String> {
+        Err(format!(
+            "can't run script {}, as this graplsub was built without the \"scripting\" feature",
+            path
+        ))
+    }
+
+    /// Can't be asked, as there's no Script without scripting.
+    pub fn weight(&self, _: &api::Album) -> f64 {
+        1.0
+    }
+}
+
+#[cfg(all(test, feature = "scripting"))]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn script(source: &str) -> Script {
+        let path = crate::testing::dir().join("script.rhai");
+        fs::write(&path, source).unwrap();
+        Script::load(path.to_str().unwrap()).unwrap()
+    }
+
+    fn album(value: serde_json::Value) -> api::Album {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn keeps_or_weighs() {
+        let jazz = album(json!({ "id": "1", "genre": "Jazz", "year": 1959 }));
+        let rock = album(json!({ "id": "2", "genre": "Rock", "year": 1971 }));
+
+        let keep = script(r#"album.genre != "Rock""#);
+        assert_eq!(keep.weight(&jazz), 1.0);
+        assert_eq!(keep.weight(&rock), 0.0);
+
+        let weigh = script("if album.year < 1970 { 3 } else { 0.5 }");
+        assert_eq!(weigh.weight(&jazz), 3.0);
+        assert_eq!(weigh.weight(&rock), 0.5);
+    }
+
+    #[test]
+    fn sees_the_songs_when_there_are_some() {
+        let script = script(
+            r#"
+            if album.song == () { return true; }
+            album.song.all(|s| s.suffix == "flac")
+            "#,
+        );
+        let songs = |suffix: &str| {
+            album(json!({
+                "id": "1",
+                "song": [
+                    { "id": "a", "suffix": "flac" },
+                    { "id": "b", "suffix": suffix },
+                ],
+            }))
+        };
+
+        assert_eq!(script.weight(&album(json!({ "id": "1" }))), 1.0);
+        assert_eq!(script.weight(&songs("flac")), 1.0);
+        assert_eq!(script.weight(&songs("mp3")), 0.0);
+    }
+
+    #[test]
+    fn failing_leaves_the_album_out() {
+        let album = album(json!({ "id": "1" }));
+
+        assert_eq!(script(r#""yes""#).weight(&album), 0.0);
+        assert_eq!(script("-1").weight(&album), 0.0);
+        assert_eq!(script("throw \"no\"").weight(&album), 0.0);
+        assert_eq!(script("loop {}").weight(&album), 0.0);
+    }
+
+    #[test]
+    fn mistakes_are_found_up_front() {
+        let path = crate::testing::dir().join("script.rhai");
+        fs::write(&path, "album.genre ==").unwrap();
+
+        assert!(Script::load(path.to_str().unwrap()).is_err());
+        assert!(Script::load("/nonexistent/script.rhai").is_err());
+    }
+}
+
+#[cfg(all(test, not(feature = "scripting")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn needs_the_feature() {
+        let e = Script::load("script.rhai").unwrap_err();
+        assert!(e.contains("\"scripting\" feature"));
+    }
+}
@@ -0,0 +1,1107 @@
+use futures_util::{StreamExt, stream};
+use rand::seq::SliceRandom;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::album;
+use crate::album::{ListType, PAGE_SIZE};
+use crate::api;
+use crate::api::Client;
+use crate::artist;
+use crate::bias::Bias;
+use crate::config;
+use crate::config::Mode;
+use crate::date;
+use crate::filter;
+use crate::output;
+use crate::playlist;
+use crate::starred;
+use crate::top_songs;
+
+/// Choose which albums go in the playlist, in the order they should be played, according to
+/// GRAPLSUB_MODE.
+pub async fn albums(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<Vec<api::Album>, api::Error> {
+    // Someone who's listed the albums knows what they want.
+    if let Some(lines) = &conf.album_list {
+        return listed(client, conf, api_ver, lines).await;
+    }
+
+    // These pick songs rather than albums; see songs().
+    if matches!(conf.mode, Mode::Starred | Mode::TopSongs) {
+        return Ok(Vec::new());
+    }
+
+    let bias = Bias::gather(client, conf, api_ver).await;
+
+    match conf.mode {
+        Mode::Random => random(client, conf, api_ver, &bias).await,
+        Mode::Newest => newest(client, conf, api_ver, &bias).await,
+        Mode::Anniversary => anniversary(client, conf, api_ver, &bias).await,
+        Mode::Decades => decades(client, conf, api_ver, &bias).await,
+        Mode::Genres => genres(client, conf, api_ver, &bias).await,
+        Mode::Artists => artists(client, conf, api_ver, &bias).await,
+        Mode::Discover => discover(client, conf, api_ver, &bias).await,
+        Mode::Highest => highest(client, conf, api_ver, &bias).await,
+        Mode::Forgotten => forgotten(client, conf, api_ver, &bias).await,
+        Mode::Pool => pool(client, conf, api_ver, &bias).await,
+        // Dealt with above.
+        Mode::Starred | Mode::TopSongs => Ok(Vec::new()),
+    }
+}
+
+/// Choose the songs that go in the playlist on their own rather than as part of an album, for
+/// the modes that work that way. There are none for the rest, or when the albums have been
+/// listed.
+pub async fn songs(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<Vec<api::Song>, api::Error> {
+    if conf.album_list.is_some() {
+        return Ok(Vec::new());
+    }
+
+    let songs = match conf.mode {
+        Mode::Starred => starred::songs(client, conf, api_ver).await?,
+        Mode::TopSongs => top_songs::songs(client, conf, api_ver).await?,
+        _ => Vec::new(),
+    };
+
+    Ok(songs
+        .into_iter()
+        .filter(|s| filter::wanted_media_type(conf, s))
+        .collect())
+}
+
+/// The album by `artist` called `name`, ignoring case, if there is one.
+pub async fn find(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    artist: &str,
+    name: &str,
+) -> Result<Option<api::Album>, api::Error> {
+    let same = |a: Option<&str>, b: &str| a.is_some_and(|a| a.to_lowercase() == b.to_lowercase());
+    let (artist, name) = (artist.trim(), name.trim());
+
+    Ok(album::search(client, conf, api_ver, name)
+        .await?
+        .into_iter()
+        .find(|a| same(a.artist.as_deref(), artist) && same(a.name.as_deref(), name)))
+}
+
+/// The albums in a list from run --albums-from, in its order. Each line is an album's ID or
+/// "Artist - Album"; blank lines and ones starting with "#" are skipped. Albums that can't be found
+/// are warned about and left out, so one typo doesn't spoil the rest.
+async fn listed(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    lines: &[String],
+) -> Result<Vec<api::Album>, api::Error> {
+    let mut picked: Vec<api::Album> = Vec::new();
+    let mut seen = HashSet::new();
+
+    for line in lines
+        .iter()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    {
+        let found = match line.split_once(" - ") {
+            Some((artist, name)) => find(client, conf, api_ver, artist, name).await?,
+            None => match album::fetch(client, conf, api_ver, line).await {
+                Ok(a) => Some(a),
+                Err(e) => {
+                    output::warn(format!("Couldn't get album {}: {}", line, e));
+                    continue;
+                }
+            },
+        };
+
+        match found {
+            Some(album) if seen.insert(album.id.clone()) => picked.push(album),
+            Some(_) => {}
+            None => output::warn(format!("Couldn't find \"{}\". Leaving it out.", line)),
+        }
+    }
+
+    if picked.is_empty() {
+        return Err(api::Error::NotFound {
+            resource: "any of the albums in --albums-from".to_string(),
+        });
+    }
+
+    Ok(picked)
+}
+
+/// Pick up to `n` of the candidates at random, with each album's chances weighted by the bias.
+/// This is the Efraimidis-Spirakis method: give every album a random key of u^(1/weight) and keep
+/// those with the biggest keys. Albums with no weight at all are never picked.
+async fn weighted_sample(candidates: Vec<api::Album>, n: usize, bias: &Bias) -> Vec<api::Album> {
+    let mut keyed: Vec<(f64, api::Album)> = candidates
+        .into_iter()
+        .filter_map(|album| {
+            let weight = bias.weight(&album);
+            (weight > 0.0).then(|| (rand::random::<f64>().powf(1.0 / weight), album))
+        })
+        .collect();
+
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    // Go down the list until there are enough that the bias fully accepts.
+    let mut picked = Vec::new();
+    for (_, album) in keyed {
+        if picked.len() >= n {
+            break;
+        }
+        if bias.accepts(&album).await {
+            picked.push(album);
+        }
+    }
+
+    picked
+}
+
+/// Pick up to GRAPLSUB_NUM_ALBUMS of the candidates at random.
+async fn sample(
+    candidates: Vec<api::Album>,
+    conf: &config::Config,
+    bias: &Bias,
+) -> Vec<api::Album> {
+    weighted_sample(candidates, usize::from(conf.num_albums), bias).await
+}
+
+// When something is biasing the selection, random mode asks the server for this many times as
+// many albums as it needs so that there is something to choose between.
+const RANDOM_OVERDRAW: u16 = 3;
+
+// How many times to go back to the server for more random albums when filters turn down too many.
+const RANDOM_DRAWS: u32 = 5;
+
+// How many of the pool playlist's albums to be fetching at once.
+const POOL_IN_FLIGHT: usize = 4;
+
+/// The original and best: let the server pick some albums at random.
+async fn random(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    bias: &Bias,
+) -> Result<Vec<api::Album>, api::Error> {
+    random_accepted(
+        client,
+        conf,
+        api_ver,
+        bias,
+        usize::from(conf.num_albums),
+        &HashSet::new(),
+    )
+    .await
+}
+
+/// Up to `wanted` random albums that the bias accepts, leaving out any in `exclude`. If the filters
+/// turn down so many that there aren't enough, this draws again, up to a few times, so that the
+/// playlist doesn't come up short just because of bad luck.
+async fn random_accepted(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    bias: &Bias,
+    wanted: usize,
+    exclude: &HashSet<String>,
+) -> Result<Vec<api::Album>, api::Error> {
+    let mut picked: Vec<api::Album> = Vec::new();
+    let mut seen = exclude.clone();
+    // Albums from GRAPLSUB_INCLUDE_QUERY join the first draw, so that they're in the running
+    // whether or not the server happens to draw them.
+    let mut included = bias.included().to_vec();
+
+    for _ in 0..RANDOM_DRAWS {
+        let needed = wanted - picked.len();
+        if needed == 0 {
+            break;
+        }
+
+        // With nothing to filter on, every album will do, so there's no need to ask for extra.
+        let size = u16::try_from(needed).unwrap_or(PAGE_SIZE).min(PAGE_SIZE);
+        let size = if bias.is_neutral() && exclude.is_empty() {
+            size
+        } else {
+            size.saturating_mul(RANDOM_OVERDRAW).min(PAGE_SIZE)
+        };
+
+        // Albums already seen, which later draws can be full of, are never made into Albums.
+        let (drawn, draw) =
+            album::scan_list(client, conf, api_ver, &ListType::Random, size, 0, |page| {
+                let drawn = page.len();
+                let draw: Vec<api::Album> = page
+                    .into_iter()
+                    .filter(|a| !seen.contains(a.id.as_ref()))
+                    .map(api::Album::from)
+                    .collect();
+                (drawn, draw)
+            })
+            .await?;
+        // Fewer than asked for means that was the whole library, so drawing again won't help.
+        let exhausted = drawn < usize::from(size);
+
+        let candidates: Vec<api::Album> = draw
+            .into_iter()
+            .chain(std::mem::take(&mut included))
+            .filter(|a| seen.insert(a.id.clone()))
+            .collect();
+        picked.extend(weighted_sample(candidates, needed, bias).await);
+
+        if exhausted {
+            break;
+        }
+    }
+
+    Ok(picked)
+}
+
+/// Random albums to stand in for ones that couldn't be used, such as those that failed to fetch.
+/// They go through the same filters as any other album but, being random, not GRAPLSUB_MODE.
+pub async fn replacements(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    count: usize,
+    chosen: &[String],
+) -> Result<Vec<api::Album>, api::Error> {
+    let bias = Bias::gather(client, conf, api_ver).await;
+    let exclude: HashSet<String> = chosen.iter().cloned().collect();
+
+    random_accepted(client, conf, api_ver, &bias, count, &exclude).await
+}
+
+/// Make sure no more than `max_percent` of `albums` were also in the last playlist, `previous`.
+/// Which of the repeats stay is down to chance, and the rest are swapped, in place, for random
+/// albums that weren't in either. If there aren't enough of those the playlist is just shorter.
+pub async fn limit_overlap(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    albums: Vec<api::Album>,
+    previous: &[String],
+    max_percent: u8,
+) -> Result<Vec<api::Album>, api::Error> {
+    let (dropping, exclude) = excess_repeats(&albums, previous, max_percent);
+    if dropping.is_empty() {
+        return Ok(albums);
+    }
+
+    let fresh = replacements(client, conf, api_ver, dropping.len(), &exclude).await?;
+
+    output::note(format!(
+        "{} of the albums were in the last playlist too. Swapping {} of them for others.",
+        albums.len() * usize::from(max_percent) / 100 + dropping.len(),
+        dropping.len()
+    ));
+
+    Ok(swap_at(albums, dropping, fresh))
+}
+
+/// The positions of the albums that were in `previous` over and above the `max_percent` that are
+/// allowed to be, chosen at random and in order, and the IDs of albums that can't replace them.
+fn excess_repeats(
+    albums: &[api::Album],
+    previous: &[String],
+    max_percent: u8,
+) -> (Vec<usize>, Vec<String>) {
+    let before: HashSet<&String> = previous.iter().collect();
+    let mut repeats: Vec<usize> = albums
+        .iter()
+        .enumerate()
+        .filter(|(_, a)| before.contains(&a.id))
+        .map(|(i, _)| i)
+        .collect();
+    let allowed = albums.len() * usize::from(max_percent) / 100;
+
+    if repeats.len() <= allowed {
+        return (Vec::new(), Vec::new());
+    }
+
+    repeats.shuffle(&mut rand::rng());
+    let mut dropping = repeats.split_off(allowed);
+    dropping.sort_unstable();
+
+    let exclude = albums
+        .iter()
+        .map(|a| a.id.clone())
+        .chain(previous.iter().cloned())
+        .collect();
+
+    (dropping, exclude)
+}
+
+/// Put the `fresh` albums in place of those at each of the `dropping` positions, taking out the
+/// ones there aren't enough to replace.
+fn swap_at(
+    mut albums: Vec<api::Album>,
+    dropping: Vec<usize>,
+    fresh: Vec<api::Album>,
+) -> Vec<api::Album> {
+    let mut fresh = fresh.into_iter();
+
+    // From the end, so that taking one out doesn't move the rest.
+    for i in dropping.into_iter().rev() {
+        match fresh.next() {
+            Some(album) => albums[i] = album,
+            None => {
+                albums.remove(i);
+            }
+        }
+    }
+
+    albums
+}
+
+/// Whether offline() can choose albums the way GRAPLSUB_MODE does, rather than just at random.
+pub fn works_offline(mode: Mode) -> bool {
+    matches!(mode, Mode::Random | Mode::Decades | Mode::Genres)
+}
+
+/// Choose albums from `library` rather than asking the server, for simulating. Modes that need the
+/// server for more than the list of albums get them at random; see works_offline(). The playlist
+/// before, `previous`, is what GRAPLSUB_MAX_OVERLAP_PERCENT goes by.
+pub async fn offline(
+    conf: &config::Config,
+    library: &[api::Album],
+    bias: &Bias,
+    previous: &[String],
+) -> Vec<api::Album> {
+    let total = usize::from(conf.num_albums);
+
+    let albums = match conf.mode {
+        Mode::Decades => {
+            let mut by_decade: BTreeMap<u32, Vec<api::Album>> = BTreeMap::new();
+            for album in library {
+                if let Some(decade) = decade(album) {
+                    by_decade.entry(decade).or_default().push(album.clone());
+                }
+            }
+            let buckets = by_decade
+                .into_iter()
+                .map(|(d, albums)| (decade_weight(conf, d), albums))
+                .collect();
+
+            balanced(buckets, total, bias).await
+        }
+        Mode::Genres => {
+            let mut by_genre: BTreeMap<String, Vec<api::Album>> = BTreeMap::new();
+            for album in library {
+                if let Some(genre) = album.genre.clone().filter(|g| !g.is_empty()) {
+                    by_genre.entry(genre).or_default().push(album.clone());
+                }
+            }
+            // The server matches genres without minding case, so this does too.
+            let buckets = match &conf.genre_quota {
+                Some(quota) => quota
+                    .iter()
+                    .map(|(genre, weight)| {
+                        let albums = by_genre
+                            .iter()
+                            .filter(|(g, _)| g.eq_ignore_ascii_case(genre))
+                            .flat_map(|(_, albums)| albums.iter().cloned())
+                            .collect();
+                        (*weight, albums)
+                    })
+                    .collect(),
+                None => by_genre.into_values().map(|albums| (1, albums)).collect(),
+            };
+
+            balanced(buckets, total, bias).await
+        }
+        _ => weighted_sample(library.to_vec(), total, bias).await,
+    };
+
+    let Some(max_percent) = conf.max_overlap_percent else {
+        return albums;
+    };
+    let (dropping, exclude) = excess_repeats(&albums, previous, max_percent);
+    if dropping.is_empty() {
+        return albums;
+    }
+
+    let exclude: HashSet<String> = exclude.into_iter().collect();
+    let candidates = library
+        .iter()
+        .filter(|a| !exclude.contains(&a.id))
+        .cloned()
+        .collect();
+    let fresh = weighted_sample(candidates, dropping.len(), bias).await;
+
+    swap_at(albums, dropping, fresh)
+}
+
+/// The most recently added albums, newest first, stopping at any that were added longer ago than
+/// GRAPLSUB_ADDED_WITHIN days.
+async fn newest(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    bias: &Bias,
+) -> Result<Vec<api::Album>, api::Error> {
+    let cutoff = conf.added_within.map(date::days_ago);
+    let wanted = usize::from(conf.num_albums);
+    let mut picked = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let (fetched, recent) = album::scan_list(
+            client,
+            conf,
+            api_ver,
+            &ListType::Newest,
+            PAGE_SIZE,
+            offset,
+            |page| {
+                let fetched = page.len();
+                // The list is in order of creation so once one is too old, the rest will be too.
+                let recent: Vec<api::Album> = page
+                    .into_iter()
+                    .take_while(|a| match (&cutoff, &a.created) {
+                        (Some(cutoff), Some(created)) => {
+                            date::date_part(created) >= cutoff.as_str()
+                        }
+                        _ => true,
+                    })
+                    .map(api::Album::from)
+                    .collect();
+                (fetched, recent)
+            },
+        )
+        .await?;
+        let too_old = recent.len() < fetched;
+
+        for album in recent {
+            // Newest is newest, so the bias can't change the order, but it can still rule some out.
+            if bias.weight(&album) > 0.0 && bias.accepts(&album).await {
+                picked.push(album);
+            }
+
+            if picked.len() >= wanted {
+                return Ok(picked);
+            }
+        }
+
+        if too_old || fetched < usize::from(PAGE_SIZE) {
+            return Ok(picked);
+        }
+
+        offset += fetched;
+    }
+}
+
+/// Whether an album of this age is worth celebrating: every ten years, plus the 25th and 75th.
+fn milestone(age: i64) -> bool {
+    age > 0 && (age % 10 == 0 || age % 25 == 0)
+}
+
+/// Albums that reach a milestone age this year, e.g. released 10, 20 or 25 years ago. The server
+/// can't filter on that so this has to look at the whole library.
+async fn anniversary(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    bias: &Bias,
+) -> Result<Vec<api::Album>, api::Error> {
+    let (this_year, _, _) = date::civil_from_days(date::today());
+
+    let mut candidates = Vec::new();
+    album::each(client, conf, api_ver, |a| {
+        if a.year.is_some_and(|y| milestone(this_year - i64::from(y))) {
+            candidates.push(a);
+        }
+    })
+    .await?;
+
+    Ok(sample(candidates, conf, bias).await)
+}
+
+/// Share out `total` picks between buckets in proportion to their weights, without giving any
+/// bucket more than it has in it. Whatever a small bucket can't use is shared out among the rest.
+fn allocate(sizes: &[usize], weights: &[u32], total: usize) -> Vec<usize> {
+    let mut alloc = vec![0; sizes.len()];
+    let mut remaining = total;
+
+    while remaining > 0 {
+        let open: Vec<usize> = (0..sizes.len())
+            .filter(|&i| weights[i] > 0 && alloc[i] < sizes[i])
+            .collect();
+
+        if open.is_empty() {
+            break;
+        }
+
+        let weight_sum: u64 = open.iter().map(|&i| u64::from(weights[i])).sum();
+        let mut given = 0;
+
+        for &i in &open {
+            let share = (remaining as u64 * u64::from(weights[i]) / weight_sum) as usize;
+            let take = share.min(sizes[i] - alloc[i]);
+            alloc[i] += take;
+            given += take;
+        }
+
+        // Rounding down can leave a remainder too small for anyone's proportional share, so hand
+        // that out one at a time to whichever bucket is furthest below its fair share.
+        if given == 0 {
+            let neediest = open
+                .iter()
+                .copied()
+                .max_by(|&a, &b| {
+                    let need = |i: usize| f64::from(weights[i]) / (alloc[i] + 1) as f64;
+                    need(a).total_cmp(&need(b))
+                })
+                .unwrap();
+            alloc[neediest] += 1;
+            given = 1;
+        }
+
+        remaining -= given;
+    }
+
+    alloc
+}
+
+/// Sample from each bucket according to its weight, then shuffle the lot together so the playlist
+/// doesn't play all of one bucket before moving on to the next.
+async fn balanced(
+    buckets: Vec<(u32, Vec<api::Album>)>,
+    total: usize,
+    bias: &Bias,
+) -> Vec<api::Album> {
+    let sizes: Vec<usize> = buckets.iter().map(|(_, b)| b.len()).collect();
+    let weights: Vec<u32> = buckets.iter().map(|(w, _)| *w).collect();
+    let alloc = allocate(&sizes, &weights, total);
+
+    let mut picked: Vec<api::Album> = Vec::new();
+    for ((_, bucket), n) in buckets.into_iter().zip(alloc) {
+        picked.extend(weighted_sample(bucket, n, bias).await);
+    }
+
+    picked.shuffle(&mut rand::rng());
+    picked
+}
+
+/// Albums at random but spread across decades, so the playlist isn't dominated by whichever era
+/// makes up most of the library. Every decade gets an equal share unless GRAPLSUB_DECADE_WEIGHTS
+/// says otherwise, in which case only the decades listed there are used.
+async fn decades(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    bias: &Bias,
+) -> Result<Vec<api::Album>, api::Error> {
+    let mut by_decade: BTreeMap<u32, Vec<api::Album>> = BTreeMap::new();
+
+    album::each(client, conf, api_ver, |album| {
+        if let Some(decade) = decade(&album) {
+            by_decade.entry(decade).or_default().push(album);
+        }
+    })
+    .await?;
+
+    let buckets = by_decade
+        .into_iter()
+        .map(|(decade, albums)| (decade_weight(conf, decade), albums))
+        .collect();
+
+    Ok(balanced(buckets, usize::from(conf.num_albums), bias).await)
+}
+
+/// The decade an album came out in, like 1970, if it says what year that was.
+fn decade(album: &api::Album) -> Option<u32> {
+    album.year.filter(|&y| y > 0).map(|y| y / 10 * 10)
+}
+
+/// A decade's share of the playlist: its weight from GRAPLSUB_DECADE_WEIGHTS, where those not
+/// listed get none, or an equal share for all of them.
+fn decade_weight(conf: &config::Config, decade: u32) -> u32 {
+    match &conf.decade_weights {
+        Some(weights) => weights
+            .iter()
+            .find(|(d, _)| d.trim_end_matches('s').parse() == Ok(decade))
+            .map_or(0, |(_, w)| *w),
+        None => 1,
+    }
+}
+
+/// Albums at random with a set share for each genre, from GRAPLSUB_GENRE_QUOTA. Without a quota,
+/// every genre in the library gets an equal share.
+async fn genres(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    bias: &Bias,
+) -> Result<Vec<api::Album>, api::Error> {
+    let mut buckets = Vec::new();
+
+    match &conf.genre_quota {
+        Some(quota) => {
+            // Only the genres asked for, so let the server do the filtering.
+            for (genre, weight) in quota {
+                let albums =
+                    album::fetch_all_of(client, conf, api_ver, &ListType::ByGenre(genre)).await?;
+
+                buckets.push((*weight, albums));
+            }
+        }
+        None => {
+            let mut by_genre: BTreeMap<String, Vec<api::Album>> = BTreeMap::new();
+
+            album::each(client, conf, api_ver, |album| {
+                if let Some(genre) = album.genre.clone().filter(|g| !g.is_empty()) {
+                    by_genre.entry(genre).or_default().push(album);
+                }
+            })
+            .await?;
+
+            buckets.extend(by_genre.into_values().map(|albums| (1, albums)));
+        }
+    }
+
+    Ok(balanced(buckets, usize::from(conf.num_albums), bias).await)
+}
+
+/// "Artist radio": albums at random from the seed artists in GRAPLSUB_SEED_ARTISTS and from
+/// artists the server thinks are similar to them.
+async fn artists(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    bias: &Bias,
+) -> Result<Vec<api::Album>, api::Error> {
+    let names = conf.seed_artists.as_deref().unwrap_or_default();
+    let mut artist_ids: Vec<String> = Vec::new();
+
+    for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        match artist::find(client, conf, api_ver, name).await? {
+            Some(found) => {
+                artist_ids.push(found.id.clone());
+
+                for similar in artist::similar(client, conf, api_ver, &found.id).await? {
+                    artist_ids.push(similar.id);
+                }
+            }
+            None => output::warn(format!(
+                "Couldn't find seed artist \"{}\". Skipping it.",
+                name
+            )),
+        }
+    }
+
+    if artist_ids.is_empty() {
+        return Err(api::Error::NotFound {
+            resource: "any of the artists in GRAPLSUB_SEED_ARTISTS".to_string(),
+        });
+    }
+
+    // Similar artists often overlap, so make sure nobody is counted twice.
+    let mut seen_artists = HashSet::new();
+    let mut seen_albums = HashSet::new();
+    let mut candidates = Vec::new();
+
+    for id in artist_ids {
+        if !seen_artists.insert(id.clone()) {
+            continue;
+        }
+
+        for album in artist::albums(client, conf, api_ver, &id).await? {
+            if seen_albums.insert(album.id.clone()) {
+                candidates.push(album);
+            }
+        }
+    }
+
+    Ok(sample(candidates, conf, bias).await)
+}
+
+/// Albums that have never been played, and when those run out, the least played. Working through
+/// this every day eventually gets round the whole library.
+async fn discover(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    bias: &Bias,
+) -> Result<Vec<api::Album>, api::Error> {
+    let mut by_plays: BTreeMap<u64, Vec<api::Album>> = BTreeMap::new();
+
+    album::each(client, conf, api_ver, |album| {
+        by_plays
+            .entry(album.play_count.unwrap_or(0))
+            .or_default()
+            .push(album);
+    })
+    .await?;
+
+    let wanted = usize::from(conf.num_albums);
+    let mut picked = Vec::new();
+
+    // Fill up from the least played upwards, at random within each play count.
+    for (_, albums) in by_plays {
+        if picked.len() >= wanted {
+            break;
+        }
+
+        picked.extend(weighted_sample(albums, wanted - picked.len(), bias).await);
+    }
+
+    picked.shuffle(&mut rand::rng());
+    Ok(picked)
+}
+
+/// "Best of": albums at random from those rated at least GRAPLSUB_MIN_RATING (or rated at all, if
+/// that isn't set).
+async fn highest(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    bias: &Bias,
+) -> Result<Vec<api::Album>, api::Error> {
+    let candidates = rated(client, conf, api_ver).await?;
+
+    Ok(sample(candidates, conf, bias).await)
+}
+
+/// "Forgotten favourites": albums at random from those that are starred or rated at least
+/// GRAPLSUB_MIN_RATING, but that haven't been played for GRAPLSUB_FORGOTTEN_MONTHS. Albums the
+/// server doesn't say were ever played count as forgotten.
+async fn forgotten(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    bias: &Bias,
+) -> Result<Vec<api::Album>, api::Error> {
+    let cutoff = date::months_ago(conf.forgotten_months);
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+
+    let starred = every(client, conf, api_ver, &ListType::Starred).await?;
+    let rated = rated(client, conf, api_ver).await?;
+
+    for album in starred.into_iter().chain(rated) {
+        let lately = album
+            .played
+            .as_deref()
+            .is_some_and(|p| date::date_part(p) >= cutoff.as_str());
+
+        if !lately && seen.insert(album.id.clone()) {
+            candidates.push(album);
+        }
+    }
+
+    Ok(sample(candidates, conf, bias).await)
+}
+
+/// Albums at random from those with any songs in GRAPLSUB_POOL_PLAYLIST, so that a big hand-made
+/// playlist of candidates can be curated once and picked from each time. Albums that can't be
+/// fetched are warned about and left out of the pool.
+async fn pool(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    bias: &Bias,
+) -> Result<Vec<api::Album>, api::Error> {
+    let name = conf.pool_playlist.as_deref().unwrap_or_default();
+    let found = playlist::find(client, conf, api_ver, name).await?;
+
+    let mut seen = HashSet::new();
+    let album_ids: Vec<String> = playlist::fetch(client, conf, api_ver, &found.id)
+        .await?
+        .entry
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|s| s.album_id)
+        .filter(|id| seen.insert(id.clone()))
+        .collect();
+
+    let mut candidates = Vec::new();
+    let mut fetched = stream::iter(&album_ids)
+        .map(|id| album::fetch(client, conf, api_ver, id))
+        .buffered(POOL_IN_FLIGHT);
+    while let Some(album) = fetched.next().await {
+        match album {
+            Ok(a) => candidates.push(a),
+            Err(e) => output::warn(format!("Leaving an album out of the pool: {}", e)),
+        }
+    }
+
+    if candidates.is_empty() {
+        return Err(api::Error::NotFound {
+            resource: format!("any albums in playlist \"{}\"", found.name),
+        });
+    }
+
+    Ok(sample(candidates, conf, bias).await)
+}
+
+/// Every album in a list, page by page.
+async fn every(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    list_type: &ListType<'_>,
+) -> Result<Vec<api::Album>, api::Error> {
+    let mut albums = Vec::new();
+
+    loop {
+        let page =
+            album::fetch_list(client, conf, api_ver, list_type, PAGE_SIZE, albums.len()).await?;
+        let fetched = page.len();
+
+        albums.extend(page);
+
+        if fetched < usize::from(PAGE_SIZE) {
+            return Ok(albums);
+        }
+    }
+}
+
+/// The albums rated at least GRAPLSUB_MIN_RATING, or rated at all if that isn't set.
+async fn rated(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<Vec<api::Album>, api::Error> {
+    let min_rating = conf.min_rating.unwrap_or(1);
+    let mut candidates = Vec::new();
+
+    loop {
+        let (fetched, rated) = album::scan_list(
+            client,
+            conf,
+            api_ver,
+            &ListType::Highest,
+            PAGE_SIZE,
+            candidates.len(),
+            |page| {
+                let fetched = page.len();
+                // Highest rated come first, so once they drop below the minimum that's it.
+                let rated: Vec<api::Album> = page
+                    .into_iter()
+                    .take_while(|a| a.user_rating.unwrap_or(0) >= min_rating)
+                    .map(api::Album::from)
+                    .collect();
+                (fetched, rated)
+            },
+        )
+        .await?;
+        let below = rated.len() < fetched;
+
+        candidates.extend(rated);
+
+        if below || fetched < usize::from(PAGE_SIZE) {
+            break;
+        }
+    }
+
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{Server, conf};
+
+    fn album(id: &str, year: u32) -> api::Album {
+        serde_json::from_value(json!({ "id": id, "year": year })).unwrap()
+    }
+
+    fn library(count: usize) -> Vec<api::Album> {
+        (0..count).map(|i| album(&i.to_string(), 1970)).collect()
+    }
+
+    fn ids(albums: &[api::Album]) -> Vec<&str> {
+        albums.iter().map(|a| a.id.as_str()).collect()
+    }
+
+    #[test]
+    fn allocate_in_proportion() {
+        assert_eq!(allocate(&[10, 10], &[1, 1], 10), vec![5, 5]);
+        assert_eq!(allocate(&[10, 10], &[3, 1], 8), vec![6, 2]);
+    }
+
+    #[test]
+    fn allocate_shares_out_what_small_buckets_cannot_use() {
+        assert_eq!(allocate(&[2, 10], &[1, 1], 10), vec![2, 8]);
+        assert_eq!(allocate(&[2, 3], &[1, 1], 10), vec![2, 3]);
+    }
+
+    #[test]
+    fn allocate_gives_nothing_without_weight() {
+        assert_eq!(allocate(&[5, 5], &[0, 1], 4), vec![0, 4]);
+        assert_eq!(allocate(&[5, 5], &[0, 0], 4), vec![0, 0]);
+    }
+
+    #[test]
+    fn allocate_hands_out_the_remainder() {
+        let alloc = allocate(&[10, 10, 10], &[1, 1, 1], 4);
+
+        assert_eq!(alloc.iter().sum::<usize>(), 4);
+        assert!(alloc.iter().all(|&n| n >= 1));
+    }
+
+    #[tokio::test]
+    async fn weighted_sample_takes_up_to_n() {
+        let bias = Bias::default();
+
+        assert_eq!(weighted_sample(library(10), 4, &bias).await.len(), 4);
+        assert_eq!(weighted_sample(library(3), 4, &bias).await.len(), 3);
+        assert!(weighted_sample(library(3), 0, &bias).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn weighted_sample_never_picks_what_has_no_weight() {
+        let bias = Bias::offline(&conf(&[("GRAPLSUB_COMPILATIONS", "exclude")]));
+        let mut candidates = library(5);
+        for album in &mut candidates[..3] {
+            album.is_compilation = Some(true);
+        }
+
+        let picked = weighted_sample(candidates, 5, &bias).await;
+
+        assert_eq!(picked.len(), 2);
+        assert!(picked.iter().all(|a| a.is_compilation.is_none()));
+    }
+
+    #[test]
+    fn excess_repeats_none_within_the_limit() {
+        let albums = library(10);
+        let previous = vec!["0".to_string(), "1".to_string()];
+
+        assert_eq!(excess_repeats(&albums, &previous, 20), (vec![], vec![]));
+    }
+
+    #[test]
+    fn excess_repeats_over_the_limit() {
+        let albums = library(10);
+        let previous: Vec<String> = ["0", "2", "4", "6", "8", "elsewhere"]
+            .map(String::from)
+            .to_vec();
+
+        let (dropping, exclude) = excess_repeats(&albums, &previous, 20);
+
+        // Two of the five can stay.
+        assert_eq!(dropping.len(), 3);
+        assert!(dropping.is_sorted());
+        assert!(dropping.iter().all(|i| i % 2 == 0));
+        assert_eq!(exclude.len(), 16);
+        assert!(exclude.contains(&"1".to_string()));
+        assert!(exclude.contains(&"elsewhere".to_string()));
+    }
+
+    #[test]
+    fn swap_at_replaces_then_removes() {
+        let albums = library(4);
+        let fresh = vec![album("new", 1980)];
+
+        let swapped = swap_at(albums, vec![1, 3], fresh);
+
+        // From the end, so the last one dropped is the one replaced.
+        assert_eq!(ids(&swapped), vec!["0", "2", "new"]);
+    }
+
+    #[test]
+    fn swap_at_keeps_the_order() {
+        let fresh = vec![album("x", 1980), album("y", 1980)];
+
+        let swapped = swap_at(library(4), vec![0, 2], fresh);
+
+        assert_eq!(ids(&swapped), vec!["y", "1", "x", "3"]);
+    }
+
+    #[test]
+    fn decades_from_years() {
+        assert_eq!(decade(&album("a", 1979)), Some(1970));
+        assert_eq!(decade(&album("a", 2000)), Some(2000));
+        assert_eq!(decade(&album("a", 0)), None);
+    }
+
+    #[test]
+    fn decade_weights_leave_out_those_not_listed() {
+        let weighted = conf(&[("GRAPLSUB_DECADE_WEIGHTS", "1970s:3,1980:1")]);
+
+        assert_eq!(decade_weight(&weighted, 1970), 3);
+        assert_eq!(decade_weight(&weighted, 1980), 1);
+        assert_eq!(decade_weight(&weighted, 1990), 0);
+        assert_eq!(decade_weight(&conf(&[]), 1990), 1);
+    }
+
+    #[tokio::test]
+    async fn offline_spreads_across_decades() {
+        let conf = conf(&[("GRAPLSUB_MODE", "decades"), ("GRAPLSUB_NUM_ALBUMS", "9")]);
+        let library: Vec<api::Album> = [1965, 1975, 1985]
+            .iter()
+            .flat_map(|&year| (0..10).map(move |i| album(&format!("{}-{}", year, i), year)))
+            .collect();
+
+        let picked = offline(&conf, &library, &Bias::default(), &[]).await;
+
+        assert_eq!(picked.len(), 9);
+        for year in ["1965", "1975", "1985"] {
+            assert_eq!(picked.iter().filter(|a| a.id.starts_with(year)).count(), 3);
+        }
+    }
+
+    #[tokio::test]
+    async fn offline_limits_overlap() {
+        let conf = conf(&[
+            ("GRAPLSUB_NUM_ALBUMS", "10"),
+            ("GRAPLSUB_MAX_OVERLAP_PERCENT", "0"),
+        ]);
+        let library = library(20);
+        let previous: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+
+        let picked = offline(&conf, &library, &Bias::default(), &previous).await;
+
+        // Only the ten that weren't in the last playlist are left to pick.
+        assert_eq!(picked.len(), 10);
+        assert!(picked.iter().all(|a| !previous.contains(&a.id)));
+    }
+
+    #[tokio::test]
+    async fn offline_gets_shorter_without_replacements() {
+        let conf = conf(&[
+            ("GRAPLSUB_NUM_ALBUMS", "10"),
+            ("GRAPLSUB_MAX_OVERLAP_PERCENT", "50"),
+        ]);
+        let library = library(10);
+        let previous: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+
+        assert_eq!(
+            offline(&conf, &library, &Bias::default(), &previous)
+                .await
+                .len(),
+            5
+        );
+    }
+
+    #[tokio::test]
+    async fn listed_ids_reach_the_server_as_they_are() {
+        let server = Server::start(|call| {
+            let id = call.get("id").unwrap_or_default();
+            (Duration::ZERO, json!({ "album": { "id": id } }))
+        })
+        .await;
+        let conf = conf(&[("GRAPLSUB_BASE_URL", &server.base_url)]);
+        let client = api::create_client(&conf).unwrap();
+        let lines = ["a&id=b", "# not this", "", " with space ", "c#d"].map(String::from);
+
+        let picked = listed(&client, &conf, "1.16.1", &lines).await.unwrap();
+
+        assert_eq!(ids(&picked), vec!["a&id=b", "with space", "c#d"]);
+        for call in server.calls_to("getAlbum") {
+            assert_eq!(call.all("id").len(), 1);
+        }
+    }
+}
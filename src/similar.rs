@@ -0,0 +1,101 @@
+use rand::seq::SliceRandom;
+use std::collections::HashSet;
+
+use crate::api;
+use crate::api::Client;
+use crate::config;
+use crate::filter;
+
+// The most similar songs to ask for from any one artist, so that the padding comes from a spread
+// of artists rather than all from the first one.
+const MAX_PER_ARTIST: usize = 10;
+
+async fn similar_songs(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    artist_id: &str,
+    count: usize,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/getSimilarSongs2?u={}&f=json&v={}&c={}&id={}&count={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        artist_id,
+        count
+    );
+
+    api::get(client, &url).await
+}
+
+fn check_similar_songs_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
+    api::check_generic_response(resp)?;
+
+    if resp.subsonic_response.similar_songs.is_none() {
+        return Err(api::RespParseError::MissingSimilarSongs {
+            excerpt: resp.excerpt.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Up to `wanted` songs by artists similar to those of the chosen albums, for padding the playlist
+/// out to GRAPLSUB_PAD_TO_SONGS. Songs whose IDs are in `exclude` (i.e. already in the playlist)
+/// are skipped. There may well be fewer than asked for, as servers only know about similar artists
+/// if they're hooked up to something like Last.fm.
+pub async fn padding(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    albums: &[api::Album],
+    wanted: usize,
+    exclude: &HashSet<String>,
+) -> Result<Vec<api::Song>, api::Error> {
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut artists: Vec<&str> = albums
+        .iter()
+        .filter_map(|a| a.artist_id.as_deref())
+        .filter(|id| seen.insert(id))
+        .collect();
+    artists.shuffle(&mut rand::rng());
+
+    let mut picked: Vec<api::Song> = Vec::new();
+    let mut picked_ids: HashSet<String> = HashSet::new();
+
+    for artist_id in artists {
+        if picked.len() >= wanted {
+            break;
+        }
+
+        let count = (wanted - picked.len()).min(MAX_PER_ARTIST);
+        let subsonic_response = similar_songs(client, conf, api_ver, artist_id, count).await?;
+
+        check_similar_songs_response(&subsonic_response)?;
+
+        // Safe to unwrap() as we already checked it was Some().
+        let songs = subsonic_response
+            .subsonic_response
+            .similar_songs
+            .unwrap()
+            .song
+            .unwrap_or_default();
+
+        for song in songs {
+            if picked.len() >= wanted {
+                break;
+            }
+
+            if !exclude.contains(&song.id)
+                && filter::wanted_media_type(conf, &song)
+                && picked_ids.insert(song.id.clone())
+            {
+                picked.push(song);
+            }
+        }
+    }
+
+    Ok(picked)
+}
@@ -0,0 +1,277 @@
+use std::collections::{HashMap, HashSet};
+
+use thiserror::Error;
+
+use crate::album;
+use crate::api;
+use crate::bias::Bias;
+use crate::config;
+use crate::output::{self, Stream, Style};
+use crate::select;
+use crate::stats;
+
+// graplsub simulate: the selection made over and over against the library as it was last cached,
+// without going near the server, to see what some weights and filters make of it over time before
+// trying them for real. Each run is as if the one before it had been built, for
+// GRAPLSUB_MAX_OVERLAP_PERCENT. Only what can be worked out from the cache is simulated: anything
+// that needs the server or somewhere else is left out, with a warning.
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(
+        "There's no cached library to simulate with. Set GRAPLSUB_CACHE_DIR and build a playlist \
+        in a mode that goes through the whole library, like decades, first. With \
+        GRAPLSUB_MUSIC_FOLDER it has to be the folder's ID."
+    )]
+    NoLibrary,
+}
+
+/// Settings that make a difference to the selection but can't be simulated.
+fn unsimulated(conf: &config::Config) -> Vec<&'static str> {
+    [
+        ("GRAPLSUB_ALBUM_HOOK", conf.album_hook.is_some()),
+        ("GRAPLSUB_INCLUDE_QUERY", conf.include_query.is_some()),
+        (
+            "GRAPLSUB_LASTFM_FAVOURITES",
+            conf.lastfm_favourites.is_some(),
+        ),
+        (
+            "GRAPLSUB_LISTENBRAINZ_USER",
+            conf.listenbrainz_user.is_some(),
+        ),
+        ("GRAPLSUB_STARRED_ARTISTS_ONLY", conf.starred_artists_only),
+    ]
+    .into_iter()
+    .filter(|(_, set)| *set)
+    .map(|(name, _)| name)
+    .collect()
+}
+
+/// What the simulated runs picked.
+#[derive(Default)]
+struct Tally<'a> {
+    runs: u32,
+    // How many albums were picked in all, counting repeats.
+    picks: usize,
+    library: usize,
+    // How many albums in the library the filters would let be picked at all.
+    eligible: usize,
+    // How many times each album was picked, by ID.
+    times: HashMap<&'a str, usize>,
+    genres: HashMap<&'a str, usize>,
+    // The share of each playlist that was in the one before, for all but the first.
+    repeats: Vec<f64>,
+    by_id: HashMap<&'a str, &'a api::Album>,
+}
+
+/// Make `runs` selections from the cached library and say how they went: how much of the library
+/// they got round to, how much each repeated the one before, and what genres they were.
+pub async fn run(conf: &config::Config, runs: u32) -> Result<(), Error> {
+    let mut conf = conf.clone();
+    // There's no asking the server which folder a name means.
+    conf.music_folder_id = conf.music_folder.clone();
+
+    let library = album::cached(&conf).ok_or(Error::NoLibrary)?;
+
+    if !select::works_offline(conf.mode) {
+        output::warn("This mode needs the server, so albums are picked at random instead.");
+    }
+    for name in unsimulated(&conf) {
+        output::warn(format!(
+            "{} needs more than the cache, so is left out.",
+            name
+        ));
+    }
+
+    let bias = Bias::offline(&conf);
+    let mut tally = Tally {
+        runs,
+        library: library.len(),
+        eligible: library.iter().filter(|a| bias.weight(a) > 0.0).count(),
+        by_id: library.iter().map(|a| (a.id.as_str(), a)).collect(),
+        ..Default::default()
+    };
+    let mut previous: Vec<String> = Vec::new();
+
+    for i in 0..runs {
+        let albums = select::offline(&conf, &library, &bias, &previous).await;
+        let ids: Vec<String> = albums.into_iter().map(|a| a.id).collect();
+
+        if i > 0 && !ids.is_empty() {
+            tally.repeats.push(repeated(&previous, &ids));
+        }
+        tally.add(&ids);
+
+        previous = ids;
+    }
+
+    tally.print();
+
+    Ok(())
+}
+
+/// `n` as a percentage of `of`, which is nothing if `of` is.
+fn percent(n: usize, of: usize) -> f64 {
+    if of == 0 {
+        0.0
+    } else {
+        100.0 * n as f64 / of as f64
+    }
+}
+
+/// The share of `ids`, from 0 to 1, that were in `previous` too.
+fn repeated(previous: &[String], ids: &[String]) -> f64 {
+    if ids.is_empty() {
+        return 0.0;
+    }
+    let before: HashSet<&String> = previous.iter().collect();
+    ids.iter().filter(|id| before.contains(id)).count() as f64 / ids.len() as f64
+}
+
+impl Tally<'_> {
+    /// Count the albums picked in one run.
+    fn add(&mut self, ids: &[String]) {
+        for id in ids {
+            // Picked from the library, so always in it.
+            let Some((&id, album)) = self.by_id.get_key_value(id.as_str()) else {
+                continue;
+            };
+            *self.times.entry(id).or_default() += 1;
+            let genre = album.genre.as_deref().filter(|g| !g.is_empty());
+            *self.genres.entry(genre.unwrap_or("Unknown")).or_default() += 1;
+            self.picks += 1;
+        }
+    }
+
+    /// The average share of each playlist that was in the one before, as a percentage.
+    fn repeat_percent(&self) -> Option<f64> {
+        match self.repeats.len() {
+            0 => None,
+            n => Some(100.0 * self.repeats.iter().sum::<f64>() / n as f64),
+        }
+    }
+
+    fn print(&self) {
+        let label = |text: &str| output::paint(Stream::Stdout, Style::Dim, text);
+
+        println!(
+            "{}",
+            output::paint(
+                Stream::Stdout,
+                Style::Bold,
+                format!(
+                    "Simulated {} runs, picking {} albums in all from a library of {}, {} of \
+                    which could be picked.",
+                    self.runs, self.picks, self.library, self.eligible
+                )
+            )
+        );
+
+        println!(
+            "{} {} different albums, {:.1}% of those that could be picked",
+            label("Coverage:"),
+            self.times.len(),
+            percent(self.times.len(), self.eligible)
+        );
+
+        let repeated = match self.repeat_percent() {
+            None => "nothing to compare with".to_string(),
+            Some(p) => format!(
+                "{:.1}% of each playlist was in the one before, on average",
+                p
+            ),
+        };
+        println!("{} {}", label("Repeats:"), repeated);
+
+        let again = self.times.values().filter(|&&n| n > 1).count();
+        println!(
+            "{} {} albums, {:.1}% of those picked",
+            label("Picked more than once:"),
+            again,
+            percent(again, self.times.len())
+        );
+
+        // Ties go to the first ID, so that it's the same every time.
+        if let Some((id, n)) = self
+            .times
+            .iter()
+            .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)))
+            .filter(|(_, n)| **n > 1)
+        {
+            println!(
+                "{} {}, {} times",
+                label("Picked most:"),
+                stats::describe(self.by_id[id]),
+                n
+            );
+        }
+
+        println!(
+            "{} {}",
+            label("Albums by genre:"),
+            stats::top(&self.genres, |g| g.to_string())
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn strings(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn percentages() {
+        assert_eq!(percent(1, 4), 25.0);
+        assert_eq!(percent(3, 3), 100.0);
+        assert_eq!(percent(5, 0), 0.0);
+    }
+
+    #[test]
+    fn repeated_share() {
+        let previous = strings(&["a", "b", "c"]);
+
+        assert_eq!(repeated(&previous, &strings(&["a", "x", "c", "y"])), 0.5);
+        assert_eq!(repeated(&previous, &strings(&["x"])), 0.0);
+        assert_eq!(repeated(&[], &strings(&["a"])), 0.0);
+        assert_eq!(repeated(&previous, &[]), 0.0);
+    }
+
+    #[test]
+    fn tally_counts_picks_and_genres() {
+        let library: Vec<api::Album> = [
+            json!({ "id": "a", "genre": "Jazz" }),
+            json!({ "id": "b", "genre": "" }),
+            json!({ "id": "c" }),
+        ]
+        .into_iter()
+        .map(|a| serde_json::from_value(a).unwrap())
+        .collect();
+        let mut tally = Tally {
+            by_id: library.iter().map(|a| (a.id.as_str(), a)).collect(),
+            ..Default::default()
+        };
+
+        tally.add(&strings(&["a", "b"]));
+        tally.add(&strings(&["a", "c", "not in the library"]));
+
+        assert_eq!(tally.picks, 4);
+        assert_eq!(tally.times["a"], 2);
+        assert_eq!(tally.times["b"], 1);
+        assert_eq!(tally.genres["Jazz"], 2);
+        assert_eq!(tally.genres["Unknown"], 2);
+    }
+
+    #[test]
+    fn repeat_percent_is_an_average() {
+        let mut tally = Tally::default();
+        assert_eq!(tally.repeat_percent(), None);
+
+        tally.repeats = vec![0.5, 0.0, 1.0];
+        assert_eq!(tally.repeat_percent(), Some(50.0));
+    }
+}
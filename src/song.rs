@@ -0,0 +1,126 @@
+use crate::api;
+use crate::api::Client;
+use crate::artist;
+use crate::config;
+
+// Songs by what they are rather than by the server's IDs for them, which can change when a library
+// is rescanned and mean nothing at all to another server.
+
+// How many songs to ask search3 for when looking for one in particular.
+const SEARCH_SONGS: u16 = 50;
+
+// How far apart two rips of the same recording can be in length, in seconds.
+const LENGTH_SLACK: u32 = 2;
+
+// The error a server gives for something that doesn't exist.
+const NOT_FOUND: u32 = 70;
+
+async fn search(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    query: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/search3?u={}&f=json&v={}&c={}&query={}&artistCount=0&albumCount=0&songCount={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        api::encode(query),
+        SEARCH_SONGS
+    );
+
+    api::get(client, &url).await
+}
+
+/// Whether the server still has a song, by asking for it. A song that's gone, say because its file
+/// was deleted or moved, is "not found"; any other error is passed on.
+pub async fn exists(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    id: &str,
+) -> Result<bool, api::Error> {
+    let url = format!(
+        "{}/rest/getSong?u={}&f=json&v={}&c={}&id={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        id
+    );
+    let subsonic_response = api::get(client, &url).await?;
+
+    match api::check_generic_response(&subsonic_response) {
+        Ok(_) => Ok(true),
+        Err(api::RespParseError::ResponseNotOk {
+            code: NOT_FOUND, ..
+        }) => Ok(false),
+        Err(e) => Err(api::Error::from(e).context(format!("song {}", id))),
+    }
+}
+
+/// What makes a song the same recording as another: its MusicBrainz ID if the server says, and
+/// otherwise its artist, title and length, which catches the same track on an album and a
+/// compilation.
+pub fn recording(song: &api::Song) -> String {
+    match &song.music_brainz_id {
+        Some(mbid) if !mbid.is_empty() => mbid.clone(),
+        // Without a title there's nothing to go on, so it's only the same as itself.
+        _ if song.title.is_none() => song.id.clone(),
+        _ => format!(
+            "{}\n{}\n{}",
+            song.artist.as_deref().unwrap_or_default().to_lowercase(),
+            song.title.as_deref().unwrap_or_default().to_lowercase(),
+            song.duration.unwrap_or_default()
+        ),
+    }
+}
+
+/// Whether two songs, which might be from different servers, are the same recording. If both have
+/// a MusicBrainz ID then that decides it. Otherwise the artist and title have to match and the
+/// lengths be close, as different rips are rarely exactly the same.
+pub fn same(a: &api::Song, b: &api::Song) -> bool {
+    let lower = |s: &Option<String>| s.as_deref().unwrap_or_default().to_lowercase();
+
+    match (&a.music_brainz_id, &b.music_brainz_id) {
+        (Some(x), Some(y)) if !x.is_empty() && !y.is_empty() => x == y,
+        _ => {
+            a.title.is_some()
+                && lower(&a.title) == lower(&b.title)
+                && lower(&a.artist) == lower(&b.artist)
+                && match (a.duration, b.duration) {
+                    (Some(x), Some(y)) => x.abs_diff(y) <= LENGTH_SLACK,
+                    _ => true,
+                }
+        }
+    }
+}
+
+/// The song in this library that's the same recording as `song`, which might be from another
+/// server, if there is one.
+pub async fn find_same(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    song: &api::Song,
+) -> Result<Option<api::Song>, api::Error> {
+    let Some(title) = &song.title else {
+        return Ok(None);
+    };
+
+    let subsonic_response = search(client, conf, api_ver, title).await?;
+
+    artist::check_search_response(&subsonic_response)?;
+
+    // Safe to unwrap() as we already checked it was Some().
+    Ok(subsonic_response
+        .subsonic_response
+        .search_result
+        .unwrap()
+        .song
+        .unwrap_or_default()
+        .into_iter()
+        .find(|s| same(s, song)))
+}
@@ -0,0 +1,95 @@
+use rand::seq::SliceRandom;
+use std::collections::HashSet;
+
+use crate::api;
+use crate::api::Client;
+use crate::config;
+
+// What's been starred, i.e. marked as a favourite: every starred song, for starred mode's playlist
+// of songs rather than albums, and the starred artists, for GRAPLSUB_STARRED_ARTISTS_ONLY. The
+// server keeps the list, so it's one request however big the library is.
+
+async fn starred(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let mut url = format!(
+        "{}/rest/getStarred2?u={}&f=json&v={}&c={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name)
+    );
+
+    if let Some(id) = &conf.music_folder_id {
+        url.push_str(&format!("&musicFolderId={}", id));
+    }
+
+    api::get(client, &url).await
+}
+
+fn check_starred_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
+    api::check_generic_response(resp)?;
+
+    if resp.subsonic_response.starred.is_none() {
+        return Err(api::RespParseError::MissingStarred {
+            excerpt: resp.excerpt.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+/// The IDs of the starred artists.
+pub async fn artist_ids(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<HashSet<String>, api::Error> {
+    let subsonic_response = starred(client, conf, api_ver).await?;
+
+    check_starred_response(&subsonic_response)?;
+
+    // Safe to unwrap() as we already checked it was Some().
+    Ok(subsonic_response
+        .subsonic_response
+        .starred
+        .unwrap()
+        .artist
+        .unwrap_or_default()
+        .into_iter()
+        .map(|a| a.id)
+        .collect())
+}
+
+/// Every starred song, in the order the server gives them or shuffled with GRAPLSUB_SHUFFLE.
+pub async fn songs(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<Vec<api::Song>, api::Error> {
+    let subsonic_response = starred(client, conf, api_ver).await?;
+
+    check_starred_response(&subsonic_response)?;
+
+    // Safe to unwrap() as we already checked it was Some().
+    let mut songs = subsonic_response
+        .subsonic_response
+        .starred
+        .unwrap()
+        .song
+        .unwrap_or_default();
+
+    if songs.is_empty() {
+        return Err(api::Error::NotFound {
+            resource: "any starred songs".to_string(),
+        });
+    }
+
+    if conf.shuffle {
+        songs.shuffle(&mut rand::rng());
+    }
+
+    Ok(songs)
+}
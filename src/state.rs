@@ -0,0 +1,920 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::types::Type;
+use rusqlite::{Connection, ToSql, Transaction, params};
+use serde::Deserialize;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::config;
+use crate::feedback;
+use crate::output;
+
+// What graplsub remembers between runs, in an SQLite database at GRAPLSUB_STATE_FILE. It's read
+// into a State in one go, and State::save() only writes what has changed since, so that anything
+// else written in the meantime, like a ban while a playlist is being built, isn't lost. Every run
+// is kept.
+//
+// The schema is changed by adding to MIGRATIONS, never by editing what's already there, as each
+// database remembers how many of them it has had in its user_version. State files from before it
+// was a database were JSON, and are moved into one the first time they're opened.
+
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE runs (
+        id TEXT PRIMARY KEY,
+        started INTEGER NOT NULL,
+        finished INTEGER,
+        mode TEXT NOT NULL,
+        playlist_name TEXT NOT NULL,
+        playlist_id TEXT,
+        temporary TEXT
+    );
+    CREATE INDEX runs_by_start ON runs (started);
+
+    CREATE TABLE run_seeds (
+        run TEXT NOT NULL REFERENCES runs (id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        artist TEXT NOT NULL,
+        PRIMARY KEY (run, position)
+    );
+    CREATE TABLE run_albums (
+        run TEXT NOT NULL REFERENCES runs (id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        album TEXT NOT NULL,
+        PRIMARY KEY (run, position)
+    );
+    CREATE TABLE run_songs (
+        run TEXT NOT NULL REFERENCES runs (id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        song TEXT NOT NULL,
+        PRIMARY KEY (run, position)
+    );
+    CREATE TABLE run_identities (
+        run TEXT NOT NULL REFERENCES runs (id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        identity TEXT NOT NULL,
+        PRIMARY KEY (run, position)
+    );
+    CREATE TABLE problems (
+        run TEXT NOT NULL REFERENCES runs (id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        album TEXT NOT NULL,
+        problem TEXT NOT NULL,
+        PRIMARY KEY (run, position)
+    );
+    CREATE TABLE placed (
+        run TEXT NOT NULL REFERENCES runs (id) ON DELETE CASCADE,
+        album TEXT NOT NULL,
+        identity TEXT NOT NULL,
+        songs INTEGER NOT NULL,
+        plays INTEGER NOT NULL,
+        PRIMARY KEY (run, album)
+    );
+
+    CREATE TABLE batch_sizes (
+        base_url TEXT PRIMARY KEY,
+        size INTEGER NOT NULL
+    );
+    CREATE TABLE banned (
+        id TEXT PRIMARY KEY,
+        identity TEXT NOT NULL,
+        title TEXT NOT NULL
+    );
+    CREATE TABLE skips (
+        identity TEXT PRIMARY KEY,
+        times INTEGER NOT NULL
+    );
+"#];
+
+// How long to wait for another graplsub to finish writing to the same state file.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// What every SQLite database starts with, to tell one from a JSON state file.
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+/// What graplsub remembers between runs. The Deserialize is for state files from when they were
+/// JSON.
+#[derive(Debug, Default, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub runs: Vec<Run>,
+    // The most songs each server, by GRAPLSUB_BASE_URL, will take being added in one request, for
+    // those that won't take the usual number.
+    #[serde(default)]
+    pub batch_sizes: BTreeMap<String, usize>,
+    // Albums never to pick, from graplsub ban.
+    #[serde(default)]
+    pub banned: Vec<Ban>,
+    // How many playlists in a row each album, by album::identity(), has gone unplayed in, for
+    // GRAPLSUB_LEARN_FROM_PLAYS. Albums that were played aren't in here.
+    #[serde(default)]
+    pub skips: BTreeMap<String, u32>,
+    // What the database had when this was loaded or last saved, to tell what has changed since.
+    #[serde(skip)]
+    saved: Saved,
+}
+
+#[derive(Debug, Default)]
+struct Saved {
+    runs: HashMap<String, Run>,
+    batch_sizes: BTreeMap<String, usize>,
+    banned: HashMap<String, Ban>,
+    skips: BTreeMap<String, u32>,
+}
+
+/// An album that's never to be picked.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Ban {
+    pub id: String,
+    // From album::identity(), so that the ban still holds if a rescan gives it a new ID.
+    pub identity: String,
+    // "Artist - Album", for people.
+    pub title: String,
+}
+
+/// One run of graplsub, oldest first in State::runs.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Run {
+    // From run_id(), or made from when it started for runs from before there were IDs.
+    #[serde(default)]
+    pub id: String,
+    // Seconds since the Unix epoch.
+    pub started: u64,
+    // Not set until the playlist is complete, so a run that died part way through can be spotted.
+    pub finished: Option<u64>,
+    pub mode: config::Mode,
+    pub playlist_name: String,
+    pub playlist_id: Option<String>,
+    // The temporary name the playlist is being built under with GRAPLSUB_STAGED_SWAP, until it's
+    // swapped in. Still set after that means the run died first and left it behind.
+    #[serde(default)]
+    pub temporary: Option<String>,
+    // Artists that the selection was seeded with, if any.
+    #[serde(default)]
+    pub seeds: Vec<String>,
+    // IDs of the albums that were chosen, in playlist order.
+    #[serde(default)]
+    pub albums: Vec<String>,
+    // IDs of the songs that went into the playlist, in order. Only set once the run has finished,
+    // and not by versions from before it was added.
+    #[serde(default)]
+    pub songs: Vec<String>,
+    // What those albums are, from album::identity(), which outlasts the server's IDs. Only set
+    // once the run has finished, and not by versions from before it was added.
+    #[serde(default)]
+    pub identities: Vec<String>,
+    // Anything that looked wrong with the library while building the playlist.
+    #[serde(default)]
+    pub problems: Vec<Problem>,
+    // With GRAPLSUB_LEARN_FROM_PLAYS, the play counts of each album's songs, by album ID, when the
+    // playlist was finished. Emptied once the next run has learnt from them.
+    #[serde(default)]
+    pub placed: BTreeMap<String, feedback::Placed>,
+}
+
+/// Something wrong with an album in the library, like it not being possible to fetch, or it having
+/// no songs. Worth a look whether or not the album made it into the playlist.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Problem {
+    pub album: String,
+    pub problem: String,
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Couldn't read state file {path}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+
+    #[error("Couldn't write state file {path}: {source}")]
+    Write { path: PathBuf, source: io::Error },
+
+    #[error("State file {path} is corrupt: {source}")]
+    Corrupt {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[error("Couldn't use state file {path}: {source}")]
+    Database {
+        path: PathBuf,
+        source: rusqlite::Error,
+    },
+
+    #[error("State file {path} is from a newer version of graplsub")]
+    Newer { path: PathBuf },
+}
+
+/// Seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// A new ID for a run, so that what it logs, records and sends can all be tied together. It's a
+/// ULID (https://github.com/ulid/spec): 26 characters made of the time in milliseconds and 80
+/// random bits, so that IDs sort by when the runs started.
+pub fn run_id() -> String {
+    ulid::Ulid::new().to_string()
+}
+
+impl State {
+    /// Read the state file. One that doesn't exist yet is the same as an empty one, but one that
+    /// can't be read is an error rather than being silently thrown away.
+    pub fn load(path: &Path) -> Result<State, Error> {
+        match fs::metadata(path) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(State::default()),
+            Err(source) => {
+                return Err(Error::Read {
+                    path: path.to_path_buf(),
+                    source,
+                });
+            }
+        }
+
+        let db = open(path)?;
+        read(&db).map_err(|source| Error::Database {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Write whatever has changed since the state file was loaded, creating it if it doesn't exist
+    /// yet. It's all written or none of it is.
+    pub fn save(&mut self, path: &Path) -> Result<(), Error> {
+        let mut db = open(path)?;
+        self.write(&mut db).map_err(|source| Error::Database {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    fn write(&mut self, db: &mut Connection) -> rusqlite::Result<()> {
+        let tx = db.transaction()?;
+
+        for run in &self.runs {
+            if self.saved.runs.get(&run.id) != Some(run) {
+                write_run(&tx, run)?;
+            }
+        }
+        for id in self.saved.runs.keys() {
+            if !self.runs.iter().any(|r| &r.id == id) {
+                tx.execute("DELETE FROM runs WHERE id = ?1", [id])?;
+            }
+        }
+
+        write_map(
+            &tx,
+            "batch_sizes",
+            "base_url",
+            "size",
+            &self.batch_sizes,
+            &self.saved.batch_sizes,
+        )?;
+        write_map(
+            &tx,
+            "skips",
+            "identity",
+            "times",
+            &self.skips,
+            &self.saved.skips,
+        )?;
+
+        for ban in &self.banned {
+            if self.saved.banned.get(&ban.id) != Some(ban) {
+                tx.execute(
+                    "INSERT OR REPLACE INTO banned (id, identity, title) VALUES (?1, ?2, ?3)",
+                    params![ban.id, ban.identity, ban.title],
+                )?;
+            }
+        }
+        for id in self.saved.banned.keys() {
+            if !self.banned.iter().any(|b| &b.id == id) {
+                tx.execute("DELETE FROM banned WHERE id = ?1", [id])?;
+            }
+        }
+
+        tx.commit()?;
+        self.saved = Saved::of(self);
+
+        Ok(())
+    }
+
+    /// Start recording a new run.
+    pub fn begin(&mut self, conf: &config::Config) -> &mut Run {
+        self.runs.push(Run {
+            id: conf.run_id.clone(),
+            started: now(),
+            finished: None,
+            mode: conf.mode,
+            playlist_name: conf.playlist_name.clone(),
+            playlist_id: None,
+            temporary: None,
+            seeds: conf
+                .seed_artists
+                .as_deref()
+                .map(|s| {
+                    s.split(',')
+                        .map(str::trim)
+                        .filter(|a| !a.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            albums: Vec::new(),
+            songs: Vec::new(),
+            identities: Vec::new(),
+            problems: Vec::new(),
+            placed: BTreeMap::new(),
+        });
+
+        // Can't fail: one was just pushed.
+        self.runs.last_mut().unwrap()
+    }
+
+    /// If the most recent run died part way through building the same playlist that's wanted now,
+    /// its playlist ID and the albums it had chosen.
+    pub fn unfinished(&self, conf: &config::Config) -> Option<(String, Vec<String>)> {
+        let run = self.runs.last()?;
+
+        if run.finished.is_some()
+            || run.playlist_name != conf.playlist_name
+            || run.mode != conf.mode
+        {
+            return None;
+        }
+
+        Some((run.playlist_id.clone()?, run.albums.clone()))
+    }
+
+    /// The last complete run before this one that made the same playlist.
+    pub fn previous_run(&self, conf: &config::Config) -> Option<&Run> {
+        let (_, earlier) = self.runs.split_last()?;
+
+        earlier
+            .iter()
+            .rev()
+            .find(|r| r.finished.is_some() && r.playlist_name == conf.playlist_name)
+    }
+
+    /// The last complete run that made the same playlist.
+    pub fn last_finished(&self, conf: &config::Config) -> Option<&Run> {
+        self.runs
+            .iter()
+            .rev()
+            .find(|r| r.finished.is_some() && r.playlist_name == conf.playlist_name)
+    }
+
+    /// When the last run that made the same playlist started, whether or not it finished.
+    pub fn last_started(&self, conf: &config::Config) -> Option<u64> {
+        self.runs
+            .iter()
+            .rev()
+            .find(|r| r.playlist_name == conf.playlist_name)
+            .map(|r| r.started)
+    }
+
+    /// The most recent run, if any.
+    pub fn current(&mut self) -> Option<&mut Run> {
+        self.runs.last_mut()
+    }
+}
+
+impl Saved {
+    fn of(state: &State) -> Saved {
+        Saved {
+            runs: state
+                .runs
+                .iter()
+                .map(|r| (r.id.clone(), r.clone()))
+                .collect(),
+            batch_sizes: state.batch_sizes.clone(),
+            banned: state
+                .banned
+                .iter()
+                .map(|b| (b.id.clone(), b.clone()))
+                .collect(),
+            skips: state.skips.clone(),
+        }
+    }
+}
+
+/// Open the state file, bringing its schema up to date, or making it from the JSON that state
+/// files used to be.
+fn open(path: &Path) -> Result<Connection, Error> {
+    let db_err = |source| Error::Database {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty()
+    {
+        fs::create_dir_all(dir).map_err(|source| Error::Write {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    }
+
+    let json = from_json(path)?;
+
+    let mut db = Connection::open(path).map_err(db_err)?;
+    db.busy_timeout(BUSY_TIMEOUT).map_err(db_err)?;
+    db.pragma_update(None, "foreign_keys", true)
+        .map_err(db_err)?;
+
+    let version: usize = db
+        .pragma_query_value(None, "user_version", |r| r.get(0))
+        .map_err(db_err)?;
+    if version > MIGRATIONS.len() {
+        return Err(Error::Newer {
+            path: path.to_path_buf(),
+        });
+    }
+    migrate(&mut db, version).map_err(db_err)?;
+
+    if let Some(mut state) = json {
+        state.write(&mut db).map_err(db_err)?;
+        output::note(format!(
+            "Moved state file {} into a database. The old one is {}.old.",
+            path.display(),
+            path.display()
+        ));
+    }
+
+    Ok(db)
+}
+
+/// Apply the migrations that a database at `version` hasn't had yet.
+fn migrate(db: &mut Connection, version: usize) -> rusqlite::Result<()> {
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = db.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+/// If the state file is JSON from before it was a database, what's in it. The file is moved out of
+/// the way, to `<path>.old`, for the database to take its place.
+fn from_json(path: &Path) -> Result<Option<State>, Error> {
+    let read_err = |source| Error::Read {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let mut header = Vec::new();
+    match fs::File::open(path) {
+        Ok(f) => f
+            .take(SQLITE_HEADER.len() as u64)
+            .read_to_end(&mut header)
+            .map_err(read_err)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(source) => return Err(read_err(source)),
+    };
+    // SQLite is happy to start a database in an empty file.
+    if header.is_empty() || header == SQLITE_HEADER {
+        return Ok(None);
+    }
+
+    let json = fs::read_to_string(path).map_err(read_err)?;
+    let mut state: State = serde_json::from_str(&json).map_err(|source| Error::Corrupt {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    for run in state.runs.iter_mut().filter(|r| r.id.is_empty()) {
+        run.id = ulid::Ulid::from_parts(run.started * 1000, rand::random()).to_string();
+    }
+
+    let mut old = path.as_os_str().to_owned();
+    old.push(".old");
+    fs::rename(path, &old).map_err(|source| Error::Write {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    Ok(Some(state))
+}
+
+/// Everything in the database.
+fn read(db: &Connection) -> rusqlite::Result<State> {
+    let mut runs: Vec<Run> = db
+        .prepare(
+            "SELECT id, started, finished, mode, playlist_name, playlist_id, temporary FROM runs \
+            ORDER BY started, rowid",
+        )?
+        .query_map([], |r| {
+            let mode: String = r.get(3)?;
+            Ok(Run {
+                id: r.get(0)?,
+                started: r.get(1)?,
+                finished: r.get(2)?,
+                mode: serde_json::from_value(Value::String(mode)).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(3, Type::Text, Box::new(e))
+                })?,
+                playlist_name: r.get(4)?,
+                playlist_id: r.get(5)?,
+                temporary: r.get(6)?,
+                seeds: Vec::new(),
+                albums: Vec::new(),
+                songs: Vec::new(),
+                identities: Vec::new(),
+                problems: Vec::new(),
+                placed: BTreeMap::new(),
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+
+    let index: HashMap<String, usize> = runs
+        .iter()
+        .enumerate()
+        .map(|(i, r)| (r.id.clone(), i))
+        .collect();
+    // Can't fail to find the run: the tables' foreign keys see to that.
+    let at = |id: &String| index[id];
+
+    for (id, artist) in list(db, "run_seeds", "artist")? {
+        runs[at(&id)].seeds.push(artist);
+    }
+    for (id, album) in list(db, "run_albums", "album")? {
+        runs[at(&id)].albums.push(album);
+    }
+    for (id, song) in list(db, "run_songs", "song")? {
+        runs[at(&id)].songs.push(song);
+    }
+    for (id, identity) in list(db, "run_identities", "identity")? {
+        runs[at(&id)].identities.push(identity);
+    }
+
+    let mut problems =
+        db.prepare("SELECT run, album, problem FROM problems ORDER BY run, position")?;
+    for row in problems.query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))? {
+        let (id, album, problem) = row?;
+        runs[at(&id)].problems.push(Problem { album, problem });
+    }
+
+    let mut placed = db.prepare("SELECT run, album, identity, songs, plays FROM placed")?;
+    for row in placed.query_map([], |r| {
+        Ok((
+            r.get(0)?,
+            r.get(1)?,
+            feedback::Placed {
+                identity: r.get(2)?,
+                songs: r.get(3)?,
+                plays: r.get(4)?,
+            },
+        ))
+    })? {
+        let (id, album, p) = row?;
+        runs[at(&id)].placed.insert(album, p);
+    }
+
+    let banned = db
+        .prepare("SELECT id, identity, title FROM banned ORDER BY rowid")?
+        .query_map([], |r| {
+            Ok(Ban {
+                id: r.get(0)?,
+                identity: r.get(1)?,
+                title: r.get(2)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+
+    let mut state = State {
+        runs,
+        batch_sizes: map(db, "batch_sizes", "base_url", "size")?,
+        banned,
+        skips: map(db, "skips", "identity", "times")?,
+        saved: Saved::default(),
+    };
+    state.saved = Saved::of(&state);
+
+    Ok(state)
+}
+
+/// Write one run, replacing what was there for it before. The run keeps its place in the table,
+/// which is what tells apart runs that started in the same second.
+fn write_run(tx: &Transaction, run: &Run) -> rusqlite::Result<()> {
+    // Can't fail: modes serialise as their names.
+    let mode = serde_json::to_value(run.mode).unwrap_or_default();
+    tx.execute(
+        "INSERT INTO runs (id, started, finished, mode, playlist_name, playlist_id, temporary) \
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) \
+        ON CONFLICT (id) DO UPDATE SET started = excluded.started, finished = excluded.finished, \
+        mode = excluded.mode, playlist_name = excluded.playlist_name, \
+        playlist_id = excluded.playlist_id, temporary = excluded.temporary",
+        params![
+            run.id,
+            run.started,
+            run.finished,
+            mode.as_str(),
+            run.playlist_name,
+            run.playlist_id,
+            run.temporary
+        ],
+    )?;
+
+    for table in [
+        "run_seeds",
+        "run_albums",
+        "run_songs",
+        "run_identities",
+        "problems",
+        "placed",
+    ] {
+        tx.execute(&format!("DELETE FROM {} WHERE run = ?1", table), [&run.id])?;
+    }
+
+    for (table, column, items) in [
+        ("run_seeds", "artist", &run.seeds),
+        ("run_albums", "album", &run.albums),
+        ("run_songs", "song", &run.songs),
+        ("run_identities", "identity", &run.identities),
+    ] {
+        let mut insert = tx.prepare_cached(&format!(
+            "INSERT INTO {} (run, position, {}) VALUES (?1, ?2, ?3)",
+            table, column
+        ))?;
+        for (i, item) in items.iter().enumerate() {
+            insert.execute(params![run.id, i, item])?;
+        }
+    }
+
+    for (i, p) in run.problems.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO problems (run, position, album, problem) VALUES (?1, ?2, ?3, ?4)",
+            params![run.id, i, p.album, p.problem],
+        )?;
+    }
+    for (album, p) in &run.placed {
+        tx.execute(
+            "INSERT INTO placed (run, album, identity, songs, plays) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![run.id, album, p.identity, p.songs, p.plays],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// One of the lists that go with each run, as the run's ID and each item, in order.
+fn list(db: &Connection, table: &str, column: &str) -> rusqlite::Result<Vec<(String, String)>> {
+    db.prepare(&format!(
+        "SELECT run, {} FROM {} ORDER BY run, position",
+        column, table
+    ))?
+    .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?
+    .collect()
+}
+
+/// A table of keys and values.
+fn map<V: rusqlite::types::FromSql>(
+    db: &Connection,
+    table: &str,
+    key: &str,
+    value: &str,
+) -> rusqlite::Result<BTreeMap<String, V>> {
+    db.prepare(&format!("SELECT {}, {} FROM {}", key, value, table))?
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?
+        .collect()
+}
+
+/// Write the keys whose values are different in `now` from `before` to a table of keys and values,
+/// and delete those that have gone.
+fn write_map<V: ToSql + PartialEq>(
+    tx: &Transaction,
+    table: &str,
+    key: &str,
+    value: &str,
+    now: &BTreeMap<String, V>,
+    before: &BTreeMap<String, V>,
+) -> rusqlite::Result<()> {
+    for (k, v) in now {
+        if before.get(k) != Some(v) {
+            tx.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO {} ({}, {}) VALUES (?1, ?2)",
+                    table, key, value
+                ),
+                params![k, v],
+            )?;
+        }
+    }
+    for k in before.keys().filter(|k| !now.contains_key(*k)) {
+        tx.execute(&format!("DELETE FROM {} WHERE {} = ?1", table, key), [k])?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn conf() -> config::Config {
+        let mut conf = crate::testing::conf(&[("GRAPLSUB_SEED_ARTISTS", "A, B")]);
+        conf.run_id = run_id();
+        conf
+    }
+
+    #[test]
+    fn everything_is_saved() {
+        let path = crate::testing::dir().join("state.db");
+        let mut state = State::default();
+
+        let run = state.begin(&conf());
+        run.finished = Some(run.started + 60);
+        run.playlist_id = Some("pl".to_string());
+        run.albums = vec!["b".to_string(), "a".to_string()];
+        run.songs = vec!["3".to_string(), "1".to_string(), "2".to_string()];
+        run.identities = vec!["B".to_string(), "A".to_string()];
+        run.problems = vec![Problem {
+            album: "c".to_string(),
+            problem: "no songs".to_string(),
+        }];
+        run.placed.insert(
+            "a".to_string(),
+            feedback::Placed {
+                identity: "A".to_string(),
+                songs: 10,
+                plays: 3,
+            },
+        );
+        state.begin(&conf()).temporary = Some("graplsub_tmp".to_string());
+        state.batch_sizes.insert("http://server".to_string(), 25);
+        state.banned.push(Ban {
+            id: "x".to_string(),
+            identity: "X".to_string(),
+            title: "Artist - X".to_string(),
+        });
+        state.skips.insert("A".to_string(), 2);
+        state.save(&path).unwrap();
+
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(loaded.runs, state.runs);
+        assert_eq!(loaded.runs[0].seeds, ["A", "B"]);
+        assert_eq!(loaded.batch_sizes, state.batch_sizes);
+        assert_eq!(loaded.banned, state.banned);
+        assert_eq!(loaded.skips, state.skips);
+    }
+
+    #[test]
+    fn a_missing_file_is_empty_and_left_alone() {
+        let path = crate::testing::dir().join("nothing").join("state.db");
+
+        let state = State::load(&path).unwrap();
+
+        assert!(state.runs.is_empty());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn every_run_is_kept() {
+        let path = crate::testing::dir().join("state.db");
+        let mut state = State::default();
+        for _ in 0..150 {
+            state.begin(&conf());
+        }
+        state.save(&path).unwrap();
+
+        assert_eq!(State::load(&path).unwrap().runs.len(), 150);
+    }
+
+    #[test]
+    fn only_changes_are_saved() {
+        let path = crate::testing::dir().join("state.db");
+        let mut state = State::default();
+        state.begin(&conf());
+        state.skips.insert("gone".to_string(), 1);
+        state.save(&path).unwrap();
+
+        // Like a run that's part way through when someone bans an album.
+        let mut running = State::load(&path).unwrap();
+        let mut banning = State::load(&path).unwrap();
+        banning.banned.push(Ban {
+            id: "x".to_string(),
+            identity: "X".to_string(),
+            title: "X".to_string(),
+        });
+        banning.save(&path).unwrap();
+
+        running.current().unwrap().finished = Some(1);
+        running.skips.remove("gone");
+        running.save(&path).unwrap();
+
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(loaded.banned.len(), 1);
+        assert_eq!(loaded.runs[0].finished, Some(1));
+        assert!(loaded.skips.is_empty());
+
+        // And taking the ban away again.
+        let mut unbanning = loaded;
+        unbanning.banned.clear();
+        unbanning.save(&path).unwrap();
+        assert!(State::load(&path).unwrap().banned.is_empty());
+    }
+
+    #[test]
+    fn json_state_files_are_moved_into_a_database() {
+        let dir = crate::testing::dir();
+        let path = dir.join("state.json");
+        fs::write(
+            &path,
+            r#"{
+                "runs": [
+                    {
+                        "started": 1700000000,
+                        "finished": 1700000060,
+                        "mode": "decades",
+                        "playlist_name": "old",
+                        "playlist_id": "1",
+                        "albums": ["a", "b"]
+                    },
+                    {
+                        "id": "01HZZZZZZZZZZZZZZZZZZZZZZZ",
+                        "started": 1800000000,
+                        "finished": null,
+                        "mode": "random",
+                        "playlist_name": "new",
+                        "playlist_id": null
+                    }
+                ],
+                "batch_sizes": { "http://server": 12 },
+                "skips": { "A": 1 }
+            }"#,
+        )
+        .unwrap();
+
+        let state = State::load(&path).unwrap();
+
+        assert_eq!(state.runs.len(), 2);
+        assert_eq!(state.runs[0].mode, config::Mode::Decades);
+        assert_eq!(state.runs[0].albums, ["a", "b"]);
+        let made = ulid::Ulid::from_string(&state.runs[0].id).unwrap();
+        assert_eq!(made.timestamp_ms(), 1_700_000_000_000);
+        assert_eq!(state.runs[1].id, "01HZZZZZZZZZZZZZZZZZZZZZZZ");
+        assert_eq!(state.batch_sizes["http://server"], 12);
+        assert_eq!(state.skips["A"], 1);
+
+        assert!(fs::read(&path).unwrap().starts_with(SQLITE_HEADER));
+        assert!(dir.join("state.json.old").exists());
+        assert_eq!(State::load(&path).unwrap().runs, state.runs);
+    }
+
+    #[test]
+    fn corrupt_json_is_an_error() {
+        let path = crate::testing::dir().join("state.json");
+        fs::write(&path, "{ not json").unwrap();
+
+        assert!(matches!(State::load(&path), Err(Error::Corrupt { .. })));
+        // Still there to be put right.
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn databases_are_migrated_and_newer_ones_refused() {
+        let path = crate::testing::dir().join("state.db");
+        State::default().save(&path).unwrap();
+
+        let db = Connection::open(&path).unwrap();
+        let version: usize = db
+            .pragma_query_value(None, "user_version", |r| r.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        db.pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(matches!(State::load(&path), Err(Error::Newer { .. })));
+    }
+
+    #[test]
+    fn run_ids_are_ulids() {
+        let id = run_id();
+
+        assert_eq!(id.len(), 26);
+        assert!(
+            id.chars()
+                .all(|c| "0123456789ABCDEFGHJKMNPQRSTVWXYZ".contains(c))
+        );
+    }
+
+    #[test]
+    fn run_ids_sort_by_time() {
+        let first = run_id();
+        thread::sleep(Duration::from_millis(2));
+        let second = run_id();
+
+        assert!(first < second);
+        assert_ne!(run_id(), run_id());
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use crate::api;
+use crate::output::{self, Stream, Style};
+
+// How many of the most common genres, decades and artists to list.
+const TOP: usize = 10;
+
+/// Tallies of what ended up in the playlist.
+#[derive(Default)]
+pub struct Stats<'a> {
+    songs: usize,
+    albums: usize,
+    // Seconds.
+    duration: u64,
+    genres: HashMap<&'a str, usize>,
+    decades: HashMap<u32, usize>,
+    artists: HashMap<&'a str, usize>,
+    oldest: Option<(u32, &'a api::Album)>,
+    newest: Option<(u32, &'a api::Album)>,
+}
+
+impl<'a> Stats<'a> {
+    /// Count up the songs of the given albums (which must have been fetched individually, so that
+    /// they include their songs) and any other songs that were added to the playlist.
+    pub fn new(albums: &'a [api::Album], extra: &'a [api::Song]) -> Stats<'a> {
+        let mut stats = Stats {
+            albums: albums.len(),
+            ..Default::default()
+        };
+
+        for album in albums {
+            for song in album.song.iter().flatten() {
+                stats.count(song, Some(album));
+            }
+
+            if let Some(year) = album.year.filter(|&y| y > 0) {
+                if stats.oldest.is_none_or(|(y, _)| year < y) {
+                    stats.oldest = Some((year, album));
+                }
+                if stats.newest.is_none_or(|(y, _)| year > y) {
+                    stats.newest = Some((year, album));
+                }
+            }
+        }
+
+        for song in extra {
+            stats.count(song, None);
+        }
+
+        stats
+    }
+
+    /// Songs don't always have their own genre, year or artist, in which case the album's is used.
+    fn count(&mut self, song: &'a api::Song, album: Option<&'a api::Album>) {
+        self.songs += 1;
+        self.duration += u64::from(song.duration.unwrap_or(0));
+
+        let genre = song
+            .genre
+            .as_deref()
+            .or_else(|| album.and_then(|a| a.genre.as_deref()));
+        *self.genres.entry(genre.unwrap_or("Unknown")).or_default() += 1;
+
+        let year = song.year.or_else(|| album.and_then(|a| a.year));
+        if let Some(year) = year.filter(|&y| y > 0) {
+            *self.decades.entry(year / 10 * 10).or_default() += 1;
+        }
+
+        let artist = song
+            .artist
+            .as_deref()
+            .or_else(|| album.and_then(|a| a.artist.as_deref()));
+        *self.artists.entry(artist.unwrap_or("Unknown")).or_default() += 1;
+    }
+
+    pub fn song_count(&self) -> usize {
+        self.songs
+    }
+
+    /// How long it all lasts, in seconds.
+    pub fn seconds(&self) -> u64 {
+        self.duration
+    }
+
+    /// How many songs and albums there are and how long they last, in a sentence.
+    pub fn summary(&self) -> String {
+        format!(
+            "Playlist has {} songs from {} albums, lasting {}.",
+            self.songs,
+            self.albums,
+            duration(self.duration)
+        )
+    }
+
+    /// The rest of the breakdown, as a label and what it says for each line.
+    pub fn breakdown(&self) -> Vec<(&'static str, String)> {
+        let mut lines = Vec::new();
+
+        if let Some((year, album)) = self.oldest {
+            lines.push(("Oldest album:", format!("{} ({})", describe(album), year)));
+        }
+        if let Some((year, album)) = self.newest {
+            lines.push(("Newest album:", format!("{} ({})", describe(album), year)));
+        }
+
+        lines.push(("Songs by genre:", top(&self.genres, |g| g.to_string())));
+
+        // Decades read better in order than by count.
+        let mut decades: Vec<(&u32, &usize)> = self.decades.iter().collect();
+        decades.sort();
+        lines.push((
+            "Songs by decade:",
+            decades
+                .iter()
+                .map(|(d, n)| format!("{}s {}", d, n))
+                .collect::<Vec<_>>()
+                .join(", "),
+        ));
+
+        lines.push(("Songs by artist:", top(&self.artists, |a| a.to_string())));
+
+        lines
+    }
+
+    /// Print the breakdown for a human to read.
+    pub fn print(&self) {
+        println!(
+            "{}",
+            output::paint(Stream::Stdout, Style::Bold, self.summary())
+        );
+
+        for (name, text) in self.breakdown() {
+            println!("{} {}", label(name), text);
+        }
+    }
+}
+
+fn label(text: &str) -> String {
+    output::paint(Stream::Stdout, Style::Dim, text)
+}
+
+/// An album as "Artist – Name".
+pub fn describe(album: &api::Album) -> String {
+    format!(
+        "{} – {}",
+        album.artist.as_deref().unwrap_or("Unknown artist"),
+        album.name.as_deref().unwrap_or("Unknown album")
+    )
+}
+
+/// The most common keys and their counts, like "Rock 40, Jazz 30, …".
+pub fn top<K: Ord>(counts: &HashMap<K, usize>, name: impl Fn(&K) -> String) -> String {
+    let mut counts: Vec<(&K, &usize)> = counts.iter().collect();
+    // Most first, then by name so that ties come out the same every time.
+    counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+    let mut listed: Vec<String> = counts
+        .iter()
+        .take(TOP)
+        .map(|(k, n)| format!("{} {}", name(k), n))
+        .collect();
+
+    if counts.len() > TOP {
+        listed.push(format!("and {} more", counts.len() - TOP));
+    }
+
+    listed.join(", ")
+}
+
+/// Seconds as something like "2d 3h 15m".
+pub fn duration(secs: u64) -> String {
+    let mins = secs / 60;
+    let (days, hours, mins) = (mins / 1440, mins / 60 % 24, mins % 60);
+
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, mins)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else {
+        format!("{}m", mins)
+    }
+}
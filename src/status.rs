@@ -0,0 +1,182 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::CONTENT_TYPE;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use tokio::net::TcpListener;
+
+use crate::output;
+
+// A tiny HTTP server for daemon mode, so that something like Prometheus or a load balancer's health
+// check can see how the runs are going:
+//
+// - /healthz is 200 unless the last run failed, when it's 503.
+// - /metrics has counters and timestamps in the Prometheus text format.
+// - /lastrun has the last run as JSON, or null before there's been one.
+
+/// How one run went.
+#[derive(Clone, Debug, Serialize)]
+pub struct LastRun {
+    // Seconds since the Unix epoch.
+    pub started: u64,
+    pub finished: u64,
+    pub ok: bool,
+    // The ID of each profile's run, as in the logs and the state file.
+    pub ids: Vec<String>,
+}
+
+/// How the daemon's getting on, shared between it and the server.
+#[derive(Debug, Default)]
+pub struct Status {
+    runs: u64,
+    failures: u64,
+    last: Option<LastRun>,
+    // When the next run is due, in seconds since the Unix epoch.
+    next: Option<u64>,
+}
+
+pub type Shared = Arc<Mutex<Status>>;
+
+impl Status {
+    /// Note how a run went and when the next one is due.
+    pub fn record(&mut self, run: LastRun, next: u64) {
+        self.runs += 1;
+        if !run.ok {
+            self.failures += 1;
+        }
+        self.last = Some(run);
+        self.next = Some(next);
+    }
+
+    fn metrics(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            out.push_str(&format!(
+                "# HELP graplsub_{name} {help}\n# TYPE graplsub_{name} {kind}\ngraplsub_{name} {value}\n"
+            ));
+        };
+
+        metric(
+            "runs_total",
+            "counter",
+            "Runs since the daemon started.",
+            self.runs,
+        );
+        metric(
+            "run_failures_total",
+            "counter",
+            "Runs that failed since the daemon started.",
+            self.failures,
+        );
+        if let Some(last) = &self.last {
+            metric(
+                "last_run_success",
+                "gauge",
+                "Whether the last run built the playlist.",
+                u64::from(last.ok),
+            );
+            metric(
+                "last_run_timestamp_seconds",
+                "gauge",
+                "When the last run finished.",
+                last.finished,
+            );
+            metric(
+                "last_run_duration_seconds",
+                "gauge",
+                "How long the last run took.",
+                last.finished.saturating_sub(last.started),
+            );
+        }
+        if let Some(next) = self.next {
+            metric(
+                "next_run_timestamp_seconds",
+                "gauge",
+                "When the next run is due.",
+                next,
+            );
+        }
+
+        out
+    }
+}
+
+/// Serve the status on `addr` in the background. Failing to bind is returned, so that a typo in the
+/// address is noticed straight away; anything that goes wrong after that is only warned about.
+pub async fn serve(addr: SocketAddr, status: Shared) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((s, _)) => s,
+                Err(e) => {
+                    output::warn(format!("Status server couldn't accept a connection: {}", e));
+                    continue;
+                }
+            };
+
+            let status = status.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req| {
+                    let response = respond(&req, &status);
+                    async move { Ok::<_, Infallible>(response) }
+                });
+
+                // A client that goes away early isn't our problem.
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn respond<B>(req: &Request<B>, status: &Shared) -> Response<Full<Bytes>> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return reply(StatusCode::METHOD_NOT_ALLOWED, "text/plain", "GET only\n");
+    }
+
+    // A poisoned lock only means a panic elsewhere; the numbers are still worth having.
+    let status = status.lock().unwrap_or_else(|e| e.into_inner());
+
+    match req.uri().path() {
+        "/healthz" => match &status.last {
+            Some(last) if !last.ok => reply(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "text/plain",
+                "last run failed\n",
+            ),
+            _ => reply(StatusCode::OK, "text/plain", "ok\n"),
+        },
+        "/metrics" => reply(
+            StatusCode::OK,
+            "text/plain; version=0.0.4",
+            status.metrics(),
+        ),
+        "/lastrun" => reply(
+            StatusCode::OK,
+            "application/json",
+            serde_json::to_string(&status.last).unwrap_or_default(),
+        ),
+        _ => reply(StatusCode::NOT_FOUND, "text/plain", "not found\n"),
+    }
+}
+
+fn reply(code: StatusCode, content_type: &str, body: impl Into<Bytes>) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(body.into()));
+    *response.status_mut() = code;
+    if let Ok(value) = content_type.parse() {
+        response.headers_mut().insert(CONTENT_TYPE, value);
+    }
+    response
+}
@@ -0,0 +1,174 @@
+use crate::api;
+use crate::api::Client;
+use crate::config;
+use crate::output;
+use crate::quirks;
+
+async fn ping(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/ping?u={}&f=json&v={}&c={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name)
+    );
+
+    api::get(client, &url).await
+}
+
+async fn extensions(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/getOpenSubsonicExtensions?u={}&f=json&v={}&c={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name)
+    );
+
+    api::get(client, &url).await
+}
+
+/// What a server says about itself.
+pub struct ServerInfo {
+    pub api_version: Option<String>,
+    pub server_type: Option<String>,
+    pub server_version: Option<String>,
+}
+
+/// Ping the server, checking that it answers and accepts our credentials, and find out what it is.
+pub async fn info(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<ServerInfo, api::Error> {
+    let subsonic_response = ping(client, conf, api_ver).await?;
+
+    api::check_generic_response(&subsonic_response)?;
+
+    let resp = subsonic_response.subsonic_response;
+    Ok(ServerInfo {
+        api_version: resp.version,
+        server_type: resp.server_type,
+        server_version: resp.server_version,
+    })
+}
+
+/// Whether an error means we never got as far as talking to the server, so trying another one
+/// might help.
+fn unreachable(err: &api::Error) -> bool {
+    match err.root() {
+        api::Error::Network(e) => e.is_connect() || e.is_timeout(),
+        api::Error::UnixSocket { .. } => true,
+        _ => false,
+    }
+}
+
+/// GRAPLSUB_BASE_URL can be a comma-separated list of servers to try in order. Ping each in turn
+/// and settle on the first one that can be reached, leaving conf.base_url set to it. Returns a
+/// client for it and the API version to speak to it, which is `api_ver` unless it's older.
+///
+/// Only failure to connect causes a failover. Anything else, like bad credentials, will be the same
+/// on every server so is left for the real requests to report.
+pub async fn find_server(
+    conf: &mut config::Config,
+    api_ver: &str,
+) -> Result<(Client, String), api::Error> {
+    let mut client = choose_server(conf, api_ver).await?;
+    let api_ver = negotiate(&mut client, conf, api_ver).await;
+
+    Ok((client, api_ver))
+}
+
+/// Ask the server what it is and which version of the API it speaks. If it's older than `api_ver`
+/// then that's what we'll speak too, and if it's too old for token authentication then the client
+/// is switched to sending the password instead, unless GRAPLSUB_AUTH_MODE says otherwise. The
+/// client is also set up to work around the server's quirks. A server that can't be asked is
+/// assumed to be up to date, and whatever went wrong is left for the real requests to report.
+async fn negotiate(client: &mut Client, conf: &config::Config, api_ver: &str) -> String {
+    // An older server says no to a newer version, with error 30, but still says what it speaks.
+    let Ok(resp) = ping(client, conf, api_ver).await else {
+        return api_ver.to_string();
+    };
+    let resp = resp.subsonic_response;
+
+    client.set_quirks(quirks::detect(resp.server_type.as_deref()));
+    client.set_server(
+        format!(
+            "{} {}",
+            resp.server_type.as_deref().unwrap_or("a Subsonic server"),
+            resp.server_version.as_deref().unwrap_or_default()
+        )
+        .trim_end()
+        .to_string(),
+    );
+    let server_ver = resp.version;
+
+    // Requests with long URLs can go as forms instead, if the server says it takes them. Not
+    // knowing only means they don't.
+    if resp.open_subsonic == Some(true)
+        && let Ok(exts) = extensions(client, conf, api_ver).await
+    {
+        client.set_form_post(
+            exts.subsonic_response
+                .extensions
+                .unwrap_or_default()
+                .iter()
+                .any(|e| e.name == "formPost"),
+        );
+    }
+
+    match server_ver {
+        Some(v) if api::older(&v, api_ver) => {
+            output::note(format!(
+                "The server only speaks version {} of the API, so using that.",
+                v
+            ));
+            if api::older(&v, "1.13.0") && conf.auth_mode == config::AuthMode::Auto {
+                client.use_password(&conf.pass);
+            }
+            v
+        }
+        _ => api_ver.to_string(),
+    }
+}
+
+async fn choose_server(conf: &mut config::Config, api_ver: &str) -> Result<Client, api::Error> {
+    let candidates: Vec<String> = conf
+        .base_url
+        .split(',')
+        .map(|u| u.trim().to_string())
+        .filter(|u| !u.is_empty())
+        .collect();
+
+    for (i, base_url) in candidates.iter().enumerate() {
+        conf.base_url = base_url.clone();
+        let client = api::create_client(conf)?;
+
+        // No point pinging the last (or only) candidate as there's nowhere else to go.
+        if i == candidates.len() - 1 {
+            return Ok(client);
+        }
+
+        match ping(&client, conf, api_ver).await {
+            Err(e) if unreachable(&e) => {
+                output::warn(format!(
+                    "Couldn't reach {}, trying the next server: {}",
+                    base_url, e
+                ));
+            }
+            _ => return Ok(client),
+        }
+    }
+
+    // Nothing but commas and spaces, so fall back to what an unset GRAPLSUB_BASE_URL would be.
+    conf.base_url = config::default_base_url();
+    api::create_client(conf)
+}
@@ -0,0 +1,48 @@
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+// Talking to systemd when run as a Type=notify service, by the same protocol as sd_notify(3). When
+// we're not run by systemd, NOTIFY_SOCKET isn't set and none of this does anything.
+
+/// Tell systemd something, like "READY=1". Returns whether it was sent.
+pub fn notify(state: &str) -> bool {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return false;
+    };
+    let path = path.to_string_lossy();
+
+    // A leading "@" means a socket in the abstract namespace, which only Linux has.
+    let addr = match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name.as_bytes())
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return false,
+        None => SocketAddr::from_pathname(path.as_ref()),
+    };
+
+    match (addr, UnixDatagram::unbound()) {
+        (Ok(addr), Ok(socket)) => socket.send_to_addr(state.as_bytes(), &addr).is_ok(),
+        _ => false,
+    }
+}
+
+/// How often systemd wants to hear that we're alive, if it's watching at all. That's half the
+/// WatchdogSec= of the unit, so that a late ping isn't taken as a hang.
+pub fn watchdog_interval() -> Option<Duration> {
+    // The watchdog is meant for one process in particular, not anything it starts.
+    if let Ok(pid) = std::env::var("WATCHDOG_PID")
+        && pid.parse::<u32>().ok() != Some(std::process::id())
+    {
+        return None;
+    }
+
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+
+    Some(Duration::from_micros(usec / 2))
+}
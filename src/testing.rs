@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use serde_json::{Value, json};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config;
+
+// Things the tests share: a configuration to start from, and a pretend Subsonic server to point it
+// at.
+
+/// A configuration with the least that has to be set, and `vars` on top.
+pub fn conf(vars: &[(&str, &str)]) -> config::Config {
+    let mut all = HashMap::from([("GRAPLSUB_USER".to_string(), "test".to_string())]);
+    all.extend(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+    config::from_vars(all).unwrap()
+}
+
+/// A request that a Server got: the endpoint without "/rest/" and ".view", and the query.
+#[derive(Clone, Debug)]
+pub struct Call {
+    pub endpoint: String,
+    pub query: Vec<(String, String)>,
+}
+
+impl Call {
+    /// Every value of one parameter, in order.
+    pub fn all(&self, name: &str) -> Vec<&str> {
+        self.query
+            .iter()
+            .filter(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.all(name).into_iter().next()
+    }
+}
+
+/// What a Server says to a call: how long to wait first, and what goes in "subsonic-response"
+/// alongside the status and version. A "status" in there overrides "ok".
+pub type Answer = (Duration, Value);
+
+/// A pretend Subsonic server on a port of its own, which answers calls however it's told to and
+/// notes each one down as it comes in.
+pub struct Server {
+    pub base_url: String,
+    calls: Arc<Mutex<Vec<Call>>>,
+    // How many calls are being answered right now, and the most there have been at once.
+    busy: Arc<(AtomicUsize, AtomicUsize)>,
+}
+
+impl Server {
+    pub async fn start<F>(answer: F) -> Server
+    where
+        F: Fn(&Call) -> Answer + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let busy = Arc::new((AtomicUsize::new(0), AtomicUsize::new(0)));
+        let answer = Arc::new(answer);
+
+        let noted = calls.clone();
+        let counted = busy.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let answer = answer.clone();
+                let noted = noted.clone();
+                let counted = counted.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req| {
+                        let call = call(&req);
+                        noted.lock().unwrap().push(call.clone());
+                        let (wait, body) = answer(&call);
+                        let counted = counted.clone();
+                        async move {
+                            let now = counted.0.fetch_add(1, Ordering::SeqCst) + 1;
+                            counted.1.fetch_max(now, Ordering::SeqCst);
+                            tokio::time::sleep(wait).await;
+                            counted.0.fetch_sub(1, Ordering::SeqCst);
+                            Ok::<_, Infallible>(respond(body))
+                        }
+                    });
+
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        Server {
+            base_url,
+            calls,
+            busy,
+        }
+    }
+
+    /// The calls so far, in the order they came in.
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// The most calls there have been at once.
+    pub fn most_at_once(&self) -> usize {
+        self.busy.1.load(Ordering::SeqCst)
+    }
+
+    /// The calls so far to one endpoint.
+    pub fn calls_to(&self, endpoint: &str) -> Vec<Call> {
+        self.calls()
+            .into_iter()
+            .filter(|c| c.endpoint == endpoint)
+            .collect()
+    }
+}
+
+fn call<B>(req: &Request<B>) -> Call {
+    let endpoint = req.uri().path();
+    let endpoint = endpoint.strip_prefix("/rest/").unwrap_or(endpoint);
+    let endpoint = endpoint.strip_suffix(".view").unwrap_or(endpoint);
+
+    Call {
+        endpoint: endpoint.to_string(),
+        query: form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+            .into_owned()
+            .collect(),
+    }
+}
+
+fn respond(body: Value) -> Response<Full<Bytes>> {
+    let mut response = json!({ "status": "ok", "version": "1.16.1" });
+    if let (Some(response), Some(body)) = (response.as_object_mut(), body.as_object()) {
+        response.extend(body.clone());
+    }
+
+    Response::new(Full::new(Bytes::from(
+        json!({ "subsonic-response": response }).to_string(),
+    )))
+}
+
+/// A SOCKS5 proxy on a port of its own, which sends every connection on to `base_url`, whatever it
+/// was asked to connect to. Returns the address to give as GRAPLSUB_PROXY.
+pub async fn socks_proxy(base_url: &str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = format!("socks5h://{}", listener.local_addr().unwrap());
+    let to = base_url.trim_start_matches("http://").to_string();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let to = to.clone();
+            tokio::spawn(async move {
+                // The greeting, which offers some methods, of which "none" is taken.
+                let mut head = [0; 2];
+                stream.read_exact(&mut head).await?;
+                stream.read_exact(&mut vec![0; head[1].into()]).await?;
+                stream.write_all(&[5, 0]).await?;
+
+                // The request, for an IPv4 or IPv6 address or a name, then a port.
+                let mut request = [0; 4];
+                stream.read_exact(&mut request).await?;
+                let length = match request[3] {
+                    1 => 4,
+                    4 => 16,
+                    _ => stream.read_u8().await?.into(),
+                };
+                stream.read_exact(&mut vec![0; length + 2]).await?;
+                stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+
+                let mut server = TcpStream::connect(to).await?;
+                tokio::io::copy_bidirectional(&mut stream, &mut server).await
+            });
+        }
+    });
+
+    proxy
+}
+
+/// A new, empty directory of its own for a test to put files in.
+pub fn dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("graplsub-test-{}", crate::state::run_id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
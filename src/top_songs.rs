@@ -0,0 +1,98 @@
+use rand::seq::SliceRandom;
+use std::collections::HashSet;
+
+use crate::api;
+use crate::api::Client;
+use crate::artist;
+use crate::config;
+use crate::output;
+
+// Top songs mode: the most popular songs of each artist in GRAPLSUB_TOP_ARTISTS, rather than
+// albums. What's popular is up to the server, which usually gets it from Last.fm, so a server
+// that isn't hooked up to anything like that won't have any.
+
+async fn top_songs(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+    artist: &str,
+) -> Result<api::TopLevel, api::Error> {
+    let url = format!(
+        "{}/rest/getTopSongs?u={}&f=json&v={}&c={}&artist={}&count={}",
+        conf.base_url,
+        api::encode(&conf.user),
+        api_ver,
+        api::encode(&conf.client_name),
+        api::encode(artist),
+        conf.top_songs_count
+    );
+
+    api::get(client, &url).await
+}
+
+fn check_top_songs_response(resp: &api::TopLevel) -> Result<(), api::RespParseError> {
+    api::check_generic_response(resp)?;
+
+    if resp.subsonic_response.top_songs.is_none() {
+        return Err(api::RespParseError::MissingTopSongs {
+            excerpt: resp.excerpt.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Up to GRAPLSUB_TOP_SONGS_COUNT top songs from each artist in GRAPLSUB_TOP_ARTISTS, artist by
+/// artist, or shuffled with GRAPLSUB_SHUFFLE. Artists that can't be found, or that the server has
+/// no top songs for, are warned about and skipped.
+pub async fn songs(
+    client: &Client,
+    conf: &config::Config,
+    api_ver: &str,
+) -> Result<Vec<api::Song>, api::Error> {
+    let names = conf.top_artists.as_deref().unwrap_or_default();
+    let mut picked: Vec<api::Song> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+
+    for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        // getTopSongs goes by name, so use the library's spelling of it.
+        let Some(found) = artist::find(client, conf, api_ver, name).await? else {
+            output::warn(format!("Couldn't find artist \"{}\". Skipping it.", name));
+            continue;
+        };
+
+        let subsonic_response = top_songs(client, conf, api_ver, &found.name).await?;
+
+        check_top_songs_response(&subsonic_response)?;
+
+        // Safe to unwrap() as we already checked it was Some().
+        let songs = subsonic_response
+            .subsonic_response
+            .top_songs
+            .unwrap()
+            .song
+            .unwrap_or_default();
+
+        if songs.is_empty() {
+            output::warn(format!(
+                "No top songs known for {}. Skipping it.",
+                found.name
+            ));
+        }
+
+        // Songs with more than one artist can turn up more than once.
+        picked.extend(songs.into_iter().filter(|s| seen.insert(s.id.clone())));
+    }
+
+    if picked.is_empty() {
+        return Err(api::Error::NotFound {
+            resource: "top songs for any of the artists in GRAPLSUB_TOP_ARTISTS".to_string(),
+        });
+    }
+
+    if conf.shuffle {
+        picked.shuffle(&mut rand::rng());
+    }
+
+    Ok(picked)
+}